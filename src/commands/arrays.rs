@@ -0,0 +1,464 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::ops::Bound;
+use std::path::PathBuf;
+
+use gds21::{GdsArrayRef, GdsElement, GdsLibrary, GdsPoint, GdsProperty, GdsStruct, GdsStructRef};
+use regex::RegexSet;
+
+use super::vertex_limit::save_library;
+
+/// Replace regular grids of SREFs by AREFs in the selected cells of a GDS file.
+///
+/// # Arguments
+/// * `input` - Path to the GDS file
+/// * `output` - Output GDS file path
+/// * `min_count` - Smallest number of instances worth an AREF
+/// * `ignore_properties` - Also combine SREFs whose properties differ, dropping them
+/// * `patterns` - Regexes a cell name must match to be compacted
+/// * `max_points` - Most XY points written per boundary or path
+pub fn compact_gds_arrays(
+    input: &PathBuf,
+    output: &PathBuf,
+    min_count: usize,
+    ignore_properties: bool,
+    patterns: Option<Vec<&str>>,
+    max_points: usize,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut lib = GdsLibrary::load(input)?;
+    let re = RegexSet::new(patterns.unwrap_or(vec![".*"]).into_iter())?;
+    for s in lib.structs.iter_mut().filter(|s| re.matches(&s.name).matched_any()) {
+        let arrays = compact_struct_arrays(s, min_count, ignore_properties);
+        if arrays > 0 {
+            println!("Created {} arrays in {}", arrays, s.name);
+        }
+    }
+    save_library(&mut lib, output, max_points)?;
+    Ok(())
+}
+
+/// Replace regular 1-D and 2-D lattices of SREFs by AREFs.
+///
+/// SREFs are combined when they place the same cell with the same transformation and, unless
+/// `ignore_properties` is set, the same properties. Lattices are axis-aligned: columns along
+/// x, rows along y. Remaining SREFs are kept in their original order, AREFs are appended.
+///
+/// # Returns
+/// The number of AREFs created.
+pub fn compact_struct_arrays(gds_struct: &mut GdsStruct, min_count: usize, ignore_properties: bool) -> usize {
+    let min_count = min_count.max(2);
+    // Group key: cell name, transformation and properties, in their debug form.
+    let mut groups: BTreeMap<(String, String, String), Vec<usize>> = BTreeMap::new();
+    for (i, element) in gds_struct.elems.iter().enumerate() {
+        if let GdsElement::GdsStructRef(r) = element {
+            let properties = if ignore_properties {
+                String::new()
+            } else {
+                format!("{:?}", r.properties)
+            };
+            groups
+                .entry((r.name.clone(), format!("{:?}", r.strans), properties))
+                .or_default()
+                .push(i);
+        }
+    }
+
+    let mut arrays = vec![];
+    let mut removed = vec![false; gds_struct.elems.len()];
+    for indices in groups.values().filter(|indices| indices.len() >= min_count) {
+        let GdsElement::GdsStructRef(first) = &gds_struct.elems[indices[0]] else {
+            unreachable!()
+        };
+        let mut remaining: HashMap<(i32, i32), Vec<usize>> = HashMap::new();
+        for &i in indices {
+            if let GdsElement::GdsStructRef(r) = &gds_struct.elems[i] {
+                remaining.entry((r.xy.x, r.xy.y)).or_default().push(i);
+            }
+        }
+        for lattice in find_lattices(&mut remaining, min_count) {
+            for i in &lattice.members {
+                removed[*i] = true;
+            }
+            arrays.push(lattice.to_array_ref(
+                first,
+                if ignore_properties { vec![] } else { first.properties.clone() },
+            ));
+        }
+    }
+
+    let count = arrays.len();
+    let elems = std::mem::take(&mut gds_struct.elems);
+    gds_struct.elems = elems
+        .into_iter()
+        .zip(removed)
+        .filter(|(_, removed)| !removed)
+        .map(|(element, _)| element)
+        .chain(arrays)
+        .collect();
+    count
+}
+
+/// A rectangular lattice of placements found among SREF origins.
+struct Lattice {
+    /// The origin and the column and row displacement points of the AREF.
+    xy: [GdsPoint; 3],
+    cols: i16,
+    rows: i16,
+    /// Element indices of the SREFs covered by the lattice.
+    members: Vec<usize>,
+}
+
+impl Lattice {
+    fn to_array_ref(&self, template: &GdsStructRef, properties: Vec<GdsProperty>) -> GdsElement {
+        GdsElement::GdsArrayRef(GdsArrayRef {
+            name: template.name.clone(),
+            xy: self.xy.clone(),
+            cols: self.cols,
+            rows: self.rows,
+            strans: template.strans.clone(),
+            elflags: template.elflags.clone(),
+            plex: template.plex.clone(),
+            properties,
+        })
+    }
+}
+
+/// The point `col` steps along x and `row` steps along y from `origin`, `None` if it does
+/// not fit into a GDS coordinate.
+fn lattice_point(origin: (i32, i32), steps: (i32, i32), col: i32, row: i32) -> Option<(i32, i32)> {
+    let x = origin.0.checked_add(steps.0.checked_mul(col)?)?;
+    let y = origin.1.checked_add(steps.1.checked_mul(row)?)?;
+    Some((x, y))
+}
+
+/// Greedily take lattices out of a set of origins, lowest row and leftmost column first.
+///
+/// Each lattice grows along x from its origin as far as the spacing stays constant, then
+/// adds rows above as long as every column of the next row is present. Lattices whose
+/// displacement points would overflow a GDS coordinate are skipped.
+fn find_lattices(remaining: &mut HashMap<(i32, i32), Vec<usize>>, min_count: usize) -> Vec<Lattice> {
+    let mut lattices = vec![];
+    let mut origins: Vec<(i32, i32)> = remaining.keys().copied().collect();
+    origins.sort_by_key(|&(x, y)| (y, x));
+
+    // Origins per row and per column, to find the nearest neighbour of a point quickly.
+    let mut by_row: HashMap<i32, BTreeSet<i32>> = HashMap::new();
+    let mut by_column: HashMap<i32, BTreeSet<i32>> = HashMap::new();
+    for &(x, y) in &origins {
+        by_row.entry(y).or_default().insert(x);
+        by_column.entry(x).or_default().insert(y);
+    }
+
+    let present = |remaining: &HashMap<(i32, i32), Vec<usize>>, p: Option<(i32, i32)>| {
+        p.and_then(|p| remaining.get(&p)).map(|v| !v.is_empty()).unwrap_or(false)
+    };
+    // Distance to the nearest remaining origin right of, or above, `p`.
+    let nearest = |remaining: &HashMap<(i32, i32), Vec<usize>>, p: (i32, i32), along_x: bool| {
+        let above = (Bound::Excluded(if along_x { p.0 } else { p.1 }), Bound::Unbounded);
+        if along_x {
+            by_row[&p.1]
+                .range(above)
+                .find(|&&x| present(remaining, Some((x, p.1))))
+                .and_then(|x| x.checked_sub(p.0))
+        } else {
+            by_column[&p.0]
+                .range(above)
+                .find(|&&y| present(remaining, Some((p.0, y))))
+                .and_then(|y| y.checked_sub(p.1))
+        }
+    };
+
+    for origin in origins {
+        if !present(remaining, Some(origin)) {
+            continue;
+        }
+        let col_step = nearest(remaining, origin, true).unwrap_or(0);
+        let row_step = nearest(remaining, origin, false).unwrap_or(0);
+        let steps = (col_step, row_step);
+        let mut cols: i32 = 1;
+        while col_step > 0
+            && cols < i16::MAX as i32
+            && present(remaining, lattice_point(origin, steps, cols, 0))
+        {
+            cols += 1;
+        }
+        let mut rows: i32 = 1;
+        while row_step > 0
+            && rows < i16::MAX as i32
+            && (0..cols).all(|c| present(remaining, lattice_point(origin, steps, c, rows)))
+        {
+            rows += 1;
+        }
+        // A single row is better described by a vertical column if that one is longer.
+        if cols > 1 && rows == 1 {
+            let mut column: i32 = 1;
+            while row_step > 0
+                && column < i16::MAX as i32
+                && present(remaining, lattice_point(origin, steps, 0, column))
+            {
+                column += 1;
+            }
+            if column > cols {
+                cols = 1;
+                rows = column;
+            }
+        }
+        if ((cols * rows) as usize) < min_count {
+            continue;
+        }
+
+        // The displacement points lie one step beyond the last column and row.
+        let steps = (if cols > 1 { col_step } else { 0 }, if rows > 1 { row_step } else { 0 });
+        let (Some(col_end), Some(row_end)) = (
+            lattice_point(origin, steps, cols, 0),
+            lattice_point(origin, steps, 0, rows),
+        ) else {
+            continue;
+        };
+
+        let mut members = vec![];
+        for r in 0..rows {
+            for c in 0..cols {
+                // Every member was found present above, so its position fits.
+                if let Some(p) = lattice_point(origin, steps, c, r) {
+                    members.extend(remaining.get_mut(&p).and_then(|v| v.pop()));
+                }
+            }
+        }
+        let point = |(x, y): (i32, i32)| GdsPoint { x, y };
+        lattices.push(Lattice {
+            xy: [point(origin), point(col_end), point(row_end)],
+            cols: cols as i16,
+            rows: rows as i16,
+            members,
+        });
+    }
+    lattices
+}
+
+/// Replace AREFs by one SREF per array member in every cell of a GDS file.
+///
+/// # Arguments
+/// * `input` - Path to the GDS file
+/// * `output` - Output GDS file path
+/// * `patterns` - Only expand AREFs placing a cell whose name matches one of these regexes
+/// * `max_points` - Most XY points written per boundary or path
+pub fn expand_gds_arrays(
+    input: &PathBuf,
+    output: &PathBuf,
+    patterns: Option<Vec<&str>>,
+    max_points: usize,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut lib = GdsLibrary::load(input)?;
+    let re = RegexSet::new(patterns.unwrap_or(vec![".*"]).into_iter())?;
+    for s in &mut lib.structs {
+        let arrays = expand_struct_arrays(s, &re).map_err(|e| format!("{}: {}", s.name, e))?;
+        if arrays > 0 {
+            println!("Expanded {} arrays in {}", arrays, s.name);
+        }
+    }
+    save_library(&mut lib, output, max_points)?;
+    Ok(())
+}
+
+/// Replace the AREFs of a struct placing a cell matching `re` by individual SREFs.
+///
+/// The SREFs take the place of the AREF in the element list, row by row, and keep its
+/// transformation and properties.
+///
+/// # Returns
+/// The number of AREFs expanded. On error the struct is left unchanged.
+pub fn expand_struct_arrays(gds_struct: &mut GdsStruct, re: &RegexSet) -> Result<usize, String> {
+    let mut count = 0;
+    let mut elems = Vec::with_capacity(gds_struct.elems.len());
+    for element in &gds_struct.elems {
+        match element {
+            GdsElement::GdsArrayRef(r) if re.matches(&r.name).matched_any() => {
+                count += 1;
+                elems.extend(array_members(r)?.into_iter().map(GdsElement::GdsStructRef));
+            }
+            _ => elems.push(element.clone()),
+        }
+    }
+    gds_struct.elems = elems;
+    Ok(count)
+}
+
+/// The SREFs placed by an AREF, row by row.
+///
+/// Member positions are computed from the column and row displacement points, rounding
+/// only once per member so lattices with non-integer steps do not drift. A member outside
+/// the GDS coordinate range is an error.
+pub fn array_members(array: &GdsArrayRef) -> Result<Vec<GdsStructRef>, String> {
+    let (cols, rows) = (array.cols.max(1) as i64, array.rows.max(1) as i64);
+    let [origin, col_end, row_end] = &array.xy;
+    let offset = |from: i32, to: i32, i: i64, n: i64| {
+        ((to as i64 - from as i64) as f64 * i as f64 / n as f64).round() as i64
+    };
+    let mut members = Vec::with_capacity((cols * rows) as usize);
+    for row in 0..rows {
+        for col in 0..cols {
+            let x = origin.x as i64
+                + offset(origin.x, col_end.x, col, cols)
+                + offset(origin.x, row_end.x, row, rows);
+            let y = origin.y as i64
+                + offset(origin.y, col_end.y, col, cols)
+                + offset(origin.y, row_end.y, row, rows);
+            let (Ok(x), Ok(y)) = (i32::try_from(x), i32::try_from(y)) else {
+                return Err(format!(
+                    "member {} of row {} of the array of {} lies at ({}, {}), outside the GDS coordinate range",
+                    col, row, array.name, x, y
+                ));
+            };
+            members.push(GdsStructRef {
+                name: array.name.clone(),
+                xy: GdsPoint { x, y },
+                strans: array.strans.clone(),
+                elflags: array.elflags.clone(),
+                plex: array.plex.clone(),
+                properties: array.properties.clone(),
+            });
+        }
+    }
+    Ok(members)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::test_support::{cell, sref_at};
+
+    fn origins(points: &[(i32, i32)]) -> HashMap<(i32, i32), Vec<usize>> {
+        let mut remaining: HashMap<(i32, i32), Vec<usize>> = HashMap::new();
+        for (i, &p) in points.iter().enumerate() {
+            remaining.entry(p).or_default().push(i);
+        }
+        remaining
+    }
+
+    fn grid(cols: i32, rows: i32, x0: i32, y0: i32, dx: i32, dy: i32) -> Vec<(i32, i32)> {
+        (0..rows)
+            .flat_map(|r| (0..cols).map(move |c| (x0 + c * dx, y0 + r * dy)))
+            .collect()
+    }
+
+    fn xy(lattice: &Lattice) -> Vec<(i32, i32)> {
+        lattice.xy.iter().map(|p| (p.x, p.y)).collect()
+    }
+
+    #[test]
+    fn grid_becomes_one_lattice() {
+        let mut remaining = origins(&grid(3, 2, 10, 20, 5, 7));
+        let lattices = find_lattices(&mut remaining, 2);
+        assert_eq!(lattices.len(), 1);
+        let lattice = &lattices[0];
+        assert_eq!((lattice.cols, lattice.rows), (3, 2));
+        assert_eq!(xy(lattice), [(10, 20), (25, 20), (10, 34)]);
+        let mut members = lattice.members.clone();
+        members.sort();
+        assert_eq!(members, (0..6).collect::<Vec<_>>());
+        assert!(remaining.values().all(|v| v.is_empty()));
+    }
+
+    #[test]
+    fn longer_column_wins_over_a_row() {
+        // Two placements in a row, four in the column above the first one.
+        let mut remaining = origins(&[(0, 0), (10, 0), (0, 5), (0, 10), (0, 15)]);
+        let lattices = find_lattices(&mut remaining, 2);
+        assert_eq!((lattices[0].cols, lattices[0].rows), (1, 4));
+        assert_eq!(xy(&lattices[0]), [(0, 0), (0, 0), (0, 20)]);
+        assert_eq!(remaining[&(10, 0)].len(), 1);
+    }
+
+    #[test]
+    fn small_groups_are_left_alone() {
+        let mut remaining = origins(&[(0, 0), (10, 0)]);
+        assert!(find_lattices(&mut remaining, 3).is_empty());
+        assert_eq!(remaining.values().map(|v| v.len()).sum::<usize>(), 2);
+    }
+
+    #[test]
+    fn lattices_overflowing_the_coordinate_range_are_skipped() {
+        let mut remaining = origins(&grid(3, 1, i32::MAX - 10, 0, 5, 0));
+        assert!(find_lattices(&mut remaining, 2).is_empty());
+        assert_eq!(remaining.values().map(|v| v.len()).sum::<usize>(), 3);
+
+        let mut remaining = origins(&[(i32::MIN, 0), (i32::MAX, 0)]);
+        assert!(find_lattices(&mut remaining, 2).is_empty());
+    }
+
+    #[test]
+    fn compacting_replaces_srefs_by_an_aref() {
+        let elems = grid(4, 1, 0, 0, 100, 0).into_iter().map(|(x, y)| sref_at("cell", x, y, None));
+        let mut gds_struct = cell("top", elems.collect());
+        assert_eq!(compact_struct_arrays(&mut gds_struct, 2, false), 1);
+        assert_eq!(gds_struct.elems.len(), 1);
+        let GdsElement::GdsArrayRef(array) = &gds_struct.elems[0] else {
+            panic!("expected an AREF");
+        };
+        assert_eq!((array.cols, array.rows), (4, 1));
+        assert_eq!((array.xy[1].x, array.xy[1].y), (400, 0));
+    }
+
+    fn aref(cols: i16, rows: i16, xy: [(i32, i32); 3]) -> GdsArrayRef {
+        GdsArrayRef {
+            name: "cell".to_string(),
+            xy: xy.map(|(x, y)| GdsPoint { x, y }),
+            cols,
+            rows,
+            strans: None,
+            elflags: None,
+            plex: None,
+            properties: vec![],
+        }
+    }
+
+    #[test]
+    fn array_members_run_row_by_row() {
+        let members = array_members(&aref(2, 2, [(0, 0), (20, 0), (0, 10)])).unwrap();
+        let positions: Vec<(i32, i32)> = members.iter().map(|m| (m.xy.x, m.xy.y)).collect();
+        assert_eq!(positions, [(0, 0), (10, 0), (0, 5), (10, 5)]);
+    }
+
+    #[test]
+    fn array_members_round_non_integer_steps_without_drift() {
+        let members = array_members(&aref(3, 1, [(0, 0), (10, 0), (0, 0)])).unwrap();
+        let xs: Vec<i32> = members.iter().map(|m| m.xy.x).collect();
+        assert_eq!(xs, [0, 3, 7]);
+    }
+
+    #[test]
+    fn array_members_outside_the_coordinate_range_are_an_error() {
+        // A rotated lattice whose column and row offsets add up beyond i32::MAX.
+        let array = aref(2, 2, [(0, 0), (i32::MAX, i32::MAX), (i32::MAX, 0)]);
+        let err = array_members(&array).unwrap_err();
+        assert!(err.contains("cell"), "{}", err);
+
+        let mut gds_struct = cell("top", vec![GdsElement::GdsArrayRef(array)]);
+        let re = RegexSet::new([".*"]).unwrap();
+        assert!(expand_struct_arrays(&mut gds_struct, &re).is_err());
+        assert!(matches!(gds_struct.elems[..], [GdsElement::GdsArrayRef(_)]));
+    }
+
+    #[test]
+    fn expanding_replaces_the_aref_in_place() {
+        let mut gds_struct = cell(
+            "top",
+            vec![
+                sref_at("cell", -5, -5, None),
+                GdsElement::GdsArrayRef(aref(3, 1, [(0, 0), (30, 0), (0, 0)])),
+                sref_at("cell", -1, -1, None),
+            ],
+        );
+        let re = RegexSet::new([".*"]).unwrap();
+        assert_eq!(expand_struct_arrays(&mut gds_struct, &re), Ok(1));
+        let xs: Vec<i32> = gds_struct
+            .elems
+            .iter()
+            .map(|e| match e {
+                GdsElement::GdsStructRef(r) => r.xy.x,
+                _ => panic!("expected SREFs"),
+            })
+            .collect();
+        assert_eq!(xs, [-5, 0, 10, 20, -1]);
+    }
+}
@@ -0,0 +1,212 @@
+use std::collections::{HashMap, HashSet};
+
+use gds21::{GdsElement, GdsLibrary};
+
+use super::gds_diff::diff_cells;
+use super::geometry::{units_pair, BoundingBox};
+use super::hierarchy::{cell_bounding_boxes, reachable_cells, structs_by_name};
+use super::merge_libraries::{merge_libraries, rescale_element, ConflictPolicy};
+
+/// What the LEF says about a macro, in database units.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MacroAbstract {
+    pub name: String,
+    /// The SIZE of the macro, `None` if the LEF gives none.
+    pub size: Option<(i32, i32)>,
+    /// The ORIGIN of the macro, the offset moving its geometry onto the placement point.
+    pub origin: (i32, i32),
+    pub pins: Vec<String>,
+}
+
+/// Findings of [`fill_macro_layouts`].
+#[derive(Clone, Debug, Default)]
+pub struct CellLayoutReport {
+    /// Macros without a layout in any of the cell libraries, they keep their LEF abstract.
+    pub missing: Vec<String>,
+    /// Disagreements between a LEF macro and its layout, one message each.
+    pub mismatches: Vec<String>,
+}
+
+/// Replace the abstract of every macro in `lib` by its layout from the cell libraries.
+///
+/// The cell libraries are merged, the first definition of a cell winning, and rescaled to the
+/// database unit of `lib`. Cells placed by a macro layout are added as well; one clashing with
+/// a cell of `lib` that is not a macro is reported and the cell of `lib` is kept. Each filled
+/// macro is checked against its LEF: shifted by the ORIGIN, the layout bounding box must span
+/// `(0, 0)` to the SIZE, and every LEF pin needs a label of the same name in the macro cell
+/// itself.
+pub fn fill_macro_layouts(
+    lib: &mut GdsLibrary,
+    macros: &[MacroAbstract],
+    cell_libraries: Vec<GdsLibrary>,
+) -> Result<CellLayoutReport, String> {
+    let mut report = CellLayoutReport::default();
+    if cell_libraries.is_empty() {
+        return Ok(report);
+    }
+    let mut cells = merge_libraries(cell_libraries, ConflictPolicy::KeepFirst, None)?;
+    let factor = units_pair(&cells).1 / units_pair(lib).1;
+    if (factor - 1.0).abs() > 1e-12 {
+        for s in &mut cells.structs {
+            for element in &mut s.elems {
+                rescale_element(element, factor)?;
+            }
+        }
+    }
+
+    let macro_names: HashSet<&str> = macros.iter().map(|m| m.name.as_str()).collect();
+    let bboxes = cell_bounding_boxes(&cells);
+    let by_name = structs_by_name(&cells);
+    let mut lib_index: HashMap<String, usize> = lib
+        .structs
+        .iter()
+        .enumerate()
+        .map(|(i, s)| (s.name.clone(), i))
+        .collect();
+
+    for m in macros {
+        let (Some(&layout), Some(&target)) = (by_name.get(m.name.as_str()), lib_index.get(&m.name)) else {
+            if lib_index.contains_key(&m.name) {
+                report.missing.push(m.name.clone());
+            }
+            continue;
+        };
+        lib.structs[target].elems = layout.elems.clone();
+
+        for child in reachable_cells(&cells, &[&m.name])? {
+            if child == m.name || macro_names.contains(child.as_str()) {
+                continue;
+            }
+            match lib_index.get(&child) {
+                Some(&existing) if !diff_cells(&lib.structs[existing], by_name[child.as_str()], 0).is_empty() => {
+                    report.mismatches.push(format!(
+                        "{}: cell {} of the layout clashes with a generated cell, keeping the generated one",
+                        m.name, child
+                    ));
+                }
+                Some(_) => {}
+                None => {
+                    lib_index.insert(child.clone(), lib.structs.len());
+                    lib.structs.push(by_name[child.as_str()].clone());
+                }
+            }
+        }
+
+        match (m.size, bboxes.get(&m.name).copied().flatten()) {
+            (Some((w, h)), Some(b)) if !spans_size(&b, m.origin, (w, h)) => {
+                report.mismatches.push(format!(
+                    "{}: layout spans ({}, {}) to ({}, {}), LEF SIZE is {} by {} at ORIGIN ({}, {})",
+                    m.name, b.min_x, b.min_y, b.max_x, b.max_y, w, h, m.origin.0, m.origin.1
+                ));
+            }
+            (Some(_), None) => report.mismatches.push(format!("{}: layout is empty", m.name)),
+            _ => {}
+        }
+
+        let labels: HashSet<&str> = layout
+            .elems
+            .iter()
+            .filter_map(|e| match e {
+                GdsElement::GdsTextElem(t) => Some(t.string.as_str()),
+                _ => None,
+            })
+            .collect();
+        let unlabelled: Vec<&str> = m
+            .pins
+            .iter()
+            .map(|p| p.as_str())
+            .filter(|p| !labels.contains(p))
+            .collect();
+        if !unlabelled.is_empty() {
+            report
+                .mismatches
+                .push(format!("{}: no label for pins {}", m.name, unlabelled.join(", ")));
+        }
+    }
+    Ok(report)
+}
+
+/// Whether a layout bounding box, shifted by the macro ORIGIN, spans `(0, 0)` to the SIZE.
+fn spans_size(bbox: &BoundingBox, origin: (i32, i32), (w, h): (i32, i32)) -> bool {
+    let corners = [
+        bbox.min_x as i64 + origin.0 as i64,
+        bbox.min_y as i64 + origin.1 as i64,
+        bbox.max_x as i64 + origin.0 as i64,
+        bbox.max_y as i64 + origin.1 as i64,
+    ];
+    corners == [0, 0, w as i64, h as i64]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gds21::{GdsPoint, GdsTextElem};
+
+    use crate::commands::test_support::{cell, library_in_units, rect};
+
+    fn text(string: &str) -> GdsElement {
+        GdsElement::GdsTextElem(GdsTextElem {
+            string: string.to_string(),
+            layer: 1,
+            texttype: 0,
+            xy: GdsPoint { x: 0, y: 0 },
+            ..Default::default()
+        })
+    }
+
+    fn inv(origin: (i32, i32)) -> MacroAbstract {
+        MacroAbstract {
+            name: "INV".to_string(),
+            size: Some((10, 20)),
+            origin,
+            pins: vec!["A".to_string(), "Y".to_string()],
+        }
+    }
+
+    /// A layout of INV drawn from (-1, -2) to (9, 18), labelling pin A only.
+    fn fill(origin: (i32, i32)) -> (GdsLibrary, CellLayoutReport) {
+        let mut lib = library_in_units("lib", 1e-9, vec![cell("INV", vec![rect(0, 0, 10, 20)]), cell("top", vec![])]);
+        let cells = library_in_units("lib", 1e-9, vec![cell("INV", vec![rect(-1, -2, 9, 18), text("A")])]);
+        let report = fill_macro_layouts(&mut lib, &[inv(origin)], vec![cells]).unwrap();
+        (lib, report)
+    }
+
+    #[test]
+    fn layouts_replace_the_abstract() {
+        let (lib, report) = fill((1, 2));
+        assert_eq!(lib.structs[0].elems.len(), 2);
+        assert!(report.missing.is_empty());
+    }
+
+    #[test]
+    fn size_is_checked_relative_to_the_origin() {
+        let (_, report) = fill((1, 2));
+        assert!(report.mismatches.iter().all(|m| !m.contains("SIZE")), "{:?}", report.mismatches);
+
+        let (_, report) = fill((0, 0));
+        assert!(report.mismatches.iter().any(|m| m.contains("SIZE")), "{:?}", report.mismatches);
+    }
+
+    #[test]
+    fn unlabelled_pins_are_reported() {
+        let (_, report) = fill((1, 2));
+        assert_eq!(report.mismatches, ["INV: no label for pins Y"]);
+    }
+
+    #[test]
+    fn macros_without_a_layout_are_missing() {
+        let mut lib = library_in_units("lib", 1e-9, vec![cell("INV", vec![rect(0, 0, 10, 20)])]);
+        let cells = library_in_units("lib", 1e-9, vec![cell("NAND", vec![])]);
+        let report = fill_macro_layouts(&mut lib, &[inv((0, 0))], vec![cells]).unwrap();
+        assert_eq!(report.missing, ["INV"]);
+        assert_eq!(lib.structs[0].elems.len(), 1);
+    }
+
+    #[test]
+    fn layouts_in_other_units_are_rescaled() {
+        let mut lib = library_in_units("lib", 1e-9, vec![cell("INV", vec![])]);
+        let cells = library_in_units("lib", 1e-8, vec![cell("INV", vec![rect(0, 0, 1, 2), text("A"), text("Y")])]);
+        let report = fill_macro_layouts(&mut lib, &[inv((0, 0))], vec![cells]).unwrap();
+        assert!(report.mismatches.is_empty(), "{:?}", report.mismatches);
+    }
+}
@@ -0,0 +1,317 @@
+use std::collections::{BTreeSet, HashMap};
+use std::path::PathBuf;
+
+use gds21::{GdsArrayRef, GdsElement, GdsLibrary, GdsStruct, GdsStructRef};
+use regex::RegexSet;
+use serde::Serialize;
+
+use super::hierarchy::structs_by_name;
+
+/// Output formats of the `tree` command.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TreeFormat {
+    Text,
+    Dot,
+    Json,
+}
+
+impl std::str::FromStr for TreeFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(TreeFormat::Text),
+            "dot" => Ok(TreeFormat::Dot),
+            "json" => Ok(TreeFormat::Json),
+            _ => Err(format!("unknown tree format {}, expected text, dot or json", s)),
+        }
+    }
+}
+
+/// A cell in the instance hierarchy together with how often its parent places it.
+#[derive(Serialize)]
+pub struct CellTree {
+    pub name: String,
+    /// Number of placements in the parent, AREFs counting once per array member.
+    pub count: usize,
+    pub children: Vec<CellTree>,
+}
+
+/// Print the instance hierarchy below a top cell.
+///
+/// # Arguments
+/// * `top` - Name of the cell at the root of the tree
+/// * `input` - Path to the GDS file
+/// * `levels` - Maximum number of levels shown below the top, unlimited if `None`
+/// * `patterns` - Regexes a child cell name must match to be shown and descended into
+/// * `format` - Indented text, Graphviz DOT or JSON
+pub fn print_cell_tree(
+    top: &str,
+    input: &PathBuf,
+    levels: Option<i32>,
+    patterns: Option<Vec<&str>>,
+    format: TreeFormat,
+) -> Result<CellTree, Box<dyn std::error::Error>> {
+    let lib = GdsLibrary::load(input)?;
+    let re = RegexSet::new(patterns.unwrap_or(vec![".*"]).into_iter())?;
+    let tree = cell_tree(&lib, top, levels, &re)?;
+    match format {
+        TreeFormat::Text => print!("{}", tree_to_text(&tree)),
+        TreeFormat::Dot => print!("{}", tree_to_dot(&tree)),
+        TreeFormat::Json => println!("{}", serde_json::to_string_pretty(&tree)?),
+    }
+    Ok(tree)
+}
+
+/// Build the instance hierarchy below `top`.
+pub fn cell_tree(
+    lib: &GdsLibrary,
+    top: &str,
+    levels: Option<i32>,
+    re: &RegexSet,
+) -> Result<CellTree, String> {
+    let by_name = structs_by_name(lib);
+    let top_struct = by_name
+        .get(top)
+        .ok_or_else(|| format!("cell {} is not defined in the library", top))?;
+    let mut path = vec![top];
+    Ok(CellTree {
+        name: top.to_string(),
+        count: 1,
+        children: child_trees(top_struct, &by_name, levels, re, 1, &mut path),
+    })
+}
+
+fn child_trees<'a>(
+    gds_struct: &'a GdsStruct,
+    by_name: &HashMap<&'a str, &'a GdsStruct>,
+    levels: Option<i32>,
+    re: &RegexSet,
+    depth: i32,
+    path: &mut Vec<&'a str>,
+) -> Vec<CellTree> {
+    if levels.map(|l| depth > l).unwrap_or(false) {
+        return vec![];
+    }
+
+    // Group the placements per child cell, in order of first appearance.
+    let mut counts: Vec<(&'a str, usize)> = vec![];
+    for element in &gds_struct.elems {
+        let (name, count) = match element {
+            GdsElement::GdsStructRef(GdsStructRef { name, .. }) => (name.as_str(), 1),
+            GdsElement::GdsArrayRef(GdsArrayRef { name, cols, rows, .. }) => (
+                name.as_str(),
+                (*cols).max(0) as usize * (*rows).max(0) as usize,
+            ),
+            _ => continue,
+        };
+        if !re.matches(name).matched_any() {
+            continue;
+        }
+        match counts.iter_mut().find(|(n, _)| *n == name) {
+            Some((_, c)) => *c += count,
+            None => counts.push((name, count)),
+        }
+    }
+
+    counts
+        .into_iter()
+        .map(|(name, count)| {
+            // Cells missing from the library and reference cycles end the branch.
+            let children = match by_name.get(name) {
+                Some(&child) if !path.contains(&name) => {
+                    path.push(name);
+                    let children = child_trees(child, by_name, levels, re, depth + 1, path);
+                    path.pop();
+                    children
+                }
+                _ => vec![],
+            };
+            CellTree {
+                name: name.to_string(),
+                count,
+                children,
+            }
+        })
+        .collect()
+}
+
+/// Render a tree with two spaces of indentation per level, e.g. `THmitll_SPLITT x128`.
+pub fn tree_to_text(tree: &CellTree) -> String {
+    fn write(tree: &CellTree, indent: usize, out: &mut String) {
+        out.push_str(&"  ".repeat(indent));
+        out.push_str(&tree.name);
+        if tree.count > 1 {
+            out.push_str(&format!(" x{}", tree.count));
+        }
+        out.push('\n');
+        for child in &tree.children {
+            write(child, indent + 1, out);
+        }
+    }
+
+    let mut out = String::new();
+    write(tree, 0, &mut out);
+    out
+}
+
+/// Render a tree as a Graphviz digraph, one edge per parent/child pair labelled with its count.
+pub fn tree_to_dot(tree: &CellTree) -> String {
+    fn collect<'a>(tree: &'a CellTree, edges: &mut BTreeSet<(&'a str, &'a str, usize)>) {
+        for child in &tree.children {
+            edges.insert((&tree.name, &child.name, child.count));
+            collect(child, edges);
+        }
+    }
+
+    let mut edges = BTreeSet::new();
+    collect(tree, &mut edges);
+
+    let mut out = String::from("digraph hierarchy {\n");
+    out.push_str(&format!("    \"{}\";\n", dot_escape(&tree.name)));
+    for (parent, child, count) in edges {
+        out.push_str(&format!(
+            "    \"{}\" -> \"{}\" [label=\"x{}\"];\n",
+            dot_escape(parent),
+            dot_escape(child),
+            count
+        ));
+    }
+    out.push_str("}\n");
+    out
+}
+
+/// Escape a name for use inside a quoted DOT identifier.
+fn dot_escape(name: &str) -> String {
+    name.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::test_support::{aref, cell, library, sref};
+
+    /// `top` places `mid` twice and a 2 by 3 array of `leaf`, `mid` places `leaf` and `via`.
+    fn hierarchy() -> GdsLibrary {
+        library(vec![
+            cell("top", vec![sref("mid"), aref("leaf", 2, 3), sref("mid")]),
+            cell("mid", vec![sref("leaf"), sref("via")]),
+            cell("leaf", vec![]),
+            cell("via", vec![]),
+        ])
+    }
+
+    fn all() -> RegexSet {
+        RegexSet::new([".*"]).unwrap()
+    }
+
+    fn summary(tree: &CellTree) -> Vec<(String, usize, usize)> {
+        fn walk(tree: &CellTree, depth: usize, out: &mut Vec<(String, usize, usize)>) {
+            out.push((tree.name.clone(), tree.count, depth));
+            for child in &tree.children {
+                walk(child, depth + 1, out);
+            }
+        }
+        let mut out = vec![];
+        walk(tree, 0, &mut out);
+        out
+    }
+
+    fn entry(name: &str, count: usize, depth: usize) -> (String, usize, usize) {
+        (name.to_string(), count, depth)
+    }
+
+    fn leaf(name: &str, count: usize) -> CellTree {
+        CellTree {
+            name: name.to_string(),
+            count,
+            children: vec![],
+        }
+    }
+
+    #[test]
+    fn dot_output_escapes_names() {
+        let tree = CellTree {
+            name: "top".to_string(),
+            count: 1,
+            children: vec![leaf("say \"hi\"", 2), leaf("back\\slash", 1)],
+        };
+        let dot = tree_to_dot(&tree);
+        assert!(dot.contains(r#""top" -> "say \"hi\"" [label="x2"];"#));
+        assert!(dot.contains(r#""top" -> "back\\slash" [label="x1"];"#));
+    }
+
+    #[test]
+    fn children_are_grouped_with_their_placement_counts() {
+        let tree = cell_tree(&hierarchy(), "top", None, &all()).unwrap();
+        assert_eq!(
+            summary(&tree),
+            [
+                entry("top", 1, 0),
+                entry("mid", 2, 1),
+                entry("leaf", 1, 2),
+                entry("via", 1, 2),
+                entry("leaf", 6, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn levels_limit_the_depth_below_the_top() {
+        let tree = cell_tree(&hierarchy(), "top", Some(1), &all()).unwrap();
+        assert_eq!(summary(&tree), [entry("top", 1, 0), entry("mid", 2, 1), entry("leaf", 6, 1)]);
+        let tree = cell_tree(&hierarchy(), "top", Some(0), &all()).unwrap();
+        assert!(tree.children.is_empty());
+    }
+
+    #[test]
+    fn patterns_filter_the_children_shown() {
+        let re = RegexSet::new(["^mid$", "^via$"]).unwrap();
+        let tree = cell_tree(&hierarchy(), "top", None, &re).unwrap();
+        assert_eq!(summary(&tree), [entry("top", 1, 0), entry("mid", 2, 1), entry("via", 1, 2)]);
+    }
+
+    #[test]
+    fn unknown_top_cell_is_an_error() {
+        assert!(cell_tree(&hierarchy(), "nope", None, &all()).is_err());
+    }
+
+    #[test]
+    fn text_output_indents_levels_and_shows_counts() {
+        let tree = cell_tree(&hierarchy(), "top", None, &all()).unwrap();
+        assert_eq!(tree_to_text(&tree), "top\n  mid x2\n    leaf\n    via\n  leaf x6\n");
+    }
+
+    #[test]
+    fn dot_output_has_one_edge_per_parent_and_child() {
+        let tree = cell_tree(&hierarchy(), "top", None, &all()).unwrap();
+        assert_eq!(
+            tree_to_dot(&tree),
+            concat!(
+                "digraph hierarchy {\n",
+                "    \"top\";\n",
+                "    \"mid\" -> \"leaf\" [label=\"x1\"];\n",
+                "    \"mid\" -> \"via\" [label=\"x1\"];\n",
+                "    \"top\" -> \"leaf\" [label=\"x6\"];\n",
+                "    \"top\" -> \"mid\" [label=\"x2\"];\n",
+                "}\n",
+            )
+        );
+    }
+
+    #[test]
+    fn json_output_nests_the_children() {
+        let tree = cell_tree(&hierarchy(), "top", Some(1), &all()).unwrap();
+        assert_eq!(
+            serde_json::to_value(&tree).unwrap(),
+            serde_json::json!({
+                "name": "top",
+                "count": 1,
+                "children": [
+                    {"name": "mid", "count": 2, "children": []},
+                    {"name": "leaf", "count": 6, "children": []},
+                ],
+            })
+        );
+    }
+}
@@ -0,0 +1,226 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::PathBuf;
+
+use gds21::{GdsArrayRef, GdsElement, GdsLibrary, GdsNode, GdsStruct, GdsStructRef, GdsTextElem};
+
+use super::gds_diff::canonical_polygon;
+use super::geometry::{element_polygons, polygon_layer};
+use super::hierarchy::{rename_references, structs_by_name};
+use super::vertex_limit::save_library;
+
+/// Find structurally identical cells in a GDS file and collapse them onto one name.
+///
+/// # Arguments
+/// * `input` - Path to the GDS file
+/// * `output` - Output GDS file path, not needed when only reporting
+/// * `report_only` - Print the duplicate groups without changing anything
+/// * `max_points` - Most XY points written per boundary or path
+///
+/// # Returns
+/// The duplicate groups, the kept name first.
+pub fn dedup_gds_file(
+    input: &PathBuf,
+    output: Option<&PathBuf>,
+    report_only: bool,
+    max_points: usize,
+) -> Result<Vec<Vec<String>>, Box<dyn std::error::Error>> {
+    let mut lib = GdsLibrary::load(input)?;
+    let groups = duplicate_groups(&lib)?;
+    for group in &groups {
+        println!("{} <- {}", group[0], group[1..].join(", "));
+    }
+    println!("Found {} groups of duplicate cells", groups.len());
+    if !report_only {
+        collapse_duplicates(&mut lib, &groups);
+        let output = output.ok_or("an output path is required unless --report is given")?;
+        save_library(&mut lib, output, max_points)?;
+    }
+    Ok(groups)
+}
+
+/// Group the cells of a library by their canonical geometry.
+///
+/// Only groups of two or more cells are returned. The cell to keep comes first: the one with
+/// the shortest name, earlier cells of the library winning ties. Cells without any elements
+/// are never considered duplicates.
+pub fn duplicate_groups(lib: &GdsLibrary) -> Result<Vec<Vec<String>>, String> {
+    let classes = cell_classes(lib)?;
+    let mut groups: BTreeMap<usize, Vec<(usize, &str)>> = BTreeMap::new();
+    for (i, s) in lib.structs.iter().enumerate() {
+        if !s.elems.is_empty() {
+            groups.entry(classes[&s.name]).or_default().push((i, &s.name));
+        }
+    }
+    let mut groups: Vec<Vec<String>> = groups
+        .into_values()
+        .filter(|group| group.len() > 1)
+        .map(|mut group| {
+            group.sort_by_key(|(i, name)| (name.len(), *i));
+            group.into_iter().map(|(_, name)| name.to_string()).collect()
+        })
+        .collect();
+    groups.sort();
+    Ok(groups)
+}
+
+/// Remove all but the first cell of every group, pointing references to the kept cell.
+pub fn collapse_duplicates(lib: &mut GdsLibrary, groups: &[Vec<String>]) {
+    let renames: HashMap<String, String> = groups
+        .iter()
+        .flat_map(|group| group[1..].iter().map(|name| (name.clone(), group[0].clone())))
+        .collect();
+    lib.structs.retain(|s| !renames.contains_key(&s.name));
+    for s in &mut lib.structs {
+        rename_references(s, &renames);
+    }
+}
+
+/// Number the cells of a library so that cells with the same geometry, including the cells
+/// they place, share a number.
+///
+/// Cells are compared by their full canonical signature, which does not depend on the element
+/// order, the start vertex or orientation of polygons, or the names of the cells placed.
+/// References contribute the number of the placed cell instead of its name, so two cells
+/// placing identical children under different names are equal. Properties are ignored.
+pub fn cell_classes(lib: &GdsLibrary) -> Result<HashMap<String, usize>, String> {
+    fn class<'a>(
+        name: &'a str,
+        by_name: &HashMap<&'a str, &'a GdsStruct>,
+        classes: &mut HashMap<String, usize>,
+        signatures_seen: &mut HashMap<Vec<String>, usize>,
+        visiting: &mut HashSet<&'a str>,
+    ) -> Result<usize, String> {
+        if let Some(c) = classes.get(name) {
+            return Ok(*c);
+        }
+        let gds_struct: &'a GdsStruct = *by_name
+            .get(name)
+            .ok_or_else(|| format!("cell {} is not defined in the library", name))?;
+        if !visiting.insert(name) {
+            return Err(format!("reference cycle through cell {}", name));
+        }
+        let mut signatures: Vec<String> = vec![];
+        for element in &gds_struct.elems {
+            match element {
+                GdsElement::GdsStructRef(GdsStructRef { name: child, xy, strans, .. }) => {
+                    let child_class = class(child, by_name, classes, signatures_seen, visiting)?;
+                    signatures.push(format!("SREF {} {:?} {:?}", child_class, xy, strans));
+                }
+                GdsElement::GdsArrayRef(GdsArrayRef { name: child, xy, cols, rows, strans, .. }) => {
+                    let child_class = class(child, by_name, classes, signatures_seen, visiting)?;
+                    signatures.push(format!(
+                        "AREF {} {:?} {} {} {:?}",
+                        child_class, xy, cols, rows, strans
+                    ));
+                }
+                GdsElement::GdsTextElem(GdsTextElem { string, layer, texttype, xy, strans, .. }) => {
+                    signatures.push(format!(
+                        "TEXT {} {} {:?} {:?} {:?}",
+                        layer, texttype, string, xy, strans
+                    ));
+                }
+                GdsElement::GdsNode(GdsNode { layer, nodetype, xy, .. }) => {
+                    signatures.push(format!("NODE {} {} {:?}", layer, nodetype, canonical_polygon(xy)));
+                }
+                _ => {
+                    let Some((layer, datatype)) = polygon_layer(element) else {
+                        continue;
+                    };
+                    for polygon in element_polygons(element) {
+                        signatures.push(format!(
+                            "POLYGON {} {} {:?}",
+                            layer,
+                            datatype,
+                            canonical_polygon(&polygon)
+                        ));
+                    }
+                }
+            }
+        }
+        visiting.remove(name);
+        signatures.sort();
+
+        let next = signatures_seen.len();
+        let c = *signatures_seen.entry(signatures).or_insert(next);
+        classes.insert(name.to_string(), c);
+        Ok(c)
+    }
+
+    let by_name = structs_by_name(lib);
+    let mut classes = HashMap::new();
+    let mut signatures_seen = HashMap::new();
+    let mut visiting = HashSet::new();
+    for s in &lib.structs {
+        class(&s.name, &by_name, &mut classes, &mut signatures_seen, &mut visiting)?;
+    }
+    Ok(classes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::test_support::{boundary, cell, library, rect, sref, sref_at};
+
+    #[test]
+    fn element_order_and_polygon_start_do_not_matter() {
+        let lib = library(vec![
+            cell("a", vec![rect(0, 0, 10, 10), rect(20, 0, 30, 10)]),
+            cell(
+                "bb",
+                vec![
+                    rect(20, 0, 30, 10),
+                    boundary(&[(10, 10), (0, 10), (0, 0), (10, 0), (10, 10)]),
+                ],
+            ),
+        ]);
+        assert_eq!(duplicate_groups(&lib).unwrap(), [["a", "bb"]]);
+    }
+
+    #[test]
+    fn placements_of_identical_children_are_equal() {
+        let lib = library(vec![
+            cell("leaf", vec![rect(0, 0, 10, 10)]),
+            cell("leaf_copy", vec![rect(0, 0, 10, 10)]),
+            cell("top_a", vec![sref("leaf")]),
+            cell("top_b", vec![sref("leaf_copy")]),
+        ]);
+        assert_eq!(
+            duplicate_groups(&lib).unwrap(),
+            [vec!["leaf", "leaf_copy"], vec!["top_a", "top_b"]]
+        );
+    }
+
+    #[test]
+    fn cells_that_differ_are_kept_apart() {
+        let lib = library(vec![
+            cell("leaf", vec![rect(0, 0, 10, 10)]),
+            cell("wide", vec![rect(0, 0, 20, 10)]),
+            cell("top_a", vec![sref("leaf")]),
+            cell("top_b", vec![sref("wide")]),
+            cell("top_c", vec![sref_at("leaf", 5, 0, None)]),
+            cell("empty", vec![]),
+            cell("empty_too", vec![]),
+        ]);
+        assert!(duplicate_groups(&lib).unwrap().is_empty());
+    }
+
+    #[test]
+    fn collapsing_keeps_the_shortest_name_and_redirects_references() {
+        let mut lib = library(vec![
+            cell("leaf_copy", vec![rect(0, 0, 10, 10)]),
+            cell("leaf", vec![rect(0, 0, 10, 10)]),
+            cell("top", vec![sref("leaf_copy"), sref("leaf")]),
+        ]);
+        let groups = duplicate_groups(&lib).unwrap();
+        assert_eq!(groups, [["leaf", "leaf_copy"]]);
+        collapse_duplicates(&mut lib, &groups);
+        let names: Vec<&str> = lib.structs.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["leaf", "top"]);
+        for element in &lib.structs[1].elems {
+            match element {
+                GdsElement::GdsStructRef(r) => assert_eq!(r.name, "leaf"),
+                _ => panic!("expected a reference"),
+            }
+        }
+    }
+}
@@ -0,0 +1,346 @@
+use std::collections::BTreeSet;
+
+use gds21::{GdsElement, GdsPath, GdsPoint, GdsTextElem};
+use iron_shapes::prelude::Orientation2D;
+use libreda_lefdef::{DEF, LEF};
+
+use super::def_objects::{orient_point, purpose_layer};
+use super::def_to_gds::{gds_coord, micron_coord};
+use super::geometry::closed_boundary;
+use super::layer_map::GdsLayer;
+
+/// Layer map suffix of the track lines of a routing layer, as in `M1.TRACK`.
+pub const TRACK_PURPOSE: &str = "TRACK";
+
+/// Width of track and gcell lines, in microns.
+const GRID_LINE_WIDTH: f64 = 0.01;
+
+/// GDS layers of the optional placement and routing grid output, nothing is drawn for `None`.
+#[derive(Clone, Debug, Default)]
+pub struct GridLayers {
+    /// Row and site outlines with orientation marks.
+    pub rows: Option<GdsLayer>,
+    /// Datatype of the track lines, drawn on the GDS layer of their routing layer unless the
+    /// layer map has a `LAYER.TRACK` entry.
+    pub tracks: Option<i16>,
+    /// Boundaries between gcells.
+    pub gcells: Option<GdsLayer>,
+}
+
+/// Draw the DEF ROWs, TRACKS and GCELLGRID on the layers that are set.
+///
+/// Rows get their outline and name, every site its outline and a triangle in the corner
+/// its origin is placed at, so flipped and rotated sites can be told apart. Tracks and gcell
+/// boundaries are thin paths across the die area.
+///
+/// # Returns
+/// The elements and a list of problems, such as unknown sites or rows and grids that do not
+/// fit into GDS coordinates.
+pub fn grid_elements(
+    lef: &LEF,
+    def: &DEF,
+    dbu: u32,
+    layers: &GridLayers,
+    layer_of: &dyn Fn(&str) -> Option<GdsLayer>,
+) -> (Vec<GdsElement>, BTreeSet<String>) {
+    let mut elements = vec![];
+    let mut problems = BTreeSet::new();
+
+    if let Some(layer) = layers.rows {
+        for row in &def.rows {
+            let Some(site) = lef.technology.sites.get(&row.site_name) else {
+                problems.insert(format!("unknown site {} of row {}", row.site_name, row.name));
+                continue;
+            };
+            // Built apart, so a row that overflows is reported without leaving half of its sites.
+            let row_elements = || -> Result<Vec<GdsElement>, String> {
+                let (width, height) = (micron_coord(site.size.0, dbu)?, micron_coord(site.size.1, dbu)?);
+                let (num_x, num_y, step_x, step_y) = row
+                    .step_pattern
+                    .as_ref()
+                    .map(|s| (s.num_x, s.num_y, s.step_x, s.step_y))
+                    .unwrap_or((1, 1, 0, 0));
+
+                // The site outline and mark in site coordinates, moved so that the oriented site
+                // has its lower left corner at the placement point like a DEF component.
+                let mark = width.min(height) / 4;
+                let oriented = |points: &[(i32, i32)]| -> Vec<(i32, i32)> {
+                    points.iter().map(|p| orient_point(row.orient, *p)).collect()
+                };
+                let site_box = oriented(&[(0, 0), (width, 0), (width, height), (0, height)]);
+                let (min_x, min_y) = (
+                    site_box.iter().map(|p| p.0).min().unwrap() as i64,
+                    site_box.iter().map(|p| p.1).min().unwrap() as i64,
+                );
+                let site_mark = oriented(&[(0, 0), (mark, 0), (0, mark)]);
+
+                let mut row_elements = vec![];
+                let (x0, y0) = (row.orig.0 as i64, row.orig.1 as i64);
+                let (step_x, step_y) = (step_x as i64, step_y as i64);
+                for j in 0..num_y.max(1) as i64 {
+                    for i in 0..num_x.max(1) as i64 {
+                        let (dx, dy) = (x0 + i * step_x - min_x, y0 + j * step_y - min_y);
+                        let shift = |points: &[(i32, i32)]| -> Result<Vec<(i32, i32)>, String> {
+                            points
+                                .iter()
+                                .map(|&(x, y)| Ok((gds_coord(x as i64 + dx)?, gds_coord(y as i64 + dy)?)))
+                                .collect()
+                        };
+                        row_elements.extend(closed_boundary(layer, &shift(&site_box)?, vec![]));
+                        row_elements.extend(closed_boundary(layer, &shift(&site_mark)?, vec![]));
+                    }
+                }
+                let (site_width, site_height) = site_box_extent(&site_box);
+                let x1 = gds_coord(x0 + step_x * (num_x.max(1) as i64 - 1) + site_width as i64)?;
+                let y1 = gds_coord(y0 + step_y * (num_y.max(1) as i64 - 1) + site_height as i64)?;
+                let (x0, y0) = row.orig;
+                row_elements.extend(closed_boundary(layer, &[(x0, y0), (x1, y0), (x1, y1), (x0, y1)], vec![]));
+                row_elements.push(GdsElement::GdsTextElem(GdsTextElem {
+                    string: row.name.clone(),
+                    layer: layer.0,
+                    texttype: layer.1,
+                    xy: GdsPoint { x: x0, y: y0 },
+                    ..Default::default()
+                }));
+                Ok(row_elements)
+            };
+            match row_elements() {
+                Ok(row_elements) => elements.extend(row_elements),
+                Err(e) => {
+                    problems.insert(format!("row {}: {}", row.name, e));
+                }
+            }
+        }
+    }
+
+    let die_area = def.die_area.as_ref().map(|points| {
+        let xs = points.iter().map(|p| p.0);
+        let ys = points.iter().map(|p| p.1);
+        (xs.clone().min().unwrap_or(0), ys.clone().min().unwrap_or(0), xs.max().unwrap_or(0), ys.max().unwrap_or(0))
+    });
+    if (layers.tracks.is_some() || layers.gcells.is_some()) && die_area.is_none() {
+        problems.insert("no DIEAREA to draw tracks and gcells across".to_string());
+    }
+    let Some((x1, y1, x2, y2)) = die_area else {
+        return (elements, problems);
+    };
+    let line_width = match micron_coord(GRID_LINE_WIDTH, dbu) {
+        Ok(width) => width.max(1),
+        Err(e) => {
+            problems.insert(format!("grid line width: {}", e));
+            return (elements, problems);
+        }
+    };
+    // `Horizontal` grids (`TRACKS Y`, `GCELLGRID Y`) are lines at constant y.
+    let line = |layer: GdsLayer, orientation: Orientation2D, at: i32| {
+        let xy = match orientation {
+            Orientation2D::Horizontal => [(x1, at), (x2, at)],
+            Orientation2D::Vertical => [(at, y1), (at, y2)],
+        };
+        GdsElement::GdsPath(GdsPath {
+            layer: layer.0,
+            datatype: layer.1,
+            xy: xy.iter().map(|&(x, y)| GdsPoint { x, y }).collect(),
+            width: Some(line_width),
+            path_type: Some(0),
+            ..Default::default()
+        })
+    };
+
+    if let Some(datatype) = layers.tracks {
+        for tracks in &def.tracks {
+            for routing_layer in &tracks.layers {
+                let track_layer = layer_of(&purpose_layer(routing_layer, TRACK_PURPOSE))
+                    .or_else(|| layer_of(routing_layer).map(|(layer, _)| (layer, datatype)));
+                let Some(track_layer) = track_layer else {
+                    problems.insert(format!("no GDS layer for tracks on {}", routing_layer));
+                    continue;
+                };
+                for i in 0..tracks.num_tracks as i64 {
+                    match gds_coord(tracks.start as i64 + i * tracks.step as i64) {
+                        Ok(at) => elements.push(line(track_layer, tracks.orientation, at)),
+                        Err(e) => {
+                            problems.insert(format!("tracks on {}: {}", routing_layer, e));
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    if let Some(layer) = layers.gcells {
+        for grid in &def.gcell_grid {
+            for i in 0..grid.num as i64 {
+                match gds_coord(grid.start as i64 + i * grid.step as i64) {
+                    Ok(at) => elements.push(line(layer, grid.orientation, at)),
+                    Err(e) => {
+                        problems.insert(format!("gcell grid: {}", e));
+                        break;
+                    }
+                }
+            }
+        }
+    }
+    (elements, problems)
+}
+
+/// Width and height of an oriented site outline.
+fn site_box_extent(points: &[(i32, i32)]) -> (i32, i32) {
+    let xs = points.iter().map(|p| p.0);
+    let ys = points.iter().map(|p| p.1);
+    (
+        xs.clone().max().unwrap() - xs.min().unwrap(),
+        ys.clone().max().unwrap() - ys.min().unwrap(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libreda_lefdef::def_parser::read_def_bytes;
+    use libreda_lefdef::lef_parser::read_lef_bytes;
+
+    const LEF_TEXT: &str = "VERSION 5.8 ;
+UNITS
+  DATABASE MICRONS 1000 ;
+END UNITS
+SITE core
+  CLASS CORE ;
+  SIZE 0.2 BY 1.0 ;
+END core
+END LIBRARY
+";
+
+    fn def(body: &str) -> DEF {
+        let text = format!(
+            "VERSION 5.8 ;\nDESIGN top ;\nUNITS DISTANCE MICRONS 1000 ;\n{}END DESIGN\n",
+            body
+        );
+        read_def_bytes(&mut text.as_bytes()).unwrap()
+    }
+
+    fn lef() -> LEF {
+        read_lef_bytes(&mut LEF_TEXT.as_bytes()).unwrap()
+    }
+
+    fn routing_layers(name: &str) -> Option<GdsLayer> {
+        match name {
+            "M1" => Some((31, 0)),
+            "M2" => Some((32, 0)),
+            "M2.TRACK" => Some((132, 7)),
+            _ => None,
+        }
+    }
+
+    /// Boundaries as their points, paths as their end points and texts as their string.
+    fn summary(elements: &[GdsElement]) -> Vec<String> {
+        let xy = |points: &[GdsPoint]| -> Vec<(i32, i32)> { points.iter().map(|p| (p.x, p.y)).collect() };
+        elements
+            .iter()
+            .map(|e| match e {
+                GdsElement::GdsBoundary(b) => format!("{}/{} {:?}", b.layer, b.datatype, xy(&b.xy)),
+                GdsElement::GdsPath(p) => format!("{}/{} path {:?}", p.layer, p.datatype, xy(&p.xy)),
+                GdsElement::GdsTextElem(t) => {
+                    format!("{}/{} {} at {:?}", t.layer, t.texttype, t.string, (t.xy.x, t.xy.y))
+                }
+                _ => panic!("unexpected element {:?}", e),
+            })
+            .collect()
+    }
+
+    fn rows_only() -> GridLayers {
+        GridLayers {
+            rows: Some((200, 0)),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn rows_repeat_sites_along_their_step_pattern() {
+        let def = def("ROW row0 core 0 0 N DO 3 BY 1 STEP 200 0 ;\n");
+        let (elements, problems) = grid_elements(&lef(), &def, 1000, &rows_only(), &routing_layers);
+        assert!(problems.is_empty(), "{:?}", problems);
+        let summary = summary(&elements);
+        assert_eq!(summary.len(), 3 * 2 + 2);
+        assert_eq!(summary[0], "200/0 [(0, 0), (200, 0), (200, 1000), (0, 1000), (0, 0)]");
+        assert_eq!(summary[1], "200/0 [(0, 0), (50, 0), (0, 50), (0, 0)]");
+        assert_eq!(summary[4], "200/0 [(400, 0), (600, 0), (600, 1000), (400, 1000), (400, 0)]");
+        assert_eq!(summary[6], "200/0 [(0, 0), (600, 0), (600, 1000), (0, 1000), (0, 0)]");
+        assert_eq!(summary[7], "200/0 row0 at (0, 0)");
+    }
+
+    #[test]
+    fn flipped_rows_put_the_site_mark_in_the_flipped_corner() {
+        let def = def("ROW row1 core 0 1000 FS DO 2 BY 1 STEP 200 0 ;\n");
+        let (elements, problems) = grid_elements(&lef(), &def, 1000, &rows_only(), &routing_layers);
+        assert!(problems.is_empty(), "{:?}", problems);
+        let summary = summary(&elements);
+        assert_eq!(summary[0], "200/0 [(0, 2000), (200, 2000), (200, 1000), (0, 1000), (0, 2000)]");
+        assert_eq!(summary[1], "200/0 [(0, 2000), (50, 2000), (0, 1950), (0, 2000)]");
+        assert_eq!(summary[3], "200/0 [(200, 2000), (250, 2000), (200, 1950), (200, 2000)]");
+        assert_eq!(summary[4], "200/0 [(0, 1000), (400, 1000), (400, 2000), (0, 2000), (0, 1000)]");
+    }
+
+    #[test]
+    fn rows_of_unknown_sites_are_reported() {
+        let def = def("ROW row0 nosite 0 0 N DO 3 BY 1 STEP 200 0 ;\n");
+        let (elements, problems) = grid_elements(&lef(), &def, 1000, &rows_only(), &routing_layers);
+        assert!(elements.is_empty());
+        assert_eq!(problems, BTreeSet::from(["unknown site nosite of row row0".to_string()]));
+    }
+
+    #[test]
+    fn tracks_x_are_vertical_and_tracks_y_horizontal_lines() {
+        let def = def(concat!(
+            "DIEAREA ( 0 0 ) ( 1000 2000 ) ;\n",
+            "TRACKS X 100 DO 2 STEP 200 LAYER M1 ;\n",
+            "TRACKS Y 50 DO 2 STEP 500 LAYER M2 ;\n",
+        ));
+        let layers = GridLayers {
+            tracks: Some(5),
+            ..Default::default()
+        };
+        let (elements, problems) = grid_elements(&lef(), &def, 1000, &layers, &routing_layers);
+        assert!(problems.is_empty(), "{:?}", problems);
+        assert_eq!(
+            summary(&elements),
+            [
+                "31/5 path [(100, 0), (100, 2000)]",
+                "31/5 path [(300, 0), (300, 2000)]",
+                "132/7 path [(0, 50), (1000, 50)]",
+                "132/7 path [(0, 550), (1000, 550)]",
+            ]
+        );
+        let GdsElement::GdsPath(line) = &elements[0] else {
+            panic!("expected a path");
+        };
+        assert_eq!(line.width, Some(10));
+    }
+
+    #[test]
+    fn gcell_grids_use_their_own_layer() {
+        let def = def("DIEAREA ( 0 0 ) ( 1000 2000 ) ;\nGCELLGRID X 0 DO 2 STEP 1000 ;\n");
+        let layers = GridLayers {
+            gcells: Some((250, 1)),
+            ..Default::default()
+        };
+        let (elements, _) = grid_elements(&lef(), &def, 1000, &layers, &routing_layers);
+        assert_eq!(
+            summary(&elements),
+            ["250/1 path [(0, 0), (0, 2000)]", "250/1 path [(1000, 0), (1000, 2000)]"]
+        );
+    }
+
+    #[test]
+    fn tracks_without_die_area_are_reported() {
+        let def = def("TRACKS X 100 DO 2 STEP 200 LAYER M1 ;\n");
+        let layers = GridLayers {
+            tracks: Some(5),
+            ..Default::default()
+        };
+        let (elements, problems) = grid_elements(&lef(), &def, 1000, &layers, &routing_layers);
+        assert!(elements.is_empty());
+        assert_eq!(problems, BTreeSet::from(["no DIEAREA to draw tracks and gcells across".to_string()]));
+    }
+}
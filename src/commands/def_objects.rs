@@ -0,0 +1,222 @@
+use std::collections::BTreeSet;
+
+use gds21::{GdsElement, GdsPoint, GdsProperty, GdsTextElem};
+use libreda_lefdef::common::Orient;
+use libreda_lefdef::def_ast::BlockageKind;
+use libreda_lefdef::DEF;
+
+use super::def_to_gds::gds_coord;
+use super::geometry::closed_boundary;
+use super::layer_map::GdsLayer;
+use super::merge_shapes::NET_NAME_PROPERTY;
+
+/// Layer map name of the die area outline.
+pub const OUTLINE_LAYER: &str = "OUTLINE";
+/// GDS layer of the die area outline when neither an option nor [`OUTLINE_LAYER`] gives one,
+/// the boundary layer of KLayout's LEF/DEF reader.
+pub const DEFAULT_OUTLINE_LAYER: GdsLayer = (235, 0);
+/// Layer map name of placement blockages.
+pub const PLACEMENT_BLOCKAGE_LAYER: &str = "PLACEMENT_BLK";
+/// Layer map name of region outlines and their labels.
+pub const REGION_LAYER: &str = "REGIONS";
+/// Layer map suffix of pin shapes on a routing layer, as in `M1.PIN`.
+pub const PIN_PURPOSE: &str = "PIN";
+/// Layer map suffix of pin labels, as in `M1.LABEL`.
+pub const LABEL_PURPOSE: &str = "LABEL";
+/// Layer map suffix of routing blockages, as in `M1.BLOCKAGE`.
+pub const BLOCKAGE_PURPOSE: &str = "BLOCKAGE";
+
+/// The layer map name of a routing layer used for a purpose, for example `M1.PIN`.
+pub fn purpose_layer(layer: &str, purpose: &str) -> String {
+    format!("{}.{}", layer, purpose)
+}
+
+/// Transform a point by a DEF orientation, about the origin.
+pub fn orient_point(orient: Orient, (x, y): (i32, i32)) -> (i32, i32) {
+    match orient {
+        Orient::N => (x, y),
+        Orient::W => (-y, x),
+        Orient::S => (-x, -y),
+        Orient::E => (y, -x),
+        Orient::FS => (x, -y),
+        Orient::FW => (y, x),
+        Orient::FN => (-x, y),
+        Orient::FE => (-y, -x),
+    }
+}
+
+/// The GDS layer of the die area outline: `chosen` if set, else the [`OUTLINE_LAYER`] entry
+/// of `layer_of`, else [`DEFAULT_OUTLINE_LAYER`].
+pub fn die_area_layer(chosen: Option<GdsLayer>, layer_of: &dyn Fn(&str) -> Option<GdsLayer>) -> GdsLayer {
+    chosen
+        .or_else(|| layer_of(OUTLINE_LAYER))
+        .unwrap_or(DEFAULT_OUTLINE_LAYER)
+}
+
+/// Outline of the DEF DIEAREA on `layer`, a rectangle when given by two corners.
+pub fn die_area_elements(def: &DEF, layer: GdsLayer) -> Vec<GdsElement> {
+    let Some(die_area) = &def.die_area else {
+        return vec![];
+    };
+    let points = match die_area.as_slice() {
+        [(x1, y1), (x2, y2)] => rect_points(*x1, *y1, *x2, *y2),
+        points => points.to_vec(),
+    };
+    closed_boundary(layer, &points, vec![]).into_iter().collect()
+}
+
+/// Convert the DEF PINS, BLOCKAGES and REGIONS to GDS elements.
+///
+/// Pin shapes go to the `LAYER.PIN` entry of the layer map, or the routing layer itself, and
+/// get a label with the pin name on `LAYER.LABEL`, or the pin layer. Routing blockages go to
+/// `LAYER.BLOCKAGE`, placement blockages to [`PLACEMENT_BLOCKAGE_LAYER`] and regions, labelled
+/// with their name, to [`REGION_LAYER`].
+///
+/// # Returns
+/// The elements and the layer map names missing for objects that were skipped, or an error
+/// if a placed pin shape does not fit into GDS coordinates.
+pub fn def_object_elements(
+    def: &DEF,
+    layer_of: &dyn Fn(&str) -> Option<GdsLayer>,
+) -> Result<(Vec<GdsElement>, BTreeSet<String>), String> {
+    let mut elements = vec![];
+    let mut missing = BTreeSet::new();
+    let mut lookup = |name: String, fallback: Option<&str>| {
+        let layer = layer_of(&name).or_else(|| fallback.and_then(|f| layer_of(f)));
+        if layer.is_none() {
+            missing.insert(name);
+        }
+        layer
+    };
+
+    for pin in &def.pins {
+        let properties: Vec<GdsProperty> = pin
+            .net_name
+            .iter()
+            .map(|net| GdsProperty {
+                attr: NET_NAME_PROPERTY,
+                value: net.clone(),
+            })
+            .collect();
+        for port in &pin.ports {
+            let ((px, py), orient) = port.placement.unwrap_or(((0, 0), Orient::N));
+            let place = |p: (i32, i32)| -> Result<(i32, i32), String> {
+                let (x, y) = orient_point(orient, p);
+                Ok((gds_coord(x as i64 + px as i64)?, gds_coord(y as i64 + py as i64)?))
+            };
+            let shapes = port
+                .rects
+                .iter()
+                .map(|(layer, (x1, y1), (x2, y2))| (layer, rect_points(*x1, *y1, *x2, *y2)))
+                .chain(port.polygons.iter().map(|(layer, points)| (layer, points.clone())));
+            for (layer, points) in shapes {
+                let points: Vec<(i32, i32)> = points
+                    .into_iter()
+                    .map(place)
+                    .collect::<Result<_, _>>()
+                    .map_err(|e| format!("pin {}: {}", pin.name, e))?;
+                if let Some(pin_layer) = lookup(purpose_layer(layer, PIN_PURPOSE), Some(layer)) {
+                    elements.extend(closed_boundary(pin_layer, &points, properties.clone()));
+                }
+                let label_layer = layer_of(&purpose_layer(layer, LABEL_PURPOSE))
+                    .or_else(|| layer_of(&purpose_layer(layer, PIN_PURPOSE)))
+                    .or_else(|| layer_of(layer));
+                if let Some(label_layer) = label_layer {
+                    elements.push(label(&pin.name, label_layer, centre(&points)));
+                }
+            }
+        }
+    }
+
+    for blockage in &def.blockages {
+        let layer = match &blockage.kind {
+            BlockageKind::Layer(layer) => lookup(purpose_layer(layer, BLOCKAGE_PURPOSE), None),
+            BlockageKind::Placement => lookup(PLACEMENT_BLOCKAGE_LAYER.to_string(), None),
+        };
+        let Some(layer) = layer else {
+            continue;
+        };
+        let shapes = blockage
+            .rects
+            .iter()
+            .map(|((x1, y1), (x2, y2))| rect_points(*x1, *y1, *x2, *y2))
+            .chain(blockage.polygons.iter().cloned());
+        for points in shapes {
+            elements.extend(closed_boundary(layer, &points, vec![]));
+        }
+    }
+
+    for (name, region) in &def.regions {
+        let Some(layer) = lookup(REGION_LAYER.to_string(), None) else {
+            break;
+        };
+        for ((x1, y1), (x2, y2)) in &region.rects {
+            let points = rect_points(*x1, *y1, *x2, *y2);
+            elements.extend(closed_boundary(layer, &points, vec![]));
+            elements.push(label(name, layer, centre(&points)));
+        }
+    }
+    Ok((elements, missing))
+}
+
+/// The corners of a rectangle, counter-clockwise from the lower left.
+fn rect_points(x1: i32, y1: i32, x2: i32, y2: i32) -> Vec<(i32, i32)> {
+    let (x1, y1, x2, y2) = (x1.min(x2), y1.min(y2), x1.max(x2), y1.max(y2));
+    vec![(x1, y1), (x2, y1), (x2, y2), (x1, y2)]
+}
+
+/// The centre of the bounding box of some points.
+fn centre(points: &[(i32, i32)]) -> (i32, i32) {
+    let xs = points.iter().map(|p| p.0 as i64);
+    let ys = points.iter().map(|p| p.1 as i64);
+    let x = (xs.clone().min().unwrap_or(0) + xs.max().unwrap_or(0)) / 2;
+    let y = (ys.clone().min().unwrap_or(0) + ys.max().unwrap_or(0)) / 2;
+    (x as i32, y as i32)
+}
+
+fn label(text: &str, (layer, texttype): GdsLayer, (x, y): (i32, i32)) -> GdsElement {
+    GdsElement::GdsTextElem(GdsTextElem {
+        string: text.to_string(),
+        layer,
+        texttype,
+        xy: GdsPoint { x, y },
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn outline_layer(layer: &str) -> Option<GdsLayer> {
+        (layer == OUTLINE_LAYER).then_some((63, 0))
+    }
+
+    fn no_layer(_: &str) -> Option<GdsLayer> {
+        None
+    }
+
+    #[test]
+    fn die_area_corners_become_a_rectangle() {
+        let def = DEF {
+            die_area: Some(vec![(100, 200), (0, 0)]),
+            ..Default::default()
+        };
+        let elements = die_area_elements(&def, (63, 0));
+        let [GdsElement::GdsBoundary(b)] = &elements[..] else {
+            panic!("expected one boundary");
+        };
+        assert_eq!((b.layer, b.datatype), (63, 0));
+        let xy: Vec<(i32, i32)> = b.xy.iter().map(|p| (p.x, p.y)).collect();
+        assert_eq!(xy, [(0, 0), (100, 0), (100, 200), (0, 200), (0, 0)]);
+
+        assert!(die_area_elements(&DEF::default(), (63, 0)).is_empty());
+    }
+
+    #[test]
+    fn die_area_layer_prefers_the_option_then_the_layer_map() {
+        assert_eq!(die_area_layer(Some((10, 1)), &outline_layer), (10, 1));
+        assert_eq!(die_area_layer(None, &outline_layer), (63, 0));
+        assert_eq!(die_area_layer(None, &no_layer), DEFAULT_OUTLINE_LAYER);
+    }
+}
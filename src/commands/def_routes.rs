@@ -0,0 +1,461 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use gds21::{GdsElement, GdsPath, GdsPoint, GdsProperty, GdsStrans, GdsStructRef};
+use iron_shapes::prelude::Point;
+use libreda_lefdef::common::Orient;
+use libreda_lefdef::def_ast::RoutingPoint;
+use libreda_lefdef::lef_ast::Layer;
+use libreda_lefdef::{DEF, LEF};
+
+use super::def_to_gds::{gds_coord, gds_path_ends, micron_coord};
+use super::def_vias::ViaShapes;
+use super::layer_map::GdsLayer;
+use super::geometry::{closed_boundary, convex_hull};
+use super::merge_shapes::NET_NAME_PROPERTY;
+
+/// Property attribute holding the SHAPE of a special wire, such as `STRIPE` or `FOLLOWPIN`.
+pub const ROUTE_SHAPE_PROPERTY: i16 = 3;
+
+/// Wire widths of the routing layers, in database units.
+#[derive(Clone, Debug, Default)]
+pub struct RouteWidths {
+    /// Default width per layer, the LEF layer WIDTH.
+    pub layers: HashMap<String, i32>,
+    /// Width per non-default rule and layer, from the LEF and the DEF NONDEFAULTRULES.
+    pub rules: HashMap<String, HashMap<String, i32>>,
+}
+
+impl RouteWidths {
+    /// Collect the widths of a LEF, scaled by `dbu`, and of the DEF non-default rules.
+    pub fn new(lef: &LEF, def: &DEF, dbu: u32) -> Result<Self, String> {
+        let mut widths = Self::default();
+        for layer in &lef.technology.layers {
+            if let Layer::Routing(routing) = layer {
+                let width = micron_coord(routing.width, dbu)
+                    .map_err(|e| format!("width of layer {}: {}", routing.name, e))?;
+                widths.layers.insert(routing.name.clone(), width);
+            }
+        }
+        for (name, rule) in &lef.technology.non_default_rules {
+            let layers = widths.rules.entry(name.clone()).or_default();
+            for (layer, rule_layer) in &rule.layers {
+                let width = micron_coord(rule_layer.width, dbu)
+                    .map_err(|e| format!("width of layer {} in rule {}: {}", layer, name, e))?;
+                layers.insert(layer.clone(), width);
+            }
+        }
+        for (name, rule) in &def.non_default_rules {
+            let layers = widths.rules.entry(name.clone()).or_default();
+            for (layer, rule_layer) in &rule.layers {
+                layers.insert(layer.clone(), rule_layer.width);
+            }
+        }
+        Ok(widths)
+    }
+
+    /// Width of a wire on `layer`, taken from the non-default rule if it sets one.
+    pub fn width(&self, layer: &str, rule: Option<&str>) -> Option<i32> {
+        rule.and_then(|r| self.rules.get(r))
+            .and_then(|r| r.get(layer))
+            .or_else(|| self.layers.get(layer))
+            .copied()
+    }
+}
+
+/// The GDS rotation and reflection of a DEF orientation, `None` for `N`.
+pub fn orient_strans(orient: Orient) -> Option<GdsStrans> {
+    let (reflected, angle) = match orient {
+        Orient::N => return None,
+        Orient::W => (false, 90.0),
+        Orient::S => (false, 180.0),
+        Orient::E => (false, 270.0),
+        Orient::FS => (true, 0.0),
+        Orient::FW => (true, 90.0),
+        Orient::FN => (true, 180.0),
+        Orient::FE => (true, 270.0),
+    };
+    Some(GdsStrans {
+        reflected,
+        abs_mag: false,
+        abs_angle: false,
+        mag: None,
+        angle: (angle != 0.0).then_some(angle),
+    })
+}
+
+/// Convert the wiring of the DEF NETS and SPECIALNETS to GDS elements.
+///
+/// Regular wires get the LEF or non-default rule width and half-width end extensions, special
+/// wires their own width and flush ends, both unless a point carries an explicit extension.
+/// Wires with a STYLE are drawn as polygons, vias are placed as references to the via structs.
+/// Every element carries the net name in [`NET_NAME_PROPERTY`], special wires, RECTs and
+/// POLYGONs also their SHAPE in [`ROUTE_SHAPE_PROPERTY`].
+///
+/// # Returns
+/// The elements and a list of problems, such as layers without a GDS layer.
+pub fn route_elements(
+    def: &DEF,
+    widths: &RouteWidths,
+    vias: &BTreeMap<String, ViaShapes>,
+    layer_of: &dyn Fn(&str) -> Option<GdsLayer>,
+) -> (Vec<GdsElement>, BTreeSet<String>) {
+    let styles = def
+        .styles
+        .iter()
+        .map(|style| (style.number, style.points.clone()))
+        .collect();
+    let mut writer = RouteWriter {
+        widths,
+        vias,
+        styles,
+        layer_of,
+        elements: vec![],
+        problems: BTreeSet::new(),
+    };
+
+    for net in &def.nets {
+        let properties = net_properties(&net.name, None);
+        let rule = net.non_default_rule.as_deref();
+        for wiring in &net.wiring {
+            for statement in &wiring.statements {
+                let route = Route {
+                    width: None,
+                    rule,
+                    style: statement.style,
+                    special: false,
+                };
+                writer.route(&statement.layer, &route, &statement.points, &properties);
+            }
+            for (layer, points) in &wiring.polygons {
+                writer.polygon(layer, points, &properties);
+            }
+        }
+    }
+
+    for net in &def.special_nets {
+        for (layer, shape, (x1, y1), (x2, y2)) in &net.rects {
+            let properties = net_properties(&net.name, shape.as_ref().map(|s| s.to_string()));
+            writer.rect(layer, (*x1, *y1, *x2, *y2), &properties);
+        }
+        for (layer, shape, points) in &net.polygons {
+            let properties = net_properties(&net.name, shape.as_ref().map(|s| s.to_string()));
+            writer.polygon(layer, points, &properties);
+        }
+        let properties = net_properties(&net.name, None);
+        for (name, orient, points) in &net.vias {
+            for point in points {
+                writer.via(name, *point, *orient, &properties);
+            }
+        }
+        for wiring in &net.wiring {
+            for statement in &wiring.statements {
+                let shape = statement.shape.as_ref().map(|s| s.to_string());
+                let properties = net_properties(&net.name, shape);
+                let route = Route {
+                    width: Some(statement.width),
+                    rule: None,
+                    style: statement.style,
+                    special: true,
+                };
+                writer.route(&statement.layer, &route, &statement.points, &properties);
+            }
+        }
+    }
+    (writer.elements, writer.problems)
+}
+
+/// The properties of a routing shape: net name and, for special wires, the SHAPE.
+fn net_properties(net: &str, shape: Option<String>) -> Vec<GdsProperty> {
+    let mut properties = vec![GdsProperty {
+        attr: NET_NAME_PROPERTY,
+        value: net.to_string(),
+    }];
+    if let Some(shape) = shape {
+        properties.push(GdsProperty {
+            attr: ROUTE_SHAPE_PROPERTY,
+            value: shape,
+        });
+    }
+    properties
+}
+
+/// A RECT of a routing statement, given relative to the point `(x, y)`.
+fn offset_rect((x, y): (i32, i32), (dx1, dy1, dx2, dy2): (i32, i32, i32, i32)) -> Result<(i32, i32, i32, i32), String> {
+    let (x, y) = (x as i64, y as i64);
+    Ok((
+        gds_coord(x + dx1 as i64)?,
+        gds_coord(y + dy1 as i64)?,
+        gds_coord(x + dx2 as i64)?,
+        gds_coord(y + dy2 as i64)?,
+    ))
+}
+
+/// How the wires of one routing statement are drawn.
+struct Route<'a> {
+    /// Width given by a special wire, looked up per layer otherwise.
+    width: Option<i32>,
+    /// Non-default rule of the net.
+    rule: Option<&'a str>,
+    style: Option<u32>,
+    /// Special wires end flush with their points.
+    special: bool,
+}
+
+/// Collects the GDS elements of the routing statements.
+struct RouteWriter<'a> {
+    widths: &'a RouteWidths,
+    vias: &'a BTreeMap<String, ViaShapes>,
+    /// Polygons of the DEF STYLES by style number, relative to the wire centre line.
+    styles: HashMap<u32, Vec<(i32, i32)>>,
+    layer_of: &'a dyn Fn(&str) -> Option<GdsLayer>,
+    elements: Vec<GdsElement>,
+    problems: BTreeSet<String>,
+}
+
+impl RouteWriter<'_> {
+    /// The GDS layer of a LEF layer, recording layers that have none.
+    fn gds_layer(&mut self, layer: &str) -> Option<GdsLayer> {
+        let gds_layer = (self.layer_of)(layer);
+        if gds_layer.is_none() {
+            self.problems.insert(format!("no GDS layer for {}", layer));
+        }
+        gds_layer
+    }
+
+    /// Draw the points of a routing statement, starting on `layer`.
+    ///
+    /// A via is placed at the last point and switches to its other routing layer, RECTs are
+    /// relative to the last point and a VIRTUAL point starts a new wire without connecting.
+    fn route(&mut self, layer: &str, route: &Route, points: &[RoutingPoint], properties: &[GdsProperty]) {
+        let mut layer = layer.to_string();
+        let mut wire: Vec<((i32, i32), Option<i32>)> = vec![];
+        let mut last: Option<(i32, i32)> = None;
+        for point in points {
+            match point {
+                RoutingPoint::Point(x, y, ext) => {
+                    let (last_x, last_y) = last.unwrap_or_default();
+                    let p = (x.unwrap_or(last_x), y.unwrap_or(last_y));
+                    if wire.last().map(|(q, _)| *q != p).unwrap_or(true) {
+                        wire.push((p, *ext));
+                    } else if ext.is_some() {
+                        wire.last_mut().unwrap().1 = *ext;
+                    }
+                    last = Some(p);
+                }
+                RoutingPoint::Via(name, orient) => {
+                    self.wire(&layer, route, &wire, properties);
+                    wire.clear();
+                    if let Some(p) = last {
+                        self.via(name, p, orient.unwrap_or(Orient::N), properties);
+                        if let Some(next) = self.via_other_layer(name, &layer) {
+                            layer = next;
+                        }
+                        wire.push((p, None));
+                    }
+                }
+                RoutingPoint::Rect(dx1, dy1, dx2, dy2) => {
+                    if let Some(p) = last {
+                        match offset_rect(p, (*dx1, *dy1, *dx2, *dy2)) {
+                            Ok(rect) => self.rect(&layer, rect, properties),
+                            Err(e) => {
+                                self.problems.insert(format!("RECT on {}: {}", layer, e));
+                            }
+                        }
+                    }
+                }
+                RoutingPoint::Virtual(x, y) => {
+                    self.wire(&layer, route, &wire, properties);
+                    wire = vec![((*x, *y), None)];
+                    last = Some((*x, *y));
+                }
+            }
+        }
+        self.wire(&layer, route, &wire, properties);
+    }
+
+    /// Draw a wire through at least two points, as a path or, with a style, as polygons.
+    fn wire(&mut self, layer: &str, route: &Route, wire: &[((i32, i32), Option<i32>)], properties: &[GdsProperty]) {
+        if wire.len() < 2 {
+            return;
+        }
+        let Some(width) = route.width.or_else(|| self.widths.width(layer, route.rule)) else {
+            self.problems.insert(format!("no wire width for {}", layer));
+            return;
+        };
+        let Some((gds_layer, datatype)) = self.gds_layer(layer) else {
+            return;
+        };
+
+        if let Some(style) = route.style.and_then(|s| self.styles.get(&s)).cloned() {
+            for segment in wire.windows(2) {
+                let ((x1, y1), (x2, y2)) = (segment[0].0, segment[1].0);
+                let corners = style
+                    .iter()
+                    .flat_map(|&(dx, dy)| {
+                        [
+                            Point::new(x1 as i64 + dx as i64, y1 as i64 + dy as i64),
+                            Point::new(x2 as i64 + dx as i64, y2 as i64 + dy as i64),
+                        ]
+                    })
+                    .collect();
+                let hull: Result<Vec<(i32, i32)>, String> = convex_hull(corners)
+                    .into_iter()
+                    .map(|p| Ok((gds_coord(p.x)?, gds_coord(p.y)?)))
+                    .collect();
+                match hull {
+                    Ok(hull) => self.push_boundary((gds_layer, datatype), &hull, properties),
+                    Err(e) => {
+                        self.problems.insert(format!("styled wire on {}: {}", layer, e));
+                    }
+                }
+            }
+            return;
+        }
+
+        let default_ext = if route.special { 0 } else { width / 2 };
+        let begin = wire[0].1.unwrap_or(default_ext);
+        let end = wire[wire.len() - 1].1.unwrap_or(default_ext);
+        let (path_type, begin_extn, end_extn) = gds_path_ends(width, begin, end);
+        self.elements.push(GdsElement::GdsPath(GdsPath {
+            layer: gds_layer,
+            datatype,
+            xy: wire.iter().map(|((x, y), _)| GdsPoint { x: *x, y: *y }).collect(),
+            width: Some(width),
+            path_type,
+            begin_extn,
+            end_extn,
+            properties: properties.to_vec(),
+            ..Default::default()
+        }));
+    }
+
+    /// Place a via struct with its origin at `at`.
+    fn via(&mut self, name: &str, at: (i32, i32), orient: Orient, properties: &[GdsProperty]) {
+        if !self.vias.contains_key(name) {
+            self.problems.insert(format!("unknown via {}", name));
+            return;
+        }
+        self.elements.push(GdsElement::GdsStructRef(GdsStructRef {
+            name: name.to_string(),
+            xy: GdsPoint { x: at.0, y: at.1 },
+            strans: orient_strans(orient),
+            elflags: None,
+            plex: None,
+            properties: properties.to_vec(),
+        }));
+    }
+
+    /// The routing layer of a via on the other side of `layer`.
+    fn via_other_layer(&self, via: &str, layer: &str) -> Option<String> {
+        let shapes = self.vias.get(via)?;
+        shapes
+            .rects
+            .iter()
+            .map(|(l, _)| l)
+            .chain(shapes.polygons.iter().map(|(l, _)| l))
+            .find(|l| *l != layer && self.widths.layers.contains_key(*l))
+            .cloned()
+    }
+
+    fn rect(&mut self, layer: &str, (x1, y1, x2, y2): (i32, i32, i32, i32), properties: &[GdsProperty]) {
+        if let Some(gds_layer) = self.gds_layer(layer) {
+            let (x1, y1, x2, y2) = (x1.min(x2), y1.min(y2), x1.max(x2), y1.max(y2));
+            self.push_boundary(gds_layer, &[(x1, y1), (x2, y1), (x2, y2), (x1, y2)], properties);
+        }
+    }
+
+    fn polygon(&mut self, layer: &str, points: &[(i32, i32)], properties: &[GdsProperty]) {
+        if let Some(gds_layer) = self.gds_layer(layer) {
+            self.push_boundary(gds_layer, points, properties);
+        }
+    }
+
+    fn push_boundary(&mut self, layer: GdsLayer, points: &[(i32, i32)], properties: &[GdsProperty]) {
+        self.elements.extend(closed_boundary(layer, points, properties.to_vec()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn writer<'a>(
+        widths: &'a RouteWidths,
+        vias: &'a BTreeMap<String, ViaShapes>,
+        layer_of: &'a dyn Fn(&str) -> Option<GdsLayer>,
+    ) -> RouteWriter<'a> {
+        RouteWriter {
+            widths,
+            vias,
+            styles: HashMap::from([(0, vec![(-5, -5), (5, -5), (5, 5), (-5, 5)])]),
+            layer_of,
+            elements: vec![],
+            problems: BTreeSet::new(),
+        }
+    }
+
+    fn metal1(layer: &str) -> Option<GdsLayer> {
+        (layer == "metal1").then_some((10, 0))
+    }
+
+    fn boundary_points(element: &GdsElement) -> Vec<(i32, i32)> {
+        match element {
+            GdsElement::GdsBoundary(b) => b.xy.iter().map(|p| (p.x, p.y)).collect(),
+            _ => panic!("expected a boundary"),
+        }
+    }
+
+    const STYLED: Route = Route {
+        width: Some(10),
+        rule: None,
+        style: Some(0),
+        special: true,
+    };
+
+    #[test]
+    fn styled_wires_become_the_hull_of_the_style_polygon() {
+        let (widths, vias) = (RouteWidths::default(), BTreeMap::new());
+        let mut writer = writer(&widths, &vias, &metal1);
+        writer.wire("metal1", &STYLED, &[((0, 0), None), ((100, 0), None)], &[]);
+        assert!(writer.problems.is_empty());
+        assert_eq!(writer.elements.len(), 1);
+        assert_eq!(
+            boundary_points(&writer.elements[0]),
+            [(-5, -5), (105, -5), (105, 5), (-5, 5), (-5, -5)]
+        );
+    }
+
+    #[test]
+    fn styled_wires_outside_the_coordinate_range_are_reported() {
+        let (widths, vias) = (RouteWidths::default(), BTreeMap::new());
+        let mut writer = writer(&widths, &vias, &metal1);
+        let end = i32::MAX - 1;
+        writer.wire("metal1", &STYLED, &[((0, 0), None), ((end, 0), None)], &[]);
+        assert!(writer.elements.is_empty());
+        assert_eq!(writer.problems.len(), 1);
+    }
+
+    #[test]
+    fn rects_outside_the_coordinate_range_are_reported() {
+        assert_eq!(offset_rect((100, 200), (-5, -5, 5, 5)), Ok((95, 195, 105, 205)));
+        assert!(offset_rect((i32::MAX - 2, 0), (-5, -5, 5, 5)).is_err());
+    }
+
+    #[test]
+    fn polygons_carry_the_shape_property() {
+        let (widths, vias) = (RouteWidths::default(), BTreeMap::new());
+        let mut writer = writer(&widths, &vias, &metal1);
+        let properties = net_properties("VDD", Some("STRIPE".to_string()));
+        writer.polygon("metal1", &[(0, 0), (10, 0), (0, 10)], &properties);
+        writer.polygon("poly", &[(0, 0), (10, 0), (0, 10)], &properties);
+
+        assert_eq!(writer.elements.len(), 1);
+        assert_eq!(boundary_points(&writer.elements[0]), [(0, 0), (10, 0), (0, 10), (0, 0)]);
+        let GdsElement::GdsBoundary(b) = &writer.elements[0] else {
+            unreachable!()
+        };
+        let attrs: Vec<(i16, &str)> = b.properties.iter().map(|p| (p.attr, p.value.as_str())).collect();
+        assert_eq!(attrs, [(NET_NAME_PROPERTY, "VDD"), (ROUTE_SHAPE_PROPERTY, "STRIPE")]);
+        assert!(writer.problems.contains("no GDS layer for poly"));
+    }
+}
@@ -1,4 +1,5 @@
 use std::any::Any;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs::File;
 use std::io::BufReader;
 use std::ops::{Add, Sub};
@@ -14,41 +15,96 @@ use iron_shapes::prelude::Orientation2D::Horizontal;
 use libreda_db::prelude::FnName::{each_cell_instance, template_cell};
 use libreda_lefdef::import::{DEFImportOptions, import_def_into_db, import_lef_into_db, LEFImportOptions};
 use libreda_oasis::OASISStreamWriter;
+use regex::RegexSet;
 use uuid::Uuid;
 
+use super::arrays::compact_struct_arrays;
+use super::cell_layouts::{fill_macro_layouts, MacroAbstract};
+use super::def_grids::{grid_elements, GridLayers};
+use super::def_objects::{def_object_elements, die_area_elements, die_area_layer};
+use super::def_routes::{route_elements, RouteWidths};
+use super::def_vias::{via_definitions, via_struct, ViaShapes};
+use super::geometry::{closed_points, polygon_to_outlines};
+use super::layer_map::{GdsLayer, LayerMap};
+use super::lef_merge::merge_lefs;
+use super::merge_libraries::rescale_element;
+use super::merge_shapes::{merge_struct_shapes, MergeMode};
+use super::prune_cells::prune_library;
+use super::rename_cells::{apply_renames, cell_renames, GDS_MAX_NAME_LENGTH};
+use super::vertex_limit::{save_library, GDS_MAX_POINTS};
+
 /// A trait to constrain coordinate types used in shapes, ensuring they implement required traits.
 pub trait CoordConstraints: CoordinateType + std::fmt::Debug + std::fmt::Display {}
 
 impl<T> CoordConstraints for T where T: CoordinateType + std::fmt::Debug + std::fmt::Display {}
 
+/// Options applied when writing the GDS library of a converted design.
+#[derive(Clone, Debug, Default)]
+pub struct GdsWriteOptions {
+    /// Merge overlapping shapes per layer, or per net within each layer.
+    pub merge: Option<MergeMode>,
+    /// Replace regular grids of instances by AREFs, dropping their instance names.
+    pub compact_arrays: bool,
+    /// GDS layers of the LEF/DEF layers and of pins, blockages, regions and the outline.
+    pub layer_map: Option<LayerMap>,
+    /// GDS layer of the DIEAREA outline, see [`die_area_layer`] when `None`.
+    pub outline_layer: Option<GdsLayer>,
+    /// Layers for drawing the rows, tracks and gcell grid of the DEF.
+    pub grids: GridLayers,
+    /// GDS libraries with the full layouts of the LEF macros.
+    pub cell_libraries: Vec<PathBuf>,
+    /// Database units per micron of the GDS file, the DEF UNITS when `None`.
+    pub dbu: Option<u32>,
+    /// The GDS user unit in microns, one micron when `None`.
+    pub user_unit: Option<f64>,
+    /// Name of the top cell, the DEF design name when `None`.
+    pub top_name: Option<String>,
+    /// Also write the LEF macros no component places.
+    pub emit_unused_macros: bool,
+    /// Prefix added to the names of the LEF macro cells.
+    pub macro_prefix: Option<String>,
+    /// Cells not to write, the references to them are kept.
+    pub exclude_cells: Option<RegexSet>,
+    /// Most XY points per boundary or path, [`GDS_MAX_POINTS`] when `None`.
+    pub max_points: Option<usize>,
+}
+
 /// Convert a DEF file to a GDSII layout.
 ///
 /// # Arguments
-/// * `_top` - Top-level cell name (currently unused)
+/// * `top` - Name of the top cell in the GDS, the DEF design name if empty
 /// * `input` - Path to the DEF file
 /// * `output` - Output file path
 /// * `lef_files` - List of LEF files to import
+/// * `options` - Post-processing of the written GDS library
 ///
 /// # Returns
 /// A result indicating the success or failure of the conversion process.
 pub fn convert_def_to_gds(
-    _top: &str,
+    top: &str,
     input: &PathBuf,
     output: &PathBuf,
     lef_files: &[&PathBuf],
+    options: &GdsWriteOptions,
 ) -> Result<bool, Box<dyn std::error::Error>> {
     // Create a GDS-to-DEF conversion flow.
     let mut flow: DefToGdsFlow<Chip> = DefToGdsFlow::new();
+    flow.gds_options = options.clone();
+    if !top.is_empty() {
+        flow.gds_options.top_name = Some(top.to_string());
+    }
+
+    // Read the DEF first, its units are the database units of the flow.
+    flow.read_def(input)?;
 
     // Import LEF files into the database.
-    flow.import_lefs_into_db(&lef_files);
+    flow.import_lefs_into_db(lef_files)?;
 
     // Import the DEF design into the DB format.
-    flow.import_def_into_db(&input);
+    flow.import_def_into_db()?;
 
-    dbg!(&flow.chip);
     // Export the design into the GDSII format.
-    flow.generate_gds_file(&output);
+    flow.generate_gds_file(output)?;
 
     Ok(true)
 }
@@ -72,14 +128,17 @@ pub fn convert_def_to_oasis(
     // Create a GDS-to-DEF conversion flow.
     let mut flow: DefToGdsFlow<Chip> = DefToGdsFlow::new();
 
+    // Read the DEF first, its units are the database units of the flow.
+    flow.read_def(input)?;
+
     // Import LEF files into the database.
-    flow.import_lefs_into_db(&lef_files);
+    flow.import_lefs_into_db(lef_files)?;
 
     // Import the DEF design into the DB format.
-    flow.import_def_into_db(&input);
+    flow.import_def_into_db()?;
 
-    // Export the design into the GDSII format.
-    flow.generate_oasis_file(&output);
+    // Export the design into the OASIS format.
+    flow.generate_oasis_file(output)?;
 
     Ok(true)
 }
@@ -94,7 +153,7 @@ pub struct DefToGdsFlow<C>
     pub chip: C,
     /// Path to technology LEF file.
     pub tech_lef_path: std::path::PathBuf,
-    /// Technology LEF data.
+    /// Technology LEF data, merged with the other LEF files.
     pub tech_lef: libreda_lefdef::LEF,
     /// Top cell identifier.
     pub top_cell: Option<C::CellId>,
@@ -110,6 +169,8 @@ pub struct DefToGdsFlow<C>
     pub outline_layer: Option<C::LayerId>,
     /// Target density for placement. Must be in the range `(0.0, 1.0]`.
     pub placement_target_density: f64,
+    /// Options applied when writing GDS.
+    pub gds_options: GdsWriteOptions,
 }
 
 impl<C> DefToGdsFlow<C>
@@ -129,6 +190,7 @@ impl<C> DefToGdsFlow<C>
             core_area: Default::default(),
             outline_layer: Default::default(),
             placement_target_density: 0.5,
+            gds_options: Default::default(),
         };
 
         simple_flow.init();
@@ -137,110 +199,445 @@ impl<C> DefToGdsFlow<C>
 
     /// Initialize the conversion flow.
     fn init(&mut self) {
-        // Set database units to 1000 (DBU).
-        self.chip.set_dbu(1000);
+        // Default database units until the DEF gives its own.
+        self.chip.set_dbu(DEFAULT_DBU);
     }
 
     /// Import LEF files into the database.
-    pub fn import_lefs_into_db(&mut self, lef_files: &[&PathBuf]) {
-        self.tech_lef_path = lef_files.get(0).expect("Expected at least one LEF").into();
+    ///
+    /// The first file is the technology LEF. All files are merged into one LEF before the
+    /// import, so cell LEFs can use the layers and sites of the technology LEF.
+    ///
+    /// LEF values are in microns and are converted with the database units of the DEF, whatever
+    /// the LEF `DATABASE MICRONS`. Without a DEF read first the LEF units are used.
+    pub fn import_lefs_into_db(&mut self, lef_files: &[&PathBuf]) -> Result<(), Box<dyn std::error::Error>> {
+        let tech_lef = lef_files.first().ok_or("Expected at least one LEF")?;
+        self.tech_lef_path = tech_lef.to_path_buf();
 
-        let lef_contents: Vec<_> = lef_files.iter().map(|f| self.import_lef(f)).collect();
-
-        // Import each LEF library into the database format.
-        for lef in lef_contents {
-            let options = LEFImportOptions::default();
-            self.tech_lef = lef.clone(); // TODO : overrides but there be more than one lef
-            import_lef_into_db(&options, &lef, &mut self.chip).expect("Failed to import LEF.");
+        let mut lef_contents = Vec::with_capacity(lef_files.len());
+        for f in lef_files {
+            lef_contents.push((f.display().to_string(), self.import_lef(f)?));
+        }
+        let (lef, conflicts) = merge_lefs(lef_contents)?;
+        for conflict in &conflicts {
+            println!("LEF: {}", conflict);
         }
+        self.check_lef_units(&lef);
+
+        // Import the merged LEF library into the database format.
+        let options = LEFImportOptions::default();
+        import_lef_into_db(&options, &lef, &mut self.chip)
+            .map_err(|e| format!("Failed to import LEF: {:?}", e))?;
+        self.tech_lef = lef;
+        Ok(())
     }
 
     /// Import a single LEF file.
-    fn import_lef(&self, fp: &PathBuf) -> libreda_lefdef::LEF {
-        let fh = File::open(fp).expect("Failed to open LEF file.");
+    fn import_lef(&self, fp: &PathBuf) -> Result<libreda_lefdef::LEF, String> {
+        let fh = File::open(fp).map_err(|e| format!("Failed to open LEF file {}: {}", fp.display(), e))?;
         let mut buf = BufReader::new(fh);
 
-        let result = libreda_lefdef::lef_parser::read_lef_bytes(&mut buf);
+        libreda_lefdef::lef_parser::read_lef_bytes(&mut buf)
+            .map_err(|e| format!("Failed to parse LEF file {}: {:?}", fp.display(), e))
+    }
+
+    /// Compare the LEF database units with those of the flow.
+    ///
+    /// The DEF units are used when a DEF was read, otherwise the LEF sets them.
+    fn check_lef_units(&mut self, lef: &libreda_lefdef::LEF) {
+        let Some(lef_dbu) = lef.technology.units.database_microns else {
+            return;
+        };
+        let dbu = self.chip.dbu() as u32;
+        if self.def.units == 0 {
+            self.chip.set_dbu(lef_dbu as _);
+        } else if dbu % lef_dbu != 0 {
+            println!(
+                "LEF DATABASE MICRONS {} do not divide the DEF UNITS {}, LEF values are rounded to 1/{} micron",
+                lef_dbu, dbu, dbu
+            );
+        } else if lef_dbu != dbu {
+            println!(
+                "LEF DATABASE MICRONS {} differ from the DEF UNITS {}, LEF values are converted with the DEF UNITS",
+                lef_dbu, dbu
+            );
+        }
+    }
 
-        result.expect("Failed to parse LEF.")
+    /// Read the DEF file and take its `UNITS DISTANCE MICRONS` as the database units.
+    pub fn read_def(&mut self, input: &PathBuf) -> Result<(), String> {
+        self.def = self.import_def(input)?;
+        if self.def.units != 0 {
+            self.chip.set_dbu(self.def.units as _);
+        }
+        Ok(())
     }
 
-    /// Import the DEF file into the database.
-    pub fn import_def_into_db(&mut self, input: &PathBuf) {
-        let def = self.import_def(input);
+    /// Import the DEF read by [`Self::read_def`] into the database.
+    pub fn import_def_into_db(&mut self) -> Result<(), String> {
+        let def = &self.def;
         let def_import_options: DEFImportOptions<_> = DEFImportOptions::default();
 
-        import_def_into_db(&def_import_options, Some(&self.tech_lef), &def, &mut self.chip)
-            .expect("Failed to import DEF.");
-        let name = def.design_name.expect("DEF error, design name expected");
-        self.top_cell = Some(self.chip
+        import_def_into_db(&def_import_options, Some(&self.tech_lef), def, &mut self.chip)
+            .map_err(|e| format!("Failed to import DEF: {:?}", e))?;
+        let name = self.design_name()?;
+        let top_cell = self
+            .chip
             .cell_by_name(name.as_str())
-            .expect("DEF error, design name not found"));
+            .ok_or_else(|| format!("DEF error, design {} not found", name))?;
+        self.top_cell = Some(top_cell);
+        Ok(())
     }
 
     /// Import a single DEF file.
-    fn import_def(&self, fp: &PathBuf) -> libreda_lefdef::DEF {
-        let fh = File::open(fp).expect("Failed to open DEF file.");
+    fn import_def(&self, fp: &PathBuf) -> Result<libreda_lefdef::DEF, String> {
+        let fh = File::open(fp).map_err(|e| format!("Failed to open DEF file {}: {}", fp.display(), e))?;
         let mut buf = BufReader::new(fh);
 
-        let result = libreda_lefdef::def_parser::read_def_bytes(&mut buf);
-
-        result.expect("Failed to parse DEF.")
+        libreda_lefdef::def_parser::read_def_bytes(&mut buf)
+            .map_err(|e| format!("Failed to parse DEF file {}: {:?}", fp.display(), e))
     }
 
     /// Generate a GDS file from the chip data using OASIS.
-    pub fn generate_gds_file(self, fp: &PathBuf) {
+    pub fn generate_gds_file(self, fp: &PathBuf) -> Result<(), Box<dyn std::error::Error>> {
         // let mut fh = File::create(fp).expect("Failed to create GDS file.");
         // let writer = OASISStreamWriter::default();
         // writer.write_layout(&mut fh, &self.chip).expect("Failed to write GDS layout.");
-        self._generate_gds_file_with_gds21(&fp);
+        self._generate_gds_file_with_gds21(fp)
     }
 
     /// Generate an OASIS file from the chip data.
-    pub fn generate_oasis_file(&self, fp: &PathBuf) {
+    pub fn generate_oasis_file(&self, fp: &PathBuf) -> Result<(), Box<dyn std::error::Error>> {
         let mut oasis_path = fp.clone();
         oasis_path.set_extension("oas");
 
-        let mut fh = File::create(&oasis_path).expect("Failed to create OASIS file");
+        let mut fh = File::create(&oasis_path)?;
 
         let writer = OASISStreamWriter::default();
         writer
             .write_layout(&mut fh, &self.chip)
-            .expect("Failed to write OASIS layout");
+            .map_err(|e| format!("Failed to write OASIS layout: {:?}", e))?;
+        Ok(())
     }
 
     /// Generate a GDS file using gds21.
-    fn _generate_gds_file_with_gds21(self, fp: &PathBuf) {
+    fn _generate_gds_file_with_gds21(self, fp: &PathBuf) -> Result<(), Box<dyn std::error::Error>> {
         let mut gds_path = fp.clone();
         gds_path.set_extension("gds");
-        let top_cell = self.top_cell;
-        let gds_library: GdsLibrary = chip_to_gds_library(&self.chip, top_cell.unwrap().into());
+        let top_cell = self.top_cell.clone().ok_or("The DEF design has not been imported")?;
+        let vias = via_definitions(&self.tech_lef, &self.def, self.chip.dbu() as u32)?;
+        let via_structs = self.via_structs(&vias);
+        let content = self.top_cell_content(&vias)?;
+        let units = gds_units(self.chip.dbu() as u32, self.gds_options.user_unit);
+        let mut gds_library: GdsLibrary =
+            chip_to_gds_library(&self.chip, top_cell.into(), units, via_structs, content)?;
+        self.fill_macro_layouts(&mut gds_library)?;
+        self.select_cells(&mut gds_library)?;
+        self.rename_output_cells(&mut gds_library)?;
+        self.rescale_to_output_units(&mut gds_library)?;
+
+        for gds_struct in &mut gds_library.structs {
+            if let Some(mode) = self.gds_options.merge {
+                for warning in merge_struct_shapes(gds_struct, None, mode) {
+                    println!("{}: {}", gds_struct.name, warning);
+                }
+            }
+            if self.gds_options.compact_arrays {
+                compact_struct_arrays(gds_struct, 2, true);
+            }
+        }
+
+        let max_points = self.gds_options.max_points.unwrap_or(GDS_MAX_POINTS);
+        save_library(&mut gds_library, &gds_path, max_points)?;
+        Ok(())
+    }
+
+    /// Replace the LEF abstracts of the macros by their layouts from the cell libraries.
+    fn fill_macro_layouts(&self, gds_library: &mut GdsLibrary) -> Result<(), String> {
+        if self.gds_options.cell_libraries.is_empty() {
+            return Ok(());
+        }
+        let cell_libraries = self
+            .gds_options
+            .cell_libraries
+            .iter()
+            .map(|path| {
+                GdsLibrary::load(path)
+                    .map_err(|e| format!("Failed to read cell GDS library {}: {}", path.display(), e))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let report = fill_macro_layouts(gds_library, &self.macro_abstracts()?, cell_libraries)
+            .map_err(|e| format!("Failed to fill in the macro layouts: {}", e))?;
+        for name in &report.missing {
+            println!("Macro {} has no GDS layout, keeping its LEF abstract", name);
+        }
+        for mismatch in &report.mismatches {
+            println!("Macro {}", mismatch);
+        }
+        Ok(())
+    }
+
+    /// Name of the DEF design, which is the top cell of the chip.
+    fn design_name(&self) -> Result<String, String> {
+        self.def
+            .design_name
+            .clone()
+            .ok_or_else(|| "DEF error, design name expected".to_string())
+    }
+
+    /// Keep the cells reachable from the top cell, and from the macros with `emit_unused_macros`,
+    /// and drop those matching `exclude_cells`, see [`select_library_cells`].
+    fn select_cells(&self, gds_library: &mut GdsLibrary) -> Result<(), String> {
+        let top = self.design_name()?;
+        let macros: Vec<&str> = if self.gds_options.emit_unused_macros {
+            self.tech_lef.library.macros.keys().map(|name| name.as_str()).collect()
+        } else {
+            vec![]
+        };
+        select_library_cells(gds_library, &top, &macros, self.gds_options.exclude_cells.as_ref())
+            .map_err(|e| format!("Failed to select the cells to write: {}", e))
+    }
+
+    /// Prefix the macro cells with `macro_prefix` and give the top cell the `top_name`.
+    fn rename_output_cells(&self, gds_library: &mut GdsLibrary) -> Result<(), String> {
+        let mut mapping: HashMap<String, String> = HashMap::new();
+        if let Some(prefix) = &self.gds_options.macro_prefix {
+            for name in self.tech_lef.library.macros.keys() {
+                mapping.insert(name.clone(), format!("{}{}", prefix, name));
+            }
+        }
+        if let Some(top) = &self.gds_options.top_name {
+            mapping.insert(self.design_name()?, top.clone());
+        }
+        // Unused macros may be gone already.
+        mapping.retain(|name, _| gds_library.structs.iter().any(|s| &s.name == name));
+        if mapping.is_empty() {
+            return Ok(());
+        }
+        let renames = cell_renames(gds_library, &[], &mapping, GDS_MAX_NAME_LENGTH)
+            .map_err(|e| format!("Failed to rename the output cells: {}", e))?;
+        apply_renames(gds_library, &renames);
+        Ok(())
+    }
 
-        gds_library
-            .save(&gds_path)
-            .expect("Failed to write GDS layout using gds21");
+    /// Rescale the library from the DEF units to the `dbu` option, if given.
+    fn rescale_to_output_units(&self, gds_library: &mut GdsLibrary) -> Result<(), String> {
+        let dbu = self.chip.dbu() as u32;
+        let Some(output_dbu) = self.gds_options.dbu.filter(|&d| d != dbu) else {
+            return Ok(());
+        };
+        if output_dbu % dbu != 0 {
+            println!(
+                "GDS database units {} are not a multiple of the DEF UNITS {}, coordinates are rounded",
+                output_dbu, dbu
+            );
+        }
+        let factor = output_dbu as f64 / dbu as f64;
+        for gds_struct in &mut gds_library.structs {
+            for element in &mut gds_struct.elems {
+                rescale_element(element, factor)
+                    .map_err(|e| format!("Failed to rescale cell {}: {}", gds_struct.name, e))?;
+            }
+        }
+        gds_library.units = gds_units(output_dbu, self.gds_options.user_unit);
+        Ok(())
+    }
+
+    /// Size, origin and pins of every LEF macro, in database units.
+    fn macro_abstracts(&self) -> Result<Vec<MacroAbstract>, String> {
+        let dbu = self.chip.dbu() as u32;
+        let pair = |(x, y): (f64, f64)| -> Result<(i32, i32), String> {
+            Ok((micron_coord(x, dbu)?, micron_coord(y, dbu)?))
+        };
+        self.tech_lef
+            .library
+            .macros
+            .iter()
+            .map(|(name, m)| {
+                let in_macro = |e: String| format!("macro {}: {}", name, e);
+                Ok(MacroAbstract {
+                    name: name.clone(),
+                    size: m.size.map(pair).transpose().map_err(in_macro)?,
+                    origin: pair(m.origin).map_err(in_macro)?,
+                    pins: m.pins.iter().map(|pin| pin.name.clone()).collect(),
+                })
+            })
+            .collect()
     }
+
+    /// The GDS layer of a LEF/DEF layer name, from the layer map or else the chip layer index.
+    fn gds_layer(&self, name: &str) -> Option<GdsLayer> {
+        self.gds_options
+            .layer_map
+            .as_ref()
+            .and_then(|map| map.get(name))
+            .or_else(|| {
+                self.chip
+                    .layer_by_name(name)
+                    .map(|layer| (self.chip.layer_info(&layer).index as i16, 0))
+            })
+    }
+
+    /// One GDS struct per via defined in the LEF or the DEF VIAS section, generated vias included.
+    fn via_structs(&self, vias: &BTreeMap<String, ViaShapes>) -> Vec<GdsStruct> {
+        vias.iter()
+            .map(|(name, shapes)| {
+                let (via, missing) = via_struct(name, shapes, |layer| self.gds_layer(layer));
+                if !missing.is_empty() {
+                    println!("Via {}: no GDS layer for {}", name, missing.join(", "));
+                }
+                via
+            })
+            .collect()
+    }
+
+    /// The routing, pins, blockages, regions, die area and, if requested, grids of the DEF.
+    fn top_cell_content(&self, vias: &BTreeMap<String, ViaShapes>) -> Result<TopCellContent, String> {
+        let layer_of = |layer: &str| self.gds_layer(layer);
+        let widths = RouteWidths::new(&self.tech_lef, &self.def, self.chip.dbu() as u32)?;
+        let (routes, problems) = route_elements(&self.def, &widths, vias, &layer_of);
+        for problem in problems {
+            println!("Routing: {}", problem);
+        }
+        let (objects, missing) = def_object_elements(&self.def, &layer_of)?;
+        let outline_layer = die_area_layer(self.gds_options.outline_layer, &layer_of);
+        let die_area = die_area_elements(&self.def, outline_layer);
+        if !missing.is_empty() {
+            let missing: Vec<String> = missing.into_iter().collect();
+            println!("No GDS layer for {}, skipping these DEF objects", missing.join(", "));
+        }
+        let dbu = self.chip.dbu() as u32;
+        let (grids, problems) = grid_elements(&self.tech_lef, &self.def, dbu, &self.gds_options.grids, &layer_of);
+        for problem in problems {
+            println!("Grids: {}", problem);
+        }
+        Ok(TopCellContent {
+            routes,
+            objects,
+            die_area,
+            grids,
+        })
+    }
+}
+
+
+/// Keep the cells reachable from `top` and from those of `macros` in the library, and drop the
+/// cells matching `exclude`.
+///
+/// Excluded cells are not followed, so cells placed only through them are dropped as well.
+/// Their references stay, to be resolved by a library merged in later. The top cell is never
+/// excluded.
+fn select_library_cells(
+    lib: &mut GdsLibrary,
+    top: &str,
+    macros: &[&str],
+    exclude: Option<&RegexSet>,
+) -> Result<(), String> {
+    let names: HashSet<&str> = lib.structs.iter().map(|s| s.name.as_str()).collect();
+    let mut tops = vec![top];
+    tops.extend(macros.iter().copied().filter(|name| names.contains(name)));
+
+    let mut excluded: HashSet<String> = match exclude {
+        Some(re) => lib
+            .structs
+            .iter()
+            .filter(|s| re.is_match(&s.name))
+            .map(|s| s.name.clone())
+            .collect(),
+        None => HashSet::new(),
+    };
+    if excluded.remove(top) {
+        println!("Not excluding the top cell {}", top);
+    }
+    // Emptied, the excluded cells end the search for reachable cells.
+    for gds_struct in &mut lib.structs {
+        if excluded.contains(&gds_struct.name) {
+            gds_struct.elems.clear();
+        }
+    }
+    prune_library(lib, &tops)?;
+    lib.structs.retain(|s| !excluded.contains(&s.name));
+    Ok(())
+}
+
+/// Database units per micron when neither the DEF nor the LEF give any.
+pub const DEFAULT_DBU: u32 = 1000;
+
+/// GDS UNITS for `dbu` database units per micron and a user unit in microns.
+pub fn gds_units(dbu: u32, user_unit: Option<f64>) -> GdsUnits {
+    let db_unit_in_meters = 1e-6 / dbu as f64;
+    let user_unit_in_meters = 1e-6 * user_unit.unwrap_or(1.0);
+    GdsUnits::new(db_unit_in_meters / user_unit_in_meters, db_unit_in_meters)
+}
+
+/// A layout coordinate as GDS coordinate, an error instead of wrapping if it does not fit.
+pub fn gds_coord(value: i64) -> Result<i32, String> {
+    i32::try_from(value).map_err(|_| format!("coordinate {} does not fit into a GDS coordinate", value))
+}
+
+/// A LEF length in microns as GDS coordinate in `dbu` database units, rounded.
+pub fn micron_coord(microns: f64, dbu: u32) -> Result<i32, String> {
+    let value = (microns * dbu as f64).round();
+    if value >= i32::MIN as f64 && value <= i32::MAX as f64 {
+        Ok(value as i32)
+    } else {
+        Err(format!("{} microns do not fit into a GDS coordinate", microns))
+    }
+}
+
+/// A layout point as `GdsPoint`, see [`gds_coord`].
+fn gds_point<C: Into<i64>>(x: C, y: C) -> Result<GdsPoint, String> {
+    Ok(GdsPoint {
+        x: gds_coord(x.into())?,
+        y: gds_coord(y.into())?,
+    })
 }
 
+/// DEF content written into the top cell next to the placed components.
+#[derive(Clone, Debug, Default)]
+pub struct TopCellContent {
+    /// Wires, vias and shapes of the nets, replacing the net shapes of the chip.
+    pub routes: Vec<GdsElement>,
+    /// Pins, blockages and regions.
+    pub objects: Vec<GdsElement>,
+    /// The DIEAREA outline, the OUTLINE shapes of the chip are used when empty.
+    pub die_area: Vec<GdsElement>,
+    /// Rows, tracks and gcell grid lines.
+    pub grids: Vec<GdsElement>,
+}
 
 /// Convert a `libreda_db::chip::Chip` to a `GdsLibrary`.
 ///
+/// The top cell holds the placed components and the DEF content, other shapes of the chip
+/// top cell are not written as they are imported from the same DEF. Every other cell of the
+/// chip is written, whether placed or not.
+///
 /// # Arguments
 /// * `chip` - The chip layout to convert.
 /// * `top`  - The top level cell id.
+/// * `units` - The GDS units matching the database units of the chip.
+/// * `vias` - Via structs replacing the chip cells of the same name.
+/// * `content` - Routing, pins and other DEF content of the top cell.
 ///
 /// # Returns
-/// A `GdsLibrary` representing the chip.
-pub fn chip_to_gds_library<C: L2NBase>(chip: &C, top: C::CellId) -> GdsLibrary
+/// A `GdsLibrary` representing the chip, or an error for coordinates that do not fit into
+/// GDS coordinates.
+pub fn chip_to_gds_library<C: L2NBase>(
+    chip: &C,
+    top: C::CellId,
+    units: GdsUnits,
+    vias: Vec<GdsStruct>,
+    content: TopCellContent,
+) -> Result<GdsLibrary, String>
     where
-        C::Coord: Into<i32>,
+        C::Coord: Into<i64>,
 {
     let design_name = chip.cell_name(&top);
     let mut gds_library = GdsLibrary {
         name: design_name.to_string(),
         version: 5,
         dates: GdsDateTimes::default(),
-        units: GdsUnits::default(),
+        units,
         structs: vec![],
         ..Default::default()
     };
@@ -251,65 +648,45 @@ pub fn chip_to_gds_library<C: L2NBase>(chip: &C, top: C::CellId) -> GdsLibrary
         elems: vec![],
     };
 
-    let boundary_layer = chip.layer_by_name("OUTLINE").unwrap();
-    let boundary_shapes = chip.each_shape_id(&top, &boundary_layer).count();
-    assert_eq!(boundary_shapes, 1);
-    // boundary_shapes .for_each(|s| {
-    //     let layer_info = chip.layer_info(&chip.shape_layer(&s));
-    //     top_cell_struct.elems.push(
-    //         shape_to_gds_element(
-    //             &chip.shape_geometry(&s),
-    //             layer_info.index as i16,
-    //             vec![]
-    //         )
-    //     )
-    // });
-
-    chip.each_shape_id(&top, &boundary_layer)
-        .for_each(|s: C::ShapeId| {
-            let layer_info = chip.layer_info(&chip.shape_layer(&s));
-            top_cell_struct.elems.push(
-                shape_to_gds_element(
-                    &chip.shape_geometry(&s),
-                    layer_info.index as i16,
-                    vec![]
-                )
-            )
-        });
+    if content.die_area.is_empty() {
+        if let Some(boundary_layer) = chip.layer_by_name("OUTLINE") {
+            for s in chip.each_shape_id(&top, &boundary_layer) {
+                let layer_info = chip.layer_info(&chip.shape_layer(&s));
+                top_cell_struct.elems.extend(
+                    shape_to_gds_elements(
+                        &chip.shape_geometry(&s),
+                        layer_info.index as i16,
+                        vec![]
+                    )?
+                );
+            }
+        }
+    }
+    top_cell_struct.elems.extend(content.die_area);
 
+    let via_names: HashSet<String> = vias.iter().map(|v| v.name.clone()).collect();
     for cell in chip.each_cell() {
-        if chip.num_cell_dependencies(&cell) != 0 || chip.num_dependent_cells(&cell) != 0 && cell != top {
-            gds_library.structs.push(chip_cell_to_gds_struct(chip, &cell));
+        if cell == top || via_names.contains(&chip.cell_name(&cell).to_string()) {
+            continue;
         }
+        gds_library.structs.push(chip_cell_to_gds_struct(chip, &cell, &via_names)?);
     }
+    gds_library.structs.extend(vias);
 
-    chip_cell_to_gds_struct(chip, &top);
-    chip.each_internal_net(&top)
-        .filter(|n| !chip.is_constant_net(&n))
-        .for_each(|n: C::NetId| {
-            chip.shapes_of_net(&n)
-                .for_each(|s| {
-                    let layer_info = chip.layer_info(&chip.shape_layer(&s));
-                    top_cell_struct.elems.push(
-                        shape_to_gds_element(
-                            &chip.shape_geometry(&s),
-                            layer_info.index as i16,
-                            // vec!(
-                            //     GdsProperty{
-                            //         attr: 1,
-                            //         value: chip.get_chip_property(
-                            //
-                            //         )
-                            //     }
-                            // )
-                            vec![]
-                        )
-                    )
-                })
-        });
+    // Only the components, vias are placed by the routing.
+    for inst in chip.each_cell_instance(&top) {
+        if !via_names.contains(&chip.cell_name(&chip.template_cell(&inst)).to_string()) {
+            top_cell_struct.elems.push(GdsElement::GdsStructRef(
+                cell_instance_to_gds_ref(chip, &inst, &via_names)?
+            ));
+        }
+    }
+    top_cell_struct.elems.extend(content.routes);
+    top_cell_struct.elems.extend(content.objects);
+    top_cell_struct.elems.extend(content.grids);
+    gds_library.structs.push(top_cell_struct);
 
-    // nets.eac
-    gds_library
+    Ok(gds_library)
 }
 
 
@@ -318,12 +695,13 @@ pub fn chip_to_gds_library<C: L2NBase>(chip: &C, top: C::CellId) -> GdsLibrary
 /// # Arguments
 /// * `chip` - The chip containing the cell.
 /// * `cell` - The cell to convert.
+/// * `vias` - Names of via cells, placed at their origin instead of their lower left corner.
 ///
 /// # Returns
 /// A `GdsStruct` representing the cell.
-pub fn chip_cell_to_gds_struct<C: LayoutBase>(layout: &C, cell: &C::CellId) -> GdsStruct
+pub fn chip_cell_to_gds_struct<C: LayoutBase>(layout: &C, cell: &C::CellId, vias: &HashSet<String>) -> Result<GdsStruct, String>
     where
-        C::Coord: Into<i32>
+        C::Coord: Into<i64>
 {
     let cell_name = {
         let it = layout.cell_name(cell).into();
@@ -342,20 +720,44 @@ pub fn chip_cell_to_gds_struct<C: LayoutBase>(layout: &C, cell: &C::CellId) -> G
 
     for layer in layout.each_layer() {
         let layer_info = layout.layer_info(&layer);
-        layout.for_each_shape(cell, &layer, |_, shape| {
-            gds_struct.elems.push(shape_to_gds_element(shape, layer_info.index as i16, vec![]));
-        });
+        for shape in layout.each_shape_id(cell, &layer) {
+            gds_struct.elems.extend(shape_to_gds_elements(&layout.shape_geometry(&shape), layer_info.index as i16, vec![])?);
+        }
     }
 
-    layout.for_each_cell_instance(&cell, |inst| {
+    for inst in layout.each_cell_instance(&cell) {
         // Write PLACEMENT records.
-        let placement_cell = layout.template_cell(&inst);
+        gds_struct.elems.push(GdsElement::GdsStructRef(cell_instance_to_gds_ref(layout, &inst, vias)?))
+    }
+
+    Ok(gds_struct)
+}
 
-        let tf = layout.get_transform(&inst);
-        let (new_x, new_y, new_rotation, should_flip) = layout.bounding_box(&placement_cell)
+/// Convert a cell instance to a `GdsStructRef` carrying the instance name.
+///
+/// # Arguments
+/// * `layout` - The layout containing the instance.
+/// * `inst` - The instance to convert.
+/// * `vias` - Names of via cells, placed at their origin instead of their lower left corner.
+///
+/// # Returns
+/// A `GdsStructRef` placing the template cell of the instance.
+pub fn cell_instance_to_gds_ref<C: LayoutBase>(layout: &C, inst: &C::CellInstId, vias: &HashSet<String>) -> Result<GdsStructRef, String>
+    where
+        C::Coord: Into<i64>
+{
+    let placement_cell = layout.template_cell(inst);
+
+    let tf = layout.get_transform(inst);
+    let is_via = vias.contains(&layout.cell_name(&placement_cell).to_string());
+    let (new_x, new_y, new_rotation, should_flip): (i64, i64, Angle, bool) = if is_via {
+        // Via shapes are drawn around the origin, which is the via location in the DEF.
+        (tf.displacement.x.into(), tf.displacement.y.into(), tf.rotation, tf.mirror)
+    } else {
+        layout.bounding_box(&placement_cell)
             .map(|bbox| {
-                let width: i32 = (bbox.upper_right().x - bbox.lower_left().x).into();
-                let height: i32 = (bbox.upper_right().y - bbox.lower_left().y).into();
+                let width: i64 = bbox.upper_right().x.into() - bbox.lower_left().x.into();
+                let height: i64 = bbox.upper_right().y.into() - bbox.lower_left().y.into();
 
                 // Calculate new origin based on rotation
                 let (tf_x, tf_y, new_rotation, should_flip) = match (tf.mirror, tf.rotation) {
@@ -373,76 +775,111 @@ pub fn chip_cell_to_gds_struct<C: LayoutBase>(layout: &C, cell: &C::CellId) -> G
                 let new_x = tf.displacement.x.into() + tf_x;
                 let new_y = tf.displacement.y.into() + tf_y;
                 (new_x, new_y, new_rotation, should_flip)
-            }).unwrap();
-
-        gds_struct.elems.push(GdsElement::GdsStructRef(
-            GdsStructRef {
-                name: layout.cell_name(&placement_cell).to_string(),
-                xy: GdsPoint { x: new_x.into(), y: new_y.into() },
-                strans: GdsStrans {
-                    reflected: should_flip,
-                    abs_mag: true,
-                    mag: Some(tf.magnification.into() as f64),
-                    abs_angle: false,
-                    angle: Some(90.0 * (new_rotation.as_int() as f64)),
-                }.into(),
-                elflags: None,
-                plex: None,
-                properties: vec!(
-                    GdsProperty {
-                        attr: 1,
-                        value: layout
-                            .cell_instance_name(&inst)
-                            .map(|n| n.into())
-                            .unwrap_or_else(|| layout.cell_name(&placement_cell).to_string()),
-                    }
-                ),
-            }
-        ))
-    });
+            })
+            .ok_or_else(|| format!("cell {} has no bounding box to place", layout.cell_name(&placement_cell)))?
+    };
+    let instance_name = layout
+        .cell_instance_name(inst)
+        .map(|n| n.into())
+        .unwrap_or_else(|| layout.cell_name(&placement_cell).to_string());
 
-    gds_struct
+    Ok(GdsStructRef {
+        name: layout.cell_name(&placement_cell).to_string(),
+        xy: gds_point(new_x, new_y).map_err(|e| format!("instance {}: {}", instance_name, e))?,
+        strans: GdsStrans {
+            reflected: should_flip,
+            abs_mag: true,
+            mag: Some(tf.magnification.into() as f64),
+            abs_angle: false,
+            angle: Some(90.0 * (new_rotation.as_int() as f64)),
+        }.into(),
+        elflags: None,
+        plex: None,
+        properties: vec!(
+            GdsProperty {
+                attr: 1,
+                value: instance_name,
+            }
+        ),
+    })
 }
 
-/// Convert a `Geometry` shape to a `GdsBoundary`.
+/// Convert a `Geometry` shape to GDS boundaries or a path.
+///
+/// Polygons with holes are cut into several hole-free boundaries, as GDS boundaries cannot
+/// have holes.
 ///
 /// # Arguments
 /// * `shape` - The shape to convert.
 /// * `layer_index` - The layer index to assign to the shape.
 ///
 /// # Returns
-/// A `GdsBoundary` representing the shape.
-pub fn shape_to_gds_element<C>(shape: &Geometry<C>, layer_index: i16, properties: Vec<GdsProperty>) -> GdsElement
+/// The GDS elements drawing the shape, or an error for coordinates that do not fit into GDS
+/// coordinates, holes too small to be cut open and unsupported shapes.
+pub fn shape_to_gds_elements<C>(shape: &Geometry<C>, layer_index: i16, properties: Vec<GdsProperty>) -> Result<Vec<GdsElement>, String>
     where
-        C: CoordConstraints + Into<i32> + Copy,
+        C: CoordConstraints + Into<i64> + Copy,
 {
-    match shape {
-        Geometry::SimplePolygon(poly) => GdsElement::GdsBoundary(
-            polygon_to_gds_element(poly, layer_index, 0i16, properties)
-        ),
-        Geometry::SimpleRPolygon(rpoly) => GdsElement::GdsBoundary(
-            polygon_to_gds_element(&rpoly.to_simple_polygon(), layer_index, 0i16, properties)
-        ),
-        Geometry::Polygon(Polygon { exterior, .. }) => GdsElement::GdsBoundary(
-            polygon_to_gds_element(exterior, layer_index, 0i16, properties)
-        ),
-        Geometry::Rect(rect) => GdsElement::GdsBoundary(
-            rect_to_gds_element(rect, layer_index, 0i16, properties)
-        ),
-        Geometry::Path(path) => GdsElement::GdsPath(
-            path_to_gds_path(path, layer_index, 0i16, properties)
-        ),
-        _ => {
-            dbg!(shape);
-            GdsElement::GdsTextElem(GdsTextElem {
-                string: "UNKNOWN_SHAPE".to_string(),
-                layer: 1,
-                texttype: 0,
-                xy: Default::default(),
-                ..Default::default()
-            })
+    Ok(match shape {
+        Geometry::SimplePolygon(poly) => vec![GdsElement::GdsBoundary(
+            polygon_to_gds_element(poly, layer_index, 0i16, properties)?
+        )],
+        Geometry::SimpleRPolygon(rpoly) => vec![GdsElement::GdsBoundary(
+            polygon_to_gds_element(&rpoly.to_simple_polygon(), layer_index, 0i16, properties)?
+        )],
+        Geometry::Polygon(polygon) if polygon.interiors.is_empty() => vec![GdsElement::GdsBoundary(
+            polygon_to_gds_element(&polygon.exterior, layer_index, 0i16, properties)?
+        )],
+        Geometry::Polygon(polygon) => {
+            let (outlines, warnings) = polygon_to_outlines(&polygon_with_holes(polygon)?);
+            if !warnings.is_empty() {
+                return Err(format!(
+                    "polygon on layer {} cannot be written without changing it: {}",
+                    layer_index,
+                    warnings.join("; ")
+                ));
+            }
+            outlines
+                .iter()
+                .map(|outline| GdsElement::GdsBoundary(GdsBoundary {
+                    layer: layer_index,
+                    datatype: 0,
+                    xy: closed_points(outline),
+                    properties: properties.clone(),
+                    ..Default::default()
+                }))
+                .collect()
         }
-    }
+        Geometry::Rect(rect) => vec![GdsElement::GdsBoundary(
+            rect_to_gds_element(rect, layer_index, 0i16, properties)?
+        )],
+        Geometry::Path(path) => vec![GdsElement::GdsPath(
+            path_to_gds_path(path, layer_index, 0i16, properties)?
+        )],
+        _ => return Err(format!("unsupported shape on layer {}: {:?}", layer_index, shape)),
+    })
+}
+
+/// A polygon with holes in `i64` coordinates, checking that every point is a GDS coordinate.
+fn polygon_with_holes<C>(polygon: &Polygon<C>) -> Result<Polygon<i64>, String>
+    where
+        C: CoordConstraints + Into<i64> + Copy,
+{
+    let simple = |poly: &SimplePolygon<C>| -> Result<SimplePolygon<i64>, String> {
+        let points = poly
+            .points()
+            .iter()
+            .map(|p| {
+                let point = gds_point(p.x, p.y)?;
+                Ok(Point::new(point.x as i64, point.y as i64))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        Ok(SimplePolygon::new(points))
+    };
+    Ok(Polygon {
+        exterior: simple(&polygon.exterior)?,
+        interiors: polygon.interiors.iter().map(simple).collect::<Result<_, _>>()?,
+    })
 }
 
 /// Convert a `SimplePolygon` to a list of `gds21::GdsPoint`.
@@ -452,30 +889,28 @@ pub fn shape_to_gds_element<C>(shape: &Geometry<C>, layer_index: i16, properties
 ///
 /// # Returns
 /// A `GdsBoundary` representing the polygon.
-pub fn polygon_to_gds_element<C>(poly: &SimplePolygon<C>, layer_index: i16, data_type: i16, properties: Vec<GdsProperty>) -> GdsBoundary
+pub fn polygon_to_gds_element<C>(poly: &SimplePolygon<C>, layer_index: i16, data_type: i16, properties: Vec<GdsProperty>) -> Result<GdsBoundary, String>
     where
-        C: Into<i32> + Copy,
+        C: Into<i64> + Copy,
 {
     let mut points: Vec<GdsPoint> = poly
         .points()
         .iter()
-        .map(|p| GdsPoint {
-            x: p.x.into(),
-            y: p.y.into(),
-        })
-        .collect();
+        .map(|p| gds_point(p.x, p.y))
+        .collect::<Result<_, _>>()?;
 
     // Close the polygon by adding the first point again.
     if let Some(first) = points.first() {
         points.push(first.clone());
     }
 
-    GdsBoundary {
+    Ok(GdsBoundary {
         layer: layer_index,
         datatype: data_type,
         xy: points,
+        properties,
         ..Default::default()
-    }
+    })
 }
 
 /// Convert a `Rect` to a list of `gds21::GdsPoint`.
@@ -485,22 +920,23 @@ pub fn polygon_to_gds_element<C>(poly: &SimplePolygon<C>, layer_index: i16, data
 ///
 /// # Returns
 /// A `GdsBoundary` representing the rectangle.
-pub fn rect_to_gds_element<C>(rect: &Rect<C>, layer_index: i16, data_type: i16, properties: Vec<GdsProperty>) -> GdsBoundary
+pub fn rect_to_gds_element<C>(rect: &Rect<C>, layer_index: i16, data_type: i16, properties: Vec<GdsProperty>) -> Result<GdsBoundary, String>
     where
-        C: Into<i32> + Copy,
+        C: Into<i64> + Copy,
 {
-    GdsBoundary {
+    Ok(GdsBoundary {
         layer: layer_index,
         datatype: data_type,
         xy: vec![
-            GdsPoint { x: rect.lower_left().x.into(), y: rect.lower_left().y.into() },
-            GdsPoint { x: rect.lower_right().x.into(), y: rect.lower_right().y.into() },
-            GdsPoint { x: rect.upper_right().x.into(), y: rect.upper_right().y.into() },
-            GdsPoint { x: rect.upper_left().x.into(), y: rect.upper_left().y.into() },
-            GdsPoint { x: rect.lower_left().x.into(), y: rect.lower_left().y.into() },
+            gds_point(rect.lower_left().x, rect.lower_left().y)?,
+            gds_point(rect.lower_right().x, rect.lower_right().y)?,
+            gds_point(rect.upper_right().x, rect.upper_right().y)?,
+            gds_point(rect.upper_left().x, rect.upper_left().y)?,
+            gds_point(rect.lower_left().x, rect.lower_left().y)?,
         ],
+        properties,
         ..Default::default()
-    }
+    })
 }
 
 /// Convert a `Path` to a `GdsPath`.
@@ -512,14 +948,15 @@ pub fn rect_to_gds_element<C>(rect: &Rect<C>, layer_index: i16, data_type: i16,
 ///
 /// # Returns
 /// A `GdsPath` representing the path with specified attributes.
-pub fn path_to_gds_path<C>(path: &Path<C>, layer_index: i16, datatype: i16, properties: Vec<GdsProperty>) -> GdsPath
+pub fn path_to_gds_path<C>(path: &Path<C>, layer_index: i16, datatype: i16, properties: Vec<GdsProperty>) -> Result<GdsPath, String>
     where
-        C: Into<i32> + Copy,
+        C: Into<i64> + Copy,
 {
-    let points: Vec<GdsPoint> = path.points.iter().map(|p| GdsPoint {
-        x: p.x.into(),
-        y: p.y.into(),
-    }).collect();
+    let points: Vec<GdsPoint> = path.points
+        .iter()
+        .map(|p| gds_point(p.x, p.y))
+        .collect::<Result<_, _>>()?;
+    let width = gds_coord(path.width.into())?;
 
     //   Type of path endpoints (int, optional). The values have the following meaning:
     //       0 – square ends, flush with endpoints
@@ -528,19 +965,149 @@ pub fn path_to_gds_path<C>(path: &Path<C>, layer_index: i16, datatype: i16, prop
     //       4 – custom square ends
     let (path_type, begin_extn, end_extn) = match path.path_type {
         PathEndType::Flat => (Some(0), None, None),
-        PathEndType::Extended(b, e) => (Some(2), Some(b.into()), Some(e.into())),
+        PathEndType::Extended(b, e) => gds_path_ends(width, gds_coord(b.into())?, gds_coord(e.into())?),
         PathEndType::Round => (Some(1), None, None)
     };
 
-    GdsPath {
+    Ok(GdsPath {
         layer: layer_index,
         datatype: datatype,
         xy: points,
-        width: Some(path.width.into()),
+        width: Some(width),
         path_type: path_type,
         begin_extn: begin_extn,
         end_extn: end_extn,
         properties: properties,
         ..Default::default()
+    })
+}
+
+/// GDS path type and extensions for square ends extended by `begin` and `end`.
+///
+/// Flush ends are written as type 0 and half-width extensions as type 2, anything else as
+/// type 4 with explicit extensions.
+pub fn gds_path_ends(width: i32, begin: i32, end: i32) -> (Option<i16>, Option<i32>, Option<i32>) {
+    if begin == 0 && end == 0 {
+        (Some(0), None, None)
+    } else if 2 * begin == width && 2 * end == width {
+        (Some(2), None, None)
+    } else {
+        (Some(4), Some(begin), Some(end))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::commands::geometry::area_doubled;
+    use crate::commands::test_support::{cell_names, library, parent};
+
+    fn simple(points: &[(i32, i32)]) -> SimplePolygon<i32> {
+        SimplePolygon::new(points.iter().map(|&(x, y)| Point::new(x, y)).collect())
+    }
+
+    fn boundaries(elements: &[GdsElement]) -> Vec<&GdsBoundary> {
+        elements
+            .iter()
+            .map(|e| match e {
+                GdsElement::GdsBoundary(b) => b,
+                _ => panic!("expected boundaries"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn polygons_with_holes_are_cut_open() {
+        let ring = Geometry::Polygon(Polygon {
+            exterior: simple(&[(0, 0), (30, 0), (30, 30), (0, 30)]),
+            interiors: vec![simple(&[(10, 10), (20, 10), (20, 20), (10, 20)])],
+        });
+        let properties = vec![GdsProperty { attr: 1, value: "n".to_string() }];
+        let elements = shape_to_gds_elements(&ring, 5, properties).unwrap();
+        let boundaries = boundaries(&elements);
+        assert!(boundaries.len() >= 2);
+        let area: i64 = boundaries.iter().map(|b| area_doubled(&b.xy).abs()).sum();
+        assert_eq!(area / 2, 800);
+        assert!(boundaries.iter().all(|b| b.layer == 5 && b.properties.len() == 1));
+    }
+
+    #[test]
+    fn holes_too_small_to_cut_are_an_error() {
+        let pinhole = Geometry::Polygon(Polygon {
+            exterior: simple(&[(0, 0), (30, 0), (30, 30), (0, 30)]),
+            interiors: vec![simple(&[(10, 10), (11, 10), (11, 11), (10, 11)])],
+        });
+        assert!(shape_to_gds_elements(&pinhole, 5, vec![]).is_err());
+    }
+
+    #[test]
+    fn unsupported_shapes_are_an_error() {
+        let point: Geometry<i32> = Geometry::Point(Point::new(1, 2));
+        assert!(shape_to_gds_elements(&point, 5, vec![]).is_err());
+    }
+
+    #[test]
+    fn gds_coord_rejects_values_outside_i32() {
+        assert_eq!(gds_coord(-5), Ok(-5));
+        assert!(gds_coord(i32::MAX as i64 + 1).is_err());
+    }
+
+    #[test]
+    fn micron_coord_rounds_and_rejects_overflow() {
+        assert_eq!(micron_coord(0.0125, 2000), Ok(25));
+        assert_eq!(micron_coord(-1.5, 1000), Ok(-1500));
+        assert!(micron_coord(3e6, 1000).is_err());
+        assert!(micron_coord(f64::NAN, 1000).is_err());
+    }
+
+    /// `TOP` places `A` and `B`, `A` places `C`; the macro `M` and `X` are unused.
+    fn design_library() -> GdsLibrary {
+        library(vec![
+            parent("C", &[]),
+            parent("A", &["C"]),
+            parent("B", &[]),
+            parent("M", &[]),
+            parent("X", &[]),
+            parent("TOP", &["A", "B"]),
+        ])
+    }
+
+    #[test]
+    fn selected_cells_are_those_reachable_from_the_top() {
+        let mut lib = design_library();
+        select_library_cells(&mut lib, "TOP", &[], None).unwrap();
+        assert_eq!(cell_names(&lib), ["C", "A", "B", "TOP"]);
+    }
+
+    #[test]
+    fn unused_macros_are_kept_when_given() {
+        let mut lib = design_library();
+        select_library_cells(&mut lib, "TOP", &["M", "NOT_IN_LIBRARY"], None).unwrap();
+        assert_eq!(cell_names(&lib), ["C", "A", "B", "M", "TOP"]);
+    }
+
+    #[test]
+    fn excluded_cells_are_not_followed_and_keep_their_references() {
+        let mut lib = design_library();
+        let exclude = RegexSet::new(["^A$"]).unwrap();
+        select_library_cells(&mut lib, "TOP", &[], Some(&exclude)).unwrap();
+        assert_eq!(cell_names(&lib), ["B", "TOP"]);
+        let top = lib.structs.iter().find(|s| s.name == "TOP").unwrap();
+        assert_eq!(top.elems.len(), 2);
+    }
+
+    #[test]
+    fn the_top_cell_is_never_excluded() {
+        let mut lib = design_library();
+        let exclude = RegexSet::new(["^TOP$", "^B$"]).unwrap();
+        select_library_cells(&mut lib, "TOP", &[], Some(&exclude)).unwrap();
+        assert_eq!(cell_names(&lib), ["C", "A", "TOP"]);
+    }
+
+    #[test]
+    fn a_missing_top_cell_is_an_error() {
+        let mut lib = design_library();
+        assert!(select_library_cells(&mut lib, "MISSING", &[], None).is_err());
     }
 }
@@ -0,0 +1,298 @@
+use std::collections::BTreeMap;
+
+use gds21::{GdsElement, GdsStruct};
+use libreda_lefdef::{DEF, LEF};
+use libreda_lefdef::common::{Shape, ViaGeometry, ViaRuleParams};
+
+use super::def_to_gds::{gds_coord, micron_coord};
+use super::geometry::closed_boundary;
+use super::layer_map::GdsLayer;
+
+/// A rectangle `(x1, y1, x2, y2)` in database units, relative to the via origin.
+pub type ViaRect = (i32, i32, i32, i32);
+
+/// Geometry of a via, rectangles and polygons per LEF layer name.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ViaShapes {
+    pub rects: Vec<(String, ViaRect)>,
+    pub polygons: Vec<(String, Vec<(i32, i32)>)>,
+}
+
+/// Parameters of a via generated from a VIARULE, in database units.
+///
+/// The cut array is centred on the via origin, shifted by `origin`. The metal rectangles
+/// enclose the array and are shifted by their own offsets.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GeneratedVia {
+    /// Bottom routing, cut and top routing layer.
+    pub layers: [String; 3],
+    pub cut_size: (i32, i32),
+    pub cut_spacing: (i32, i32),
+    pub bottom_enclosure: (i32, i32),
+    pub top_enclosure: (i32, i32),
+    pub rows: u32,
+    pub cols: u32,
+    pub origin: (i32, i32),
+    pub bottom_offset: (i32, i32),
+    pub top_offset: (i32, i32),
+}
+
+impl GeneratedVia {
+    /// Convert the VIARULE parameters of a LEF or DEF via, scaling coordinates with `dbu`.
+    pub fn from_params<T: Copy>(
+        params: &ViaRuleParams<T>,
+        dbu: impl Fn(T) -> Result<i32, String>,
+    ) -> Result<Self, String> {
+        let pair = |(x, y): (T, T)| -> Result<(i32, i32), String> { Ok((dbu(x)?, dbu(y)?)) };
+        let (bottom, cut, top) = params.layers.clone();
+        let (rows, cols) = params.rowcol.unwrap_or((1, 1));
+        let (bottom_offset, top_offset) = match params.offset {
+            Some((b, t)) => (pair(b)?, pair(t)?),
+            None => Default::default(),
+        };
+        Ok(Self {
+            layers: [bottom, cut, top],
+            cut_size: pair(params.cut_size)?,
+            cut_spacing: pair(params.cut_spacing)?,
+            bottom_enclosure: pair(params.enclosure.0)?,
+            top_enclosure: pair(params.enclosure.1)?,
+            rows: rows.max(1),
+            cols: cols.max(1),
+            origin: params.origin.map(pair).transpose()?.unwrap_or_default(),
+            bottom_offset,
+            top_offset,
+        })
+    }
+
+    /// The rectangles of the generated via: both metals and every cut of the array.
+    ///
+    /// A cut PATTERN is not applied, all cuts of the array are present.
+    ///
+    /// # Returns
+    /// The shapes, or an error if a rectangle does not fit into GDS coordinates.
+    pub fn shapes(&self) -> Result<ViaShapes, String> {
+        let (cut_w, cut_h) = (self.cut_size.0 as i64, self.cut_size.1 as i64);
+        let (space_x, space_y) = (self.cut_spacing.0 as i64, self.cut_spacing.1 as i64);
+        // Extent of `n` cuts of `size` with `space` between them.
+        let span = |n: u32, size: i64, space: i64| {
+            (n as i64)
+                .checked_mul(size + space)
+                .ok_or_else(|| format!("array of {} cuts does not fit into GDS coordinates", n))
+                .and_then(|v| gds_coord(v - space))
+                .map(i64::from)
+        };
+        let array_w = span(self.cols, cut_w, space_x)?;
+        let array_h = span(self.rows, cut_h, space_y)?;
+        let (x0, y0) = (self.origin.0 as i64 - array_w / 2, self.origin.1 as i64 - array_h / 2);
+        let rect = |x1: i64, y1: i64, x2: i64, y2: i64| -> Result<ViaRect, String> {
+            Ok((gds_coord(x1)?, gds_coord(y1)?, gds_coord(x2)?, gds_coord(y2)?))
+        };
+
+        let metal = |enclosure: (i32, i32), offset: (i32, i32)| {
+            let (ex, ey) = (enclosure.0 as i64, enclosure.1 as i64);
+            let (ox, oy) = (offset.0 as i64, offset.1 as i64);
+            rect(x0 - ex + ox, y0 - ey + oy, x0 + array_w + ex + ox, y0 + array_h + ey + oy)
+        };
+        let [bottom, cut, top] = &self.layers;
+        let mut rects = vec![
+            (bottom.clone(), metal(self.bottom_enclosure, self.bottom_offset)?),
+            (top.clone(), metal(self.top_enclosure, self.top_offset)?),
+        ];
+        for row in 0..self.rows as i64 {
+            for col in 0..self.cols as i64 {
+                let x = x0 + col * (cut_w + space_x);
+                let y = y0 + row * (cut_h + space_y);
+                rects.push((cut.clone(), rect(x, y, x + cut_w, y + cut_h)?));
+            }
+        }
+        Ok(ViaShapes { rects, polygons: vec![] })
+    }
+}
+
+/// The shapes of every via defined in the LEF and in the VIAS section of the DEF.
+///
+/// LEF coordinates are in microns and scaled by `dbu`, DEF coordinates are taken as they
+/// are. A DEF via replaces a LEF via of the same name.
+///
+/// # Returns
+/// The via shapes by name, or an error naming a LEF via that does not fit into GDS coordinates.
+pub fn via_definitions(lef: &LEF, def: &DEF, dbu: u32) -> Result<BTreeMap<String, ViaShapes>, String> {
+    let microns = |v: f64| micron_coord(v, dbu);
+    let mut vias = BTreeMap::new();
+    for (name, via) in &lef.technology.vias {
+        let shapes = via_shapes(&via.geometry, microns).map_err(|e| format!("via {}: {}", name, e))?;
+        vias.insert(name.clone(), shapes);
+    }
+    for (name, via) in &def.vias {
+        let shapes = via_shapes(&via.geometry, |v: i32| Ok(v)).map_err(|e| format!("via {}: {}", name, e))?;
+        vias.insert(name.clone(), shapes);
+    }
+    Ok(vias)
+}
+
+/// Shapes of a fixed or generated via geometry.
+fn via_shapes<T: Copy>(
+    geometry: &ViaGeometry<T>,
+    dbu: impl Fn(T) -> Result<i32, String> + Copy,
+) -> Result<ViaShapes, String> {
+    match geometry {
+        ViaGeometry::Generated(params) => GeneratedVia::from_params(params, dbu)?.shapes(),
+        ViaGeometry::Fixed(layers) => {
+            let mut shapes = ViaShapes::default();
+            for (layer, layer_shapes) in layers {
+                for shape in layer_shapes {
+                    match shape {
+                        Shape::Rect(p1, p2) => {
+                            let (x1, y1, x2, y2) = (dbu(p1.0)?, dbu(p1.1)?, dbu(p2.0)?, dbu(p2.1)?);
+                            shapes
+                                .rects
+                                .push((layer.clone(), (x1.min(x2), y1.min(y2), x1.max(x2), y1.max(y2))));
+                        }
+                        Shape::Polygon(points) => shapes.polygons.push((
+                            layer.clone(),
+                            points
+                                .iter()
+                                .map(|p| Ok((dbu(p.0)?, dbu(p.1)?)))
+                                .collect::<Result<_, String>>()?,
+                        )),
+                    }
+                }
+            }
+            Ok(shapes)
+        }
+    }
+}
+
+/// A GDS struct holding the shapes of a via.
+///
+/// Shapes on layers `layer_of` does not know are skipped and their layer names returned.
+/// Polygons with fewer than three points cover no area and are skipped as well.
+pub fn via_struct(
+    name: &str,
+    shapes: &ViaShapes,
+    layer_of: impl Fn(&str) -> Option<GdsLayer>,
+) -> (GdsStruct, Vec<String>) {
+    let mut missing = vec![];
+    let mut elems = vec![];
+    let outlines = shapes
+        .rects
+        .iter()
+        .map(|(layer, &(x1, y1, x2, y2))| (layer, vec![(x1, y1), (x2, y1), (x2, y2), (x1, y2)]))
+        .chain(shapes.polygons.iter().map(|(layer, points)| (layer, points.clone())));
+    for (layer, points) in outlines {
+        let Some(gds_layer) = layer_of(layer) else {
+            if !missing.contains(layer) {
+                missing.push(layer.clone());
+            }
+            continue;
+        };
+        elems.extend(closed_boundary(gds_layer, &points, vec![]));
+    }
+    let gds_struct = GdsStruct {
+        name: name.to_string(),
+        dates: Default::default(),
+        elems,
+    };
+    (gds_struct, missing)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 2 by 3 array of 10 by 20 cuts spaced 5 by 6, enclosed by 1 by 2 and 3 by 4.
+    fn via() -> GeneratedVia {
+        GeneratedVia {
+            layers: ["M1".to_string(), "V1".to_string(), "M2".to_string()],
+            cut_size: (10, 20),
+            cut_spacing: (5, 6),
+            bottom_enclosure: (1, 2),
+            top_enclosure: (3, 4),
+            rows: 2,
+            cols: 3,
+            ..Default::default()
+        }
+    }
+
+    fn rects_on<'a>(shapes: &'a ViaShapes, layer: &str) -> Vec<&'a ViaRect> {
+        shapes.rects.iter().filter(|(l, _)| l == layer).map(|(_, r)| r).collect()
+    }
+
+    #[test]
+    fn cut_array_is_centred_on_the_origin() {
+        // The array is 3 * 10 + 2 * 5 = 40 wide and 2 * 20 + 6 = 46 high.
+        let shapes = via().shapes().unwrap();
+        assert_eq!(rects_on(&shapes, "M1"), [&(-21, -25, 21, 25)]);
+        assert_eq!(rects_on(&shapes, "M2"), [&(-23, -27, 23, 27)]);
+        assert_eq!(
+            rects_on(&shapes, "V1"),
+            [
+                &(-20, -23, -10, -3),
+                &(-5, -23, 5, -3),
+                &(10, -23, 20, -3),
+                &(-20, 3, -10, 23),
+                &(-5, 3, 5, 23),
+                &(10, 3, 20, 23),
+            ]
+        );
+    }
+
+    #[test]
+    fn origin_moves_everything_and_offsets_move_one_metal() {
+        let shapes = GeneratedVia {
+            origin: (100, 200),
+            bottom_offset: (7, 0),
+            top_offset: (0, -8),
+            ..via()
+        }
+        .shapes()
+        .unwrap();
+        assert_eq!(rects_on(&shapes, "M1"), [&(86, 175, 128, 225)]);
+        assert_eq!(rects_on(&shapes, "M2"), [&(77, 165, 123, 219)]);
+        assert_eq!(rects_on(&shapes, "V1")[0], &(80, 177, 90, 197));
+    }
+
+    #[test]
+    fn single_cut_without_rowcol() {
+        let shapes = GeneratedVia {
+            rows: 1,
+            cols: 1,
+            ..via()
+        }
+        .shapes()
+        .unwrap();
+        assert_eq!(rects_on(&shapes, "V1"), [&(-5, -10, 5, 10)]);
+    }
+
+    #[test]
+    fn arrays_beyond_gds_coordinates_are_errors() {
+        let huge = GeneratedVia {
+            cut_size: (i32::MAX, 20),
+            ..via()
+        };
+        assert!(huge.shapes().is_err());
+        let far = GeneratedVia {
+            origin: (i32::MAX - 10, 0),
+            ..via()
+        };
+        assert!(far.shapes().is_err());
+    }
+
+    #[test]
+    fn via_structs_skip_unknown_layers_and_degenerate_polygons() {
+        let shapes = ViaShapes {
+            rects: vec![("M1".to_string(), (0, 0, 10, 10)), ("POLY".to_string(), (0, 0, 1, 1))],
+            polygons: vec![("M1".to_string(), vec![]), ("M1".to_string(), vec![(0, 0), (5, 5)])],
+        };
+        let (via, missing) = via_struct("v", &shapes, |layer| (layer == "M1").then_some((31, 0)));
+        assert_eq!(missing, ["POLY"]);
+        assert_eq!(via.elems.len(), 1);
+        match &via.elems[0] {
+            GdsElement::GdsBoundary(b) => {
+                assert_eq!((b.layer, b.xy.len()), (31, 5));
+                assert_eq!(b.xy.first(), b.xy.last());
+            }
+            _ => panic!("expected a boundary"),
+        }
+    }
+}
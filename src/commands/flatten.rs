@@ -0,0 +1,273 @@
+use std::collections::{BTreeMap, HashMap};
+
+use gds21::{
+    GdsArrayRef, GdsBoundary, GdsBox, GdsElement, GdsLibrary, GdsNode, GdsPath, GdsPoint,
+    GdsStruct, GdsStructRef, GdsTextElem,
+};
+use regex::RegexSet;
+
+use super::geometry::{element_polygons, polygon_layer, GdsTransform};
+use super::hierarchy::{reference_transforms, structs_by_name};
+
+/// Polygons per layer/datatype, as open point lists.
+pub type LayerPolygons = BTreeMap<(i16, i16), Vec<Vec<GdsPoint>>>;
+
+/// Collect every polygon below `top`, transformed into the coordinate system of `top`.
+///
+/// Boundaries, boxes and path outlines of all cells placed directly or indirectly by `top`
+/// are included, AREFs contributing once per array member.
+pub fn flat_polygons(lib: &GdsLibrary, top: &str) -> Result<LayerPolygons, String> {
+    fn walk<'a>(
+        gds_struct: &'a GdsStruct,
+        tf: &GdsTransform,
+        by_name: &HashMap<&'a str, &'a GdsStruct>,
+        path: &mut Vec<&'a str>,
+        out: &mut LayerPolygons,
+    ) -> Result<(), String> {
+        for element in &gds_struct.elems {
+            match element {
+                GdsElement::GdsStructRef(r) => {
+                    walk_reference(&r.name, element, tf, by_name, path, out)?
+                }
+                GdsElement::GdsArrayRef(r) => {
+                    walk_reference(&r.name, element, tf, by_name, path, out)?
+                }
+                _ => {
+                    let Some(key) = polygon_layer(element) else {
+                        continue;
+                    };
+                    let polygons = out.entry(key).or_default();
+                    for polygon in element_polygons(element) {
+                        polygons.push(polygon.iter().map(|p| tf.apply_point(p)).collect());
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn walk_reference<'a>(
+        name: &'a str,
+        element: &GdsElement,
+        tf: &GdsTransform,
+        by_name: &HashMap<&'a str, &'a GdsStruct>,
+        path: &mut Vec<&'a str>,
+        out: &mut LayerPolygons,
+    ) -> Result<(), String> {
+        let child: &'a GdsStruct = *by_name
+            .get(name)
+            .ok_or_else(|| format!("cell {} is not defined in the library", name))?;
+        if path.contains(&name) {
+            return Err(format!("reference cycle through cell {}", name));
+        }
+        path.push(name);
+        for child_tf in reference_transforms(element) {
+            walk(child, &child_tf.then(tf), by_name, path, out)?;
+        }
+        path.pop();
+        Ok(())
+    }
+
+    let by_name = structs_by_name(lib);
+    let top_struct: &GdsStruct = *by_name
+        .get(top)
+        .ok_or_else(|| format!("cell {} is not defined in the library", top))?;
+    let mut out = LayerPolygons::new();
+    let mut path = vec![top_struct.name.as_str()];
+    walk(top_struct, &GdsTransform::identity(), &by_name, &mut path, &mut out)?;
+    Ok(out)
+}
+
+/// Flatten the references of a cell into its own geometry.
+///
+/// # Arguments
+/// * `lib` - The library holding the cell, modified in place
+/// * `top` - Name of the cell to flatten
+/// * `levels` - Number of hierarchy levels to dissolve, all of them if `None`
+/// * `re` - Only references to cells matching one of these patterns are dissolved
+///
+/// References left in place are re-expressed in the coordinate system of `top`. The cells
+/// placed by `top` stay in the library, even if nothing references them anymore.
+pub fn flatten_cell(
+    lib: &mut GdsLibrary,
+    top: &str,
+    levels: Option<i32>,
+    re: &RegexSet,
+) -> Result<(), String> {
+    fn flatten_into<'a>(
+        elems: &'a [GdsElement],
+        tf: &GdsTransform,
+        depth: i32,
+        levels: Option<i32>,
+        re: &RegexSet,
+        by_name: &HashMap<&'a str, &'a GdsStruct>,
+        path: &mut Vec<&'a str>,
+        out: &mut Vec<GdsElement>,
+    ) -> Result<(), String> {
+        for element in elems {
+            let name = match element {
+                GdsElement::GdsStructRef(GdsStructRef { name, .. }) => Some(name.as_str()),
+                GdsElement::GdsArrayRef(GdsArrayRef { name, .. }) => Some(name.as_str()),
+                _ => None,
+            };
+            let dissolve = name.filter(|name| {
+                levels.map(|l| depth <= l).unwrap_or(true) && re.matches(name).matched_any()
+            });
+            let Some(name) = dissolve else {
+                out.push(transform_element(element, tf));
+                continue;
+            };
+            let child: &'a GdsStruct = *by_name
+                .get(name)
+                .ok_or_else(|| format!("cell {} is not defined in the library", name))?;
+            if path.contains(&name) {
+                return Err(format!("reference cycle through cell {}", name));
+            }
+            path.push(name);
+            for child_tf in reference_transforms(element) {
+                let child_tf = child_tf.then(tf);
+                flatten_into(&child.elems, &child_tf, depth + 1, levels, re, by_name, path, out)?;
+            }
+            path.pop();
+        }
+        Ok(())
+    }
+
+    let flat_elems = {
+        let by_name = structs_by_name(lib);
+        let top_struct: &GdsStruct = *by_name
+            .get(top)
+            .ok_or_else(|| format!("cell {} is not defined in the library", top))?;
+        let mut out = vec![];
+        let mut path = vec![top_struct.name.as_str()];
+        flatten_into(
+            &top_struct.elems,
+            &GdsTransform::identity(),
+            1,
+            levels,
+            re,
+            &by_name,
+            &mut path,
+            &mut out,
+        )?;
+        out
+    };
+
+    let top_struct = lib.structs.iter_mut().find(|s| s.name == top).unwrap();
+    top_struct.elems = flat_elems;
+    Ok(())
+}
+
+/// Apply a transformation to an element.
+///
+/// Path widths and extensions as well as text widths scale with the magnification, negative
+/// (absolute) widths are kept. References get the combined placement of both transformations.
+pub fn transform_element(element: &GdsElement, tf: &GdsTransform) -> GdsElement {
+    if *tf == GdsTransform::identity() {
+        return element.clone();
+    }
+    let mag = tf.magnification();
+    let scale = |v: i32| (v as f64 * mag).round() as i32;
+    let points = |xy: &[GdsPoint]| xy.iter().map(|p| tf.apply_point(p)).collect::<Vec<_>>();
+
+    match element {
+        GdsElement::GdsBoundary(b) => GdsElement::GdsBoundary(GdsBoundary {
+            xy: points(&b.xy),
+            ..b.clone()
+        }),
+        GdsElement::GdsPath(p) => GdsElement::GdsPath(GdsPath {
+            xy: points(&p.xy),
+            width: p.width.map(|w| if w < 0 { w } else { scale(w) }),
+            begin_extn: p.begin_extn.map(scale),
+            end_extn: p.end_extn.map(scale),
+            ..p.clone()
+        }),
+        GdsElement::GdsStructRef(r) => {
+            let placement = GdsTransform::new(r.strans.as_ref(), &r.xy).then(tf);
+            GdsElement::GdsStructRef(GdsStructRef {
+                xy: placement.origin(),
+                strans: placement.to_strans(),
+                ..r.clone()
+            })
+        }
+        GdsElement::GdsArrayRef(r) => {
+            let placement = GdsTransform::new(r.strans.as_ref(), &r.xy[0]).then(tf);
+            GdsElement::GdsArrayRef(GdsArrayRef {
+                xy: r.xy.clone().map(|p| tf.apply_point(&p)),
+                strans: placement.to_strans(),
+                ..r.clone()
+            })
+        }
+        GdsElement::GdsTextElem(t) => {
+            let placement = GdsTransform::new(t.strans.as_ref(), &t.xy).then(tf);
+            GdsElement::GdsTextElem(GdsTextElem {
+                xy: placement.origin(),
+                strans: placement.to_strans(),
+                width: t.width.map(|w| if w < 0 { w } else { scale(w) }),
+                ..t.clone()
+            })
+        }
+        GdsElement::GdsNode(n) => GdsElement::GdsNode(GdsNode {
+            xy: points(&n.xy),
+            ..n.clone()
+        }),
+        GdsElement::GdsBox(b) => GdsElement::GdsBox(GdsBox {
+            xy: b.xy.clone().map(|p| tf.apply_point(&p)),
+            ..b.clone()
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gds21::GdsStrans;
+
+    use crate::commands::geometry::BoundingBox;
+    use crate::commands::test_support::{cell, library, sref_at, square};
+
+    fn sref(name: &str, x: i32, y: i32, mag: f64, abs_mag: bool) -> GdsElement {
+        let strans = GdsStrans {
+            reflected: false,
+            abs_mag,
+            abs_angle: false,
+            mag: Some(mag),
+            angle: None,
+        };
+        sref_at(name, x, y, Some(strans))
+    }
+
+    fn nested() -> GdsLibrary {
+        library(vec![
+            cell("leaf", vec![square(10)]),
+            cell("fixed", vec![sref("leaf", 0, 0, 1.0, true)]),
+            cell("top", vec![sref("leaf", 0, 0, 2.0, false), sref("fixed", 100, 0, 3.0, false)]),
+        ])
+    }
+
+    fn bboxes(polygons: &[Vec<GdsPoint>]) -> Vec<BoundingBox> {
+        polygons.iter().map(|p| BoundingBox::from_points(p).unwrap()).collect()
+    }
+
+    #[test]
+    fn flat_polygons_follow_relative_and_absolute_magnification() {
+        let polygons = flat_polygons(&nested(), "top").unwrap();
+        let boxes = bboxes(&polygons[&(1, 0)]);
+        let corners: Vec<_> = boxes.iter().map(|b| (b.min_x, b.min_y, b.max_x, b.max_y)).collect();
+        assert_eq!(corners, vec![(0, 0, 20, 20), (100, 0, 110, 10)]);
+    }
+
+    #[test]
+    fn flatten_cell_keeps_absolute_placements() {
+        let mut lib = nested();
+        flatten_cell(&mut lib, "top", Some(1), &RegexSet::new(["fixed"]).unwrap()).unwrap();
+        let top = lib.structs.iter().find(|s| s.name == "top").unwrap();
+        let GdsElement::GdsStructRef(leaf) = &top.elems[1] else {
+            panic!("expected the leaf reference of the dissolved cell");
+        };
+        assert_eq!((leaf.name.as_str(), leaf.xy.x, leaf.xy.y), ("leaf", 100, 0));
+        let strans = leaf.strans.as_ref().unwrap();
+        assert!(strans.abs_mag);
+        assert_eq!(strans.mag, None);
+    }
+}
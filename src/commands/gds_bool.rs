@@ -0,0 +1,291 @@
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+use gds21::{GdsLibrary, GdsStruct};
+use iron_shapes::prelude::MultiPolygon;
+use iron_shapes_booleanop::BooleanOp;
+
+use super::flatten::{flat_polygons, LayerPolygons};
+use super::geometry::{
+    element_polygons, merge_polygons, polygon_layer, polygons_to_boundaries, to_polygon,
+};
+use super::hierarchy::reachable_cells;
+use super::layer_map::{parse_gds_layer, GdsLayer};
+use super::vertex_limit::save_library;
+
+/// A boolean operator between two layers.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BoolOp {
+    And,
+    Or,
+    Xor,
+    /// Area of the left operand not covered by the right one, also written `AND NOT`.
+    Not,
+}
+
+/// A derived layer, for example `100/0 = 10/0 AND NOT 11/0`.
+///
+/// Operators are applied from left to right without precedence.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BoolExpression {
+    pub target: GdsLayer,
+    pub first: GdsLayer,
+    pub rest: Vec<(BoolOp, GdsLayer)>,
+}
+
+impl std::str::FromStr for BoolExpression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (target, expression) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected TARGET = EXPRESSION, got {}", s))?;
+        let target = target.trim();
+        let target = parse_gds_layer(target.strip_prefix("out ").unwrap_or(target))?;
+
+        let mut tokens = expression.split_whitespace().peekable();
+        let first = parse_gds_layer(tokens.next().ok_or("empty boolean expression")?)?;
+        let mut rest = vec![];
+        while let Some(token) = tokens.next() {
+            let op = match token.to_ascii_uppercase().as_str() {
+                "AND" if tokens.peek().map(|t| t.eq_ignore_ascii_case("NOT")).unwrap_or(false) => {
+                    tokens.next();
+                    BoolOp::Not
+                }
+                "AND" => BoolOp::And,
+                "OR" => BoolOp::Or,
+                "XOR" => BoolOp::Xor,
+                "NOT" => BoolOp::Not,
+                _ => return Err(format!("unknown boolean operator {}", token)),
+            };
+            let operand = tokens
+                .next()
+                .ok_or_else(|| format!("missing operand after {}", token))?;
+            rest.push((op, parse_gds_layer(operand)?));
+        }
+        Ok(Self { target, first, rest })
+    }
+}
+
+impl BoolExpression {
+    /// The layers read by the expression.
+    pub fn operands(&self) -> Vec<GdsLayer> {
+        std::iter::once(self.first)
+            .chain(self.rest.iter().map(|(_, layer)| *layer))
+            .collect()
+    }
+
+    /// Evaluate the expression on a set of polygons, returning the merged result.
+    pub fn evaluate(&self, polygons: &LayerPolygons) -> MultiPolygon<i64> {
+        let merged = |layer: &GdsLayer| {
+            let layer_polygons = polygons.get(layer).map(|p| p.as_slice()).unwrap_or_default();
+            merge_polygons(layer_polygons.iter().map(|p| to_polygon(p)).collect())
+        };
+        self.rest.iter().fold(merged(&self.first), |result, (op, layer)| {
+            let operand = merged(layer);
+            match op {
+                BoolOp::And => result.intersection(&operand),
+                BoolOp::Or => result.union(&operand),
+                BoolOp::Xor => result.xor(&operand),
+                BoolOp::Not => result.difference(&operand),
+            }
+        })
+    }
+}
+
+/// Derive layers of a GDS file with boolean operations.
+///
+/// # Arguments
+/// * `top` - Cell whose flattened geometry the expressions are evaluated on
+/// * `input` - Path to the GDS file
+/// * `output` - Output GDS file path
+/// * `expressions` - Evaluated in order, later expressions can read earlier targets
+/// * `per_cell` - Evaluate inside every cell instead of on the flattened top, where safe
+/// * `max_points` - Most XY points written per boundary or path
+pub fn bool_gds_layers(
+    top: &str,
+    input: &PathBuf,
+    output: &PathBuf,
+    expressions: &[BoolExpression],
+    per_cell: bool,
+    max_points: usize,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut lib = GdsLibrary::load(input)?;
+    for expression in expressions {
+        let cells = if per_cell { per_cell_targets(&lib, top, expression)? } else { None };
+        match cells {
+            Some(cells) => {
+                for s in lib.structs.iter_mut().filter(|s| cells.contains(&s.name)) {
+                    let result = expression.evaluate(&own_polygons(s));
+                    let (layer, datatype) = expression.target;
+                    let (boundaries, warnings) = polygons_to_boundaries(&result, layer, datatype);
+                    for warning in warnings {
+                        println!("{}: {}", s.name, warning);
+                    }
+                    s.elems.extend(boundaries);
+                }
+            }
+            None => {
+                if per_cell {
+                    println!(
+                        "Deriving layer {}/{} on the flattened top cell, per cell is not safe",
+                        expression.target.0, expression.target.1
+                    );
+                }
+                let result = expression.evaluate(&flat_polygons(&lib, top)?);
+                let (layer, datatype) = expression.target;
+                let (boundaries, warnings) = polygons_to_boundaries(&result, layer, datatype);
+                for warning in warnings {
+                    println!("{}: {}", top, warning);
+                }
+                let top_struct = lib.structs.iter_mut().find(|s| s.name == top).unwrap();
+                top_struct.elems.extend(boundaries);
+            }
+        }
+    }
+    save_library(&mut lib, output, max_points)?;
+    Ok(())
+}
+
+/// The cells an expression can be evaluated in separately with the same result as on the
+/// flattened top, `None` if that is not safe.
+///
+/// A pure OR is safe in every cell. Other operators are only safe when no cell but `top`
+/// holds geometry on the operand layers, as overlapping instances could interact otherwise.
+fn per_cell_targets(
+    lib: &GdsLibrary,
+    top: &str,
+    expression: &BoolExpression,
+) -> Result<Option<HashSet<String>>, String> {
+    let operands = expression.operands();
+    let reachable: HashSet<String> = reachable_cells(lib, &[top])?.into_iter().collect();
+    let cells: HashSet<String> = lib
+        .structs
+        .iter()
+        .filter(|s| reachable.contains(&s.name))
+        .filter(|s| {
+            s.elems
+                .iter()
+                .any(|e| polygon_layer(e).map(|l| operands.contains(&l)).unwrap_or(false))
+        })
+        .map(|s| s.name.clone())
+        .collect();
+    let pure_or = expression.rest.iter().all(|(op, _)| *op == BoolOp::Or);
+    let only_top = cells.iter().all(|name| name == top);
+    Ok((pure_or || only_top).then_some(cells))
+}
+
+/// The polygons of a cell itself, placed cells not included.
+fn own_polygons(gds_struct: &GdsStruct) -> LayerPolygons {
+    let mut polygons = LayerPolygons::new();
+    for element in &gds_struct.elems {
+        if let Some(key) = polygon_layer(element) {
+            polygons.entry(key).or_default().extend(element_polygons(element));
+        }
+    }
+    polygons
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gds21::GdsElement;
+
+    use crate::commands::geometry::polygon_area;
+    use crate::commands::test_support::{cell, library, points, rect, sref};
+
+    fn on_layer(layer: i16, element: GdsElement) -> GdsElement {
+        match element {
+            GdsElement::GdsBoundary(mut b) => {
+                b.layer = layer;
+                GdsElement::GdsBoundary(b)
+            }
+            _ => panic!("expected a boundary"),
+        }
+    }
+
+    fn names(cells: Option<HashSet<String>>) -> Option<Vec<String>> {
+        cells.map(|cells| {
+            let mut names: Vec<String> = cells.into_iter().collect();
+            names.sort();
+            names
+        })
+    }
+
+    #[test]
+    fn expressions_parse_operators_left_to_right() {
+        let expression: BoolExpression = "100/0 = 10/0 AND NOT 11 or 12/3 xor 13/0 NOT 14/0".parse().unwrap();
+        assert_eq!(
+            expression,
+            BoolExpression {
+                target: (100, 0),
+                first: (10, 0),
+                rest: vec![
+                    (BoolOp::Not, (11, 0)),
+                    (BoolOp::Or, (12, 3)),
+                    (BoolOp::Xor, (13, 0)),
+                    (BoolOp::Not, (14, 0)),
+                ],
+            }
+        );
+        assert_eq!(expression.operands(), [(10, 0), (11, 0), (12, 3), (13, 0), (14, 0)]);
+    }
+
+    #[test]
+    fn targets_may_carry_the_out_prefix() {
+        let expression: BoolExpression = "out 100/2 = 10/0 AND 11/0".parse().unwrap();
+        assert_eq!(expression.target, (100, 2));
+        assert_eq!(expression.rest, [(BoolOp::And, (11, 0))]);
+        let expression: BoolExpression = "7=1".parse().unwrap();
+        assert_eq!((expression.target, expression.first), ((7, 0), (1, 0)));
+        assert!(expression.rest.is_empty());
+    }
+
+    #[test]
+    fn malformed_expressions_are_errors() {
+        for text in [
+            "10/0 AND 11/0",
+            "100/0 =",
+            "100/0 = 10/0 AND",
+            "100/0 = 10/0 NAND 11/0",
+            "100/0 = 10/0 AND M1",
+            "top = 10/0",
+        ] {
+            assert!(text.parse::<BoolExpression>().is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn evaluation_applies_the_operators_in_order() {
+        let polygons = LayerPolygons::from([
+            ((1, 0), vec![points(&[(0, 0), (20, 0), (20, 10), (0, 10)])]),
+            ((2, 0), vec![points(&[(10, 0), (30, 0), (30, 10), (10, 10)])]),
+        ]);
+        let area = |text: &str| -> f64 {
+            let expression: BoolExpression = text.parse().unwrap();
+            expression.evaluate(&polygons).polygons.iter().map(polygon_area).sum()
+        };
+        assert_eq!(area("3 = 1 AND 2"), 100.0);
+        assert_eq!(area("3 = 1 OR 2"), 300.0);
+        assert_eq!(area("3 = 1 XOR 2"), 200.0);
+        assert_eq!(area("3 = 1 AND NOT 2"), 100.0);
+        assert_eq!(area("3 = 1 OR 2 NOT 1"), 100.0);
+        assert_eq!(area("3 = 4 OR 1"), 200.0);
+    }
+
+    #[test]
+    fn per_cell_targets_allow_or_everywhere_and_other_operators_in_top_only() {
+        let lib = library(vec![
+            cell("top", vec![on_layer(1, rect(0, 0, 10, 10)), sref("leaf")]),
+            cell("leaf", vec![on_layer(2, rect(0, 0, 10, 10))]),
+            cell("unused", vec![on_layer(1, rect(0, 0, 10, 10))]),
+        ]);
+        let targets = |text: &str| names(per_cell_targets(&lib, "top", &text.parse().unwrap()).unwrap());
+
+        assert_eq!(targets("9 = 1 OR 2"), Some(vec!["leaf".to_string(), "top".to_string()]));
+        assert_eq!(targets("9 = 1 AND NOT 2"), None);
+        assert_eq!(targets("9 = 1 AND 3"), Some(vec!["top".to_string()]));
+        assert_eq!(targets("9 = 3 AND 4"), Some(vec![]));
+        assert!(per_cell_targets(&lib, "missing", &"9 = 1".parse().unwrap()).is_err());
+    }
+}
@@ -0,0 +1,471 @@
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use std::path::PathBuf;
+
+use gds21::{
+    GdsArrayRef, GdsElement, GdsLibrary, GdsPoint, GdsStrans, GdsStruct, GdsStructRef,
+    GdsTextElem,
+};
+use serde::Serialize;
+
+use super::geometry::{area_doubled, element_polygons, polygon_layer};
+use super::hierarchy::structs_by_name;
+
+#[derive(Serialize, Default)]
+pub struct DiffReport {
+    pub added_cells: Vec<String>,
+    pub removed_cells: Vec<String>,
+    pub changed_cells: Vec<CellDiff>,
+}
+
+impl DiffReport {
+    pub fn is_empty(&self) -> bool {
+        self.added_cells.is_empty() && self.removed_cells.is_empty() && self.changed_cells.is_empty()
+    }
+}
+
+#[derive(Serialize, Default)]
+pub struct CellDiff {
+    pub name: String,
+    pub layers: Vec<LayerDiff>,
+    pub added_references: Vec<ReferenceSummary>,
+    pub removed_references: Vec<ReferenceSummary>,
+    pub added_texts: Vec<TextSummary>,
+    pub removed_texts: Vec<TextSummary>,
+}
+
+impl CellDiff {
+    pub fn is_empty(&self) -> bool {
+        self.layers.is_empty()
+            && self.added_references.is_empty()
+            && self.removed_references.is_empty()
+            && self.added_texts.is_empty()
+            && self.removed_texts.is_empty()
+    }
+}
+
+/// Polygons only present in one of the two versions of a cell, on one layer/datatype.
+#[derive(Serialize, Default)]
+pub struct LayerDiff {
+    pub layer: i16,
+    pub datatype: i16,
+    pub added: Vec<Vec<GdsPoint>>,
+    pub removed: Vec<Vec<GdsPoint>>,
+}
+
+/// A SREF or AREF, reduced to the fields that matter for comparison.
+#[derive(Serialize, Clone, Debug)]
+pub struct ReferenceSummary {
+    pub name: String,
+    /// The origin of a SREF, or the three lattice points of an AREF.
+    pub xy: Vec<GdsPoint>,
+    pub reflected: bool,
+    pub mag: f64,
+    pub angle: f64,
+    pub cols: i16,
+    pub rows: i16,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct TextSummary {
+    pub layer: i16,
+    pub texttype: i16,
+    pub string: String,
+    pub xy: GdsPoint,
+}
+
+/// Compare two GDS files and print the differences.
+///
+/// # Arguments
+/// * `a` - Path to the original GDS file
+/// * `b` - Path to the modified GDS file
+/// * `tolerance` - Coordinates differing by at most this many database units are considered equal
+/// * `json` - Print a JSON report instead of text
+///
+/// # Returns
+/// The report, empty when both libraries match.
+pub fn print_gds_diff(
+    a: &PathBuf,
+    b: &PathBuf,
+    tolerance: i32,
+    json: bool,
+) -> Result<DiffReport, Box<dyn std::error::Error>> {
+    let lib_a = GdsLibrary::load(a)?;
+    let lib_b = GdsLibrary::load(b)?;
+    let report = diff_libraries(&lib_a, &lib_b, tolerance);
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print!("{}", report_to_text(&report));
+    }
+    Ok(report)
+}
+
+/// Compare the cells of two libraries by name.
+pub fn diff_libraries(a: &GdsLibrary, b: &GdsLibrary, tolerance: i32) -> DiffReport {
+    let cells_a = structs_by_name(a);
+    let cells_b = structs_by_name(b);
+
+    let mut report = DiffReport::default();
+    for s in &a.structs {
+        match cells_b.get(s.name.as_str()) {
+            None => report.removed_cells.push(s.name.clone()),
+            Some(other) => {
+                let diff = diff_cells(s, other, tolerance);
+                if !diff.is_empty() {
+                    report.changed_cells.push(diff);
+                }
+            }
+        }
+    }
+    report.added_cells = b
+        .structs
+        .iter()
+        .filter(|s| !cells_a.contains_key(s.name.as_str()))
+        .map(|s| s.name.clone())
+        .collect();
+    report
+}
+
+/// Compare two versions of a cell element by element.
+pub fn diff_cells(a: &GdsStruct, b: &GdsStruct, tolerance: i32) -> CellDiff {
+    let mut diff = CellDiff {
+        name: a.name.clone(),
+        ..Default::default()
+    };
+
+    let mut polygons_a = polygons_per_layer(a);
+    let mut polygons_b = polygons_per_layer(b);
+    let mut layers: Vec<(i16, i16)> = polygons_a.keys().chain(polygons_b.keys()).copied().collect();
+    layers.sort();
+    layers.dedup();
+    for key in layers {
+        let (removed, added) = unmatched(
+            polygons_a.remove(&key).unwrap_or_default(),
+            polygons_b.remove(&key).unwrap_or_default(),
+            |p| p.iter().map(|p| (p.x, p.y)).collect::<Vec<_>>(),
+            (tolerance > 0).then_some(|x: &Vec<GdsPoint>, y: &Vec<GdsPoint>| {
+                cyclic_match(x, y, tolerance)
+            }),
+        );
+        if !removed.is_empty() || !added.is_empty() {
+            diff.layers.push(LayerDiff {
+                layer: key.0,
+                datatype: key.1,
+                added,
+                removed,
+            });
+        }
+    }
+
+    let (removed, added) = unmatched(
+        references(a),
+        references(b),
+        |r| format!("{:?}", r),
+        (tolerance > 0).then_some(|x: &ReferenceSummary, y: &ReferenceSummary| {
+            x.name == y.name
+                && x.cols == y.cols
+                && x.rows == y.rows
+                && x.reflected == y.reflected
+                && (x.mag - y.mag).abs() < 1e-9
+                && (x.angle - y.angle).abs() < 1e-9
+                && points_match(&x.xy, &y.xy, tolerance)
+        }),
+    );
+    diff.removed_references = removed;
+    diff.added_references = added;
+
+    let (removed, added) = unmatched(
+        texts(a),
+        texts(b),
+        |t| format!("{:?}", t),
+        (tolerance > 0).then_some(|x: &TextSummary, y: &TextSummary| {
+            x.layer == y.layer
+                && x.texttype == y.texttype
+                && x.string == y.string
+                && points_match(std::slice::from_ref(&x.xy), std::slice::from_ref(&y.xy), tolerance)
+        }),
+    );
+    diff.removed_texts = removed;
+    diff.added_texts = added;
+
+    diff
+}
+
+/// The canonical polygons of a cell per layer/datatype.
+///
+/// Paths are compared by their outline, so a path and an identical boundary match.
+fn polygons_per_layer(gds_struct: &GdsStruct) -> BTreeMap<(i16, i16), Vec<Vec<GdsPoint>>> {
+    let mut result: BTreeMap<(i16, i16), Vec<Vec<GdsPoint>>> = BTreeMap::new();
+    for element in &gds_struct.elems {
+        let Some(key) = polygon_layer(element) else {
+            continue;
+        };
+        for polygon in element_polygons(element) {
+            result.entry(key).or_default().push(canonical_polygon(&polygon));
+        }
+    }
+    result
+}
+
+/// Bring a polygon into a canonical vertex order.
+///
+/// Repeated vertices are dropped, the orientation is made counter-clockwise and the
+/// point list starts at the lowest (x, y) vertex.
+pub fn canonical_polygon(points: &[GdsPoint]) -> Vec<GdsPoint> {
+    let mut points: Vec<GdsPoint> = points.to_vec();
+    points.dedup();
+    while points.len() > 1 && points.first() == points.last() {
+        points.pop();
+    }
+    if area_doubled(&points) < 0 {
+        points.reverse();
+    }
+    if let Some(start) = (0..points.len()).min_by_key(|&i| (points[i].x, points[i].y)) {
+        points.rotate_left(start);
+    }
+    points
+}
+
+fn references(gds_struct: &GdsStruct) -> Vec<ReferenceSummary> {
+    let summary = |name: &String, xy: Vec<GdsPoint>, strans: &Option<GdsStrans>, cols, rows| {
+        ReferenceSummary {
+            name: name.clone(),
+            xy,
+            reflected: strans.as_ref().map(|s| s.reflected).unwrap_or(false),
+            mag: strans.as_ref().and_then(|s| s.mag).unwrap_or(1.0),
+            angle: strans.as_ref().and_then(|s| s.angle).unwrap_or(0.0),
+            cols,
+            rows,
+        }
+    };
+    gds_struct
+        .elems
+        .iter()
+        .filter_map(|element| match element {
+            GdsElement::GdsStructRef(GdsStructRef { name, xy, strans, .. }) => {
+                Some(summary(name, vec![xy.clone()], strans, 1, 1))
+            }
+            GdsElement::GdsArrayRef(GdsArrayRef { name, xy, cols, rows, strans, .. }) => {
+                Some(summary(name, xy.to_vec(), strans, *cols, *rows))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+fn texts(gds_struct: &GdsStruct) -> Vec<TextSummary> {
+    gds_struct
+        .elems
+        .iter()
+        .filter_map(|element| match element {
+            GdsElement::GdsTextElem(GdsTextElem { layer, texttype, string, xy, .. }) => {
+                Some(TextSummary {
+                    layer: *layer,
+                    texttype: *texttype,
+                    string: string.clone(),
+                    xy: xy.clone(),
+                })
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Pair up the items of `a` and `b`, returning those of `a` and of `b` left without a partner.
+///
+/// Items are paired on equal keys first. When `close` is given, the remaining items are
+/// paired greedily whenever `close` accepts them.
+fn unmatched<T, K, F>(a: Vec<T>, b: Vec<T>, key: impl Fn(&T) -> K, close: Option<F>) -> (Vec<T>, Vec<T>)
+where
+    K: Hash + Eq,
+    F: Fn(&T, &T) -> bool,
+{
+    let mut b_by_key: HashMap<K, Vec<usize>> = HashMap::new();
+    for (i, item) in b.iter().enumerate() {
+        b_by_key.entry(key(item)).or_default().push(i);
+    }
+
+    let mut b_used = vec![false; b.len()];
+    let mut removed = vec![];
+    for item in a {
+        match b_by_key.get_mut(&key(&item)).and_then(|indices| indices.pop()) {
+            Some(i) => b_used[i] = true,
+            None => removed.push(item),
+        }
+    }
+    let mut added: Vec<T> = b
+        .into_iter()
+        .zip(b_used)
+        .filter(|(_, used)| !used)
+        .map(|(item, _)| item)
+        .collect();
+
+    if let Some(close) = close {
+        removed.retain(|r| match added.iter().position(|a| close(r, a)) {
+            Some(i) => {
+                added.remove(i);
+                false
+            }
+            None => true,
+        });
+    }
+    (removed, added)
+}
+
+fn points_match(a: &[GdsPoint], b: &[GdsPoint], tolerance: i32) -> bool {
+    a.len() == b.len()
+        && a.iter().zip(b).all(|(p, q)| {
+            (p.x as i64 - q.x as i64).abs() <= tolerance as i64
+                && (p.y as i64 - q.y as i64).abs() <= tolerance as i64
+        })
+}
+
+/// Whether two canonical polygons match within the tolerance for any choice of start vertex.
+///
+/// Coordinate noise can move the lowest vertex, so every rotation of `b` is tried.
+fn cyclic_match(a: &[GdsPoint], b: &[GdsPoint], tolerance: i32) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    let mut rotated = b.to_vec();
+    for _ in 0..b.len().max(1) {
+        if points_match(a, &rotated, tolerance) {
+            return true;
+        }
+        rotated.rotate_left(1);
+    }
+    false
+}
+
+fn format_points(points: &[GdsPoint]) -> String {
+    points
+        .iter()
+        .map(|p| format!("({}, {})", p.x, p.y))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn format_reference(r: &ReferenceSummary) -> String {
+    let mut s = format!("{} at {}", r.name, format_points(&r.xy));
+    if r.cols != 1 || r.rows != 1 {
+        s.push_str(&format!(" {}x{}", r.cols, r.rows));
+    }
+    if r.angle != 0.0 {
+        s.push_str(&format!(" angle {}", r.angle));
+    }
+    if r.mag != 1.0 {
+        s.push_str(&format!(" mag {}", r.mag));
+    }
+    if r.reflected {
+        s.push_str(" reflected");
+    }
+    s
+}
+
+/// Render a report in a `diff`-like text form, `+` marking additions and `-` removals.
+pub fn report_to_text(report: &DiffReport) -> String {
+    let mut out = String::new();
+    for name in &report.removed_cells {
+        out.push_str(&format!("- cell {}\n", name));
+    }
+    for name in &report.added_cells {
+        out.push_str(&format!("+ cell {}\n", name));
+    }
+    for cell in &report.changed_cells {
+        out.push_str(&format!("~ cell {}\n", cell.name));
+        for layer in &cell.layers {
+            out.push_str(&format!(
+                "    layer {}/{}: {} removed, {} added\n",
+                layer.layer,
+                layer.datatype,
+                layer.removed.len(),
+                layer.added.len()
+            ));
+            for p in &layer.removed {
+                out.push_str(&format!("      - {}\n", format_points(p)));
+            }
+            for p in &layer.added {
+                out.push_str(&format!("      + {}\n", format_points(p)));
+            }
+        }
+        for r in &cell.removed_references {
+            out.push_str(&format!("    - ref {}\n", format_reference(r)));
+        }
+        for r in &cell.added_references {
+            out.push_str(&format!("    + ref {}\n", format_reference(r)));
+        }
+        for t in &cell.removed_texts {
+            out.push_str(&format!(
+                "    - text {}/{} '{}' at ({}, {})\n",
+                t.layer, t.texttype, t.string, t.xy.x, t.xy.y
+            ));
+        }
+        for t in &cell.added_texts {
+            out.push_str(&format!(
+                "    + text {}/{} '{}' at ({}, {})\n",
+                t.layer, t.texttype, t.string, t.xy.x, t.xy.y
+            ));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::test_support::{boundary, cell, library, points};
+
+    #[test]
+    fn canonical_polygon_ignores_start_vertex_orientation_and_repeats() {
+        let expected = points(&[(0, 0), (10, 0), (10, 5), (0, 5)]);
+        let variants = [
+            points(&[(0, 0), (10, 0), (10, 5), (0, 5), (0, 0)]),
+            points(&[(10, 5), (0, 5), (0, 0), (10, 0)]),
+            points(&[(0, 0), (0, 5), (10, 5), (10, 0), (0, 0)]),
+            points(&[(10, 0), (10, 0), (10, 5), (0, 5), (0, 5), (0, 0)]),
+        ];
+        for variant in &variants {
+            assert_eq!(canonical_polygon(variant), expected);
+        }
+    }
+
+    #[test]
+    fn equal_cells_have_an_empty_diff() {
+        let a = cell("c", vec![boundary(&[(0, 0), (10, 0), (10, 10), (0, 10), (0, 0)])]);
+        let b = cell("c", vec![boundary(&[(10, 10), (10, 0), (0, 0), (0, 10), (10, 10)])]);
+        assert!(diff_cells(&a, &b, 0).is_empty());
+    }
+
+    #[test]
+    fn moved_polygon_is_reported_unless_within_tolerance() {
+        let a = cell("c", vec![boundary(&[(0, 0), (10, 0), (10, 10), (0, 10), (0, 0)])]);
+        let b = cell("c", vec![boundary(&[(1, 0), (11, 0), (11, 10), (1, 10), (1, 0)])]);
+
+        let diff = diff_cells(&a, &b, 0);
+        assert_eq!(diff.layers.len(), 1);
+        assert_eq!(diff.layers[0].removed.len(), 1);
+        assert_eq!(diff.layers[0].added.len(), 1);
+
+        assert!(diff_cells(&a, &b, 1).is_empty());
+    }
+
+    #[test]
+    fn tolerance_matches_polygons_whose_lowest_vertex_moved() {
+        // The noise swaps which vertex sorts lowest, so the canonical start points differ.
+        let a = cell("c", vec![boundary(&[(1, 0), (10, 0), (10, 10), (0, 10)])]);
+        let b = cell("c", vec![boundary(&[(0, 0), (10, 0), (10, 10), (1, 10)])]);
+        assert!(!diff_cells(&a, &b, 0).is_empty());
+        assert!(diff_cells(&a, &b, 1).is_empty());
+    }
+
+    #[test]
+    fn added_and_removed_cells_are_listed() {
+        let a = library(vec![cell("only_a", vec![]), cell("both", vec![])]);
+        let b = library(vec![cell("both", vec![]), cell("only_b", vec![])]);
+        let report = diff_libraries(&a, &b, 0);
+        assert_eq!(report.removed_cells, vec!["only_a".to_string()]);
+        assert_eq!(report.added_cells, vec!["only_b".to_string()]);
+        assert!(report.changed_cells.is_empty());
+    }
+}
@@ -0,0 +1,287 @@
+use std::path::PathBuf;
+
+use gds21::{
+    GdsArrayRef, GdsBoundary, GdsBox, GdsDateTimes, GdsElement, GdsLibrary, GdsNode, GdsPath,
+    GdsPoint, GdsProperty, GdsStrans, GdsStruct, GdsStructRef, GdsTextElem,
+};
+use serde::Serialize;
+
+use super::geometry::units_pair;
+
+/// Bit of the STRANS record flagging a reflection about the x-axis.
+pub(crate) const STRANS_REFLECTED: u16 = 0x8000;
+/// Bit of the STRANS record flagging an absolute magnification.
+pub(crate) const STRANS_ABS_MAG: u16 = 0x0004;
+/// Bit of the STRANS record flagging an absolute angle.
+pub(crate) const STRANS_ABS_ANGLE: u16 = 0x0002;
+
+/// Date format of the BGNLIB and BGNSTR records, e.g. `5/11/2024 21:58:14`.
+pub(crate) const DATE_FORMAT: &str = "%-m/%-d/%Y %H:%M:%S";
+
+/// Convert a GDS file into the textual record dump kept next to the layouts in `resources/`.
+///
+/// # Arguments
+/// * `input` - Path to the GDS file
+/// * `output` - Output file path, the dump is printed to stdout when omitted
+pub fn convert_gds_to_txt(
+    input: &PathBuf,
+    output: Option<&PathBuf>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let lib = GdsLibrary::load(input)?;
+    let text = gds_library_to_txt(&lib);
+    match output {
+        Some(output) => std::fs::write(output, text)?,
+        None => println!("{}", text),
+    }
+    Ok(())
+}
+
+/// Render a `GdsLibrary` as a line-oriented record dump.
+///
+/// Every record is written on its own line, as `NAME value value `. Coordinates of an `XY`
+/// record are written one point per line as `x: y`. Elements carrying a layer, as well as
+/// every `BGNSTR`, are preceded by an empty line.
+///
+/// # Arguments
+/// * `lib` - The library to render.
+///
+/// # Returns
+/// The dump, without a trailing newline after `ENDLIB`.
+pub fn gds_library_to_txt(lib: &GdsLibrary) -> String {
+    let mut lines: Vec<String> = vec![];
+
+    lines.push(format!("HEADER {} ", lib.version));
+    lines.push(format!("BGNLIB {} ", format_dates(&lib.dates)));
+    lines.push(format!("LIBNAME {}", lib.name));
+    let (user_units, db_units) = units_pair(lib);
+    lines.push(format!("UNITS {} {} ", format_real(user_units), format_real(db_units)));
+
+    for gds_struct in &lib.structs {
+        struct_to_txt(gds_struct, &mut lines);
+    }
+    lines.push("ENDLIB ".to_string());
+
+    lines.join("\n")
+}
+
+fn struct_to_txt(gds_struct: &GdsStruct, lines: &mut Vec<String>) {
+    lines.push(String::new());
+    lines.push(format!("BGNSTR {} ", format_dates(&gds_struct.dates)));
+    lines.push(format!("STRNAME {}", gds_struct.name));
+    for element in &gds_struct.elems {
+        element_to_txt(element, lines);
+    }
+    lines.push("ENDSTR ".to_string());
+}
+
+fn element_to_txt(element: &GdsElement, lines: &mut Vec<String>) {
+    match element {
+        GdsElement::GdsBoundary(GdsBoundary { layer, datatype, xy, elflags, plex, properties }) => {
+            lines.push(String::new());
+            lines.push("BOUNDARY ".to_string());
+            flags_to_txt(elflags, plex, lines);
+            lines.push(format!("LAYER {} ", layer));
+            lines.push(format!("DATATYPE {} ", datatype));
+            xy_to_txt(xy, lines);
+            properties_to_txt(properties, lines);
+        }
+        GdsElement::GdsPath(GdsPath {
+            layer,
+            datatype,
+            xy,
+            width,
+            path_type,
+            begin_extn,
+            end_extn,
+            elflags,
+            plex,
+            properties,
+        }) => {
+            lines.push(String::new());
+            lines.push("PATH ".to_string());
+            flags_to_txt(elflags, plex, lines);
+            lines.push(format!("LAYER {} ", layer));
+            lines.push(format!("DATATYPE {} ", datatype));
+            if let Some(path_type) = path_type {
+                lines.push(format!("PATHTYPE {} ", path_type));
+            }
+            if let Some(width) = width {
+                lines.push(format!("WIDTH {} ", width));
+            }
+            if let Some(begin_extn) = begin_extn {
+                lines.push(format!("BGNEXTN {} ", begin_extn));
+            }
+            if let Some(end_extn) = end_extn {
+                lines.push(format!("ENDEXTN {} ", end_extn));
+            }
+            xy_to_txt(xy, lines);
+            properties_to_txt(properties, lines);
+        }
+        GdsElement::GdsStructRef(GdsStructRef { name, xy, strans, elflags, plex, properties }) => {
+            lines.push("SREF ".to_string());
+            flags_to_txt(elflags, plex, lines);
+            lines.push(format!("SNAME {}", name));
+            strans_to_txt(strans, lines);
+            xy_to_txt(std::slice::from_ref(xy), lines);
+            properties_to_txt(properties, lines);
+        }
+        GdsElement::GdsArrayRef(GdsArrayRef {
+            name,
+            xy,
+            cols,
+            rows,
+            strans,
+            elflags,
+            plex,
+            properties,
+        }) => {
+            lines.push("AREF ".to_string());
+            flags_to_txt(elflags, plex, lines);
+            lines.push(format!("SNAME {}", name));
+            strans_to_txt(strans, lines);
+            lines.push(format!("COLROW {} {} ", cols, rows));
+            xy_to_txt(xy, lines);
+            properties_to_txt(properties, lines);
+        }
+        GdsElement::GdsTextElem(GdsTextElem {
+            string,
+            layer,
+            texttype,
+            xy,
+            strans,
+            width,
+            presentation,
+            path_type,
+            elflags,
+            plex,
+            properties,
+        }) => {
+            lines.push(String::new());
+            lines.push("TEXT ".to_string());
+            flags_to_txt(elflags, plex, lines);
+            lines.push(format!("LAYER {} ", layer));
+            lines.push(format!("TEXTTYPE {} ", texttype));
+            if let Some(presentation) = presentation {
+                lines.push(format!("PRESENTATION {} ", opaque_bits(presentation)));
+            }
+            if let Some(path_type) = path_type {
+                lines.push(format!("PATHTYPE {} ", path_type));
+            }
+            if let Some(width) = width {
+                lines.push(format!("WIDTH {} ", width));
+            }
+            strans_to_txt(strans, lines);
+            xy_to_txt(std::slice::from_ref(xy), lines);
+            lines.push(format!("STRING {}", string));
+            properties_to_txt(properties, lines);
+        }
+        GdsElement::GdsNode(GdsNode { layer, nodetype, xy, elflags, plex, properties }) => {
+            lines.push(String::new());
+            lines.push("NODE ".to_string());
+            flags_to_txt(elflags, plex, lines);
+            lines.push(format!("LAYER {} ", layer));
+            lines.push(format!("NODETYPE {} ", nodetype));
+            xy_to_txt(xy, lines);
+            properties_to_txt(properties, lines);
+        }
+        GdsElement::GdsBox(GdsBox { layer, boxtype, xy, elflags, plex, properties }) => {
+            lines.push(String::new());
+            lines.push("BOX ".to_string());
+            flags_to_txt(elflags, plex, lines);
+            lines.push(format!("LAYER {} ", layer));
+            lines.push(format!("BOXTYPE {} ", boxtype));
+            xy_to_txt(xy, lines);
+            properties_to_txt(properties, lines);
+        }
+    }
+    lines.push("ENDEL ".to_string());
+}
+
+fn flags_to_txt<F: Serialize, P: Serialize>(
+    elflags: &Option<F>,
+    plex: &Option<P>,
+    lines: &mut Vec<String>,
+) {
+    if let Some(elflags) = elflags {
+        lines.push(format!("ELFLAGS {} ", opaque_bits(elflags)));
+    }
+    if let Some(plex) = plex {
+        lines.push(format!("PLEX {} ", opaque_bits(plex)));
+    }
+}
+
+fn strans_to_txt(strans: &Option<GdsStrans>, lines: &mut Vec<String>) {
+    if let Some(strans) = strans {
+        lines.push(format!("STRANS {} ", strans_bits(strans)));
+        if let Some(mag) = strans.mag {
+            lines.push(format!("MAG {} ", format_real(mag)));
+        }
+        if let Some(angle) = strans.angle {
+            lines.push(format!("ANGLE {} ", format_real(angle)));
+        }
+    }
+}
+
+fn xy_to_txt(xy: &[GdsPoint], lines: &mut Vec<String>) {
+    for (i, p) in xy.iter().enumerate() {
+        if i == 0 {
+            lines.push(format!("XY {}: {}", p.x, p.y));
+        } else {
+            lines.push(format!("{}: {}", p.x, p.y));
+        }
+    }
+}
+
+fn properties_to_txt(properties: &[GdsProperty], lines: &mut Vec<String>) {
+    for property in properties {
+        lines.push(format!("PROPATTR {} ", property.attr));
+        lines.push(format!("PROPVALUE {}", property.value));
+    }
+}
+
+/// The STRANS flags as the signed 16-bit integer stored in the record.
+pub(crate) fn strans_bits(strans: &GdsStrans) -> i16 {
+    let mut bits: u16 = 0;
+    if strans.reflected {
+        bits |= STRANS_REFLECTED;
+    }
+    if strans.abs_mag {
+        bits |= STRANS_ABS_MAG;
+    }
+    if strans.abs_angle {
+        bits |= STRANS_ABS_ANGLE;
+    }
+    bits as i16
+}
+
+/// Pack the private fields of a gds21 flag record (ELFLAGS, PLEX, PRESENTATION) into one integer.
+///
+/// Two-byte records are packed high byte first, as they are stored in the stream.
+fn opaque_bits<T: Serialize>(value: &T) -> i64 {
+    match serde_json::to_value(value).unwrap_or_default() {
+        serde_json::Value::Array(fields) => fields
+            .iter()
+            .fold(0, |acc, f| (acc << 8) | f.as_i64().unwrap_or_default()),
+        other => other.as_i64().unwrap_or_default(),
+    }
+}
+
+fn format_dates(dates: &GdsDateTimes) -> String {
+    format!(
+        "{} {}",
+        dates.modified.format(DATE_FORMAT),
+        dates.accessed.format(DATE_FORMAT)
+    )
+}
+
+/// Format a real number the way the record dumps do: `180`, `0.001`, `1e-09`.
+pub(crate) fn format_real(value: f64) -> String {
+    let scientific = format!("{:e}", value);
+    let (mantissa, exponent) = scientific.split_once('e').unwrap_or((&scientific, "0"));
+    let exponent: i32 = exponent.parse().unwrap_or_default();
+    if value == 0.0 || (-5..16).contains(&exponent) {
+        return format!("{}", value);
+    }
+    let sign = if exponent < 0 { '-' } else { '+' };
+    format!("{}e{}{:02}", mantissa, sign, exponent.abs())
+}
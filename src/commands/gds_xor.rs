@@ -0,0 +1,182 @@
+use std::path::PathBuf;
+
+use gds21::{GdsBoundary, GdsElement, GdsLibrary, GdsPoint, GdsStruct};
+use iron_shapes_booleanop::BooleanOp;
+use serde::Serialize;
+
+use super::flatten::flat_polygons;
+use super::geometry::{closed_points, merge_polygons, polygon_area, polygon_to_outlines, to_polygon, units_pair};
+use super::vertex_limit::save_library;
+
+/// The area by which two layouts differ on one layer/datatype.
+#[derive(Serialize)]
+pub struct LayerMismatch {
+    pub layer: i16,
+    pub datatype: i16,
+    /// Area of the XOR in square microns.
+    pub area: f64,
+    /// Number of polygons in the XOR.
+    pub polygons: usize,
+}
+
+/// Flatten two layouts below `top`, XOR them per layer and write the difference polygons.
+///
+/// # Arguments
+/// * `a` - Path to the first GDS file
+/// * `b` - Path to the second GDS file
+/// * `top` - Top cell flattened in both files
+/// * `output` - GDS file receiving the XOR polygons, in a cell named after `top`
+/// * `max_points` - Most XY points written per boundary or path
+///
+/// # Returns
+/// The mismatch per layer/datatype present in either layout.
+pub fn xor_layouts(
+    a: &PathBuf,
+    b: &PathBuf,
+    top: &str,
+    output: &PathBuf,
+    max_points: usize,
+) -> Result<Vec<LayerMismatch>, Box<dyn std::error::Error>> {
+    let lib_a = GdsLibrary::load(a)?;
+    let lib_b = GdsLibrary::load(b)?;
+    let (mut xor_lib, mismatches) = xor_libraries(&lib_a, &lib_b, top)?;
+    save_library(&mut xor_lib, output, max_points)?;
+
+    println!("{:>9}  {:>9}  {:>16}", "Layer", "Polygons", "XOR [um^2]");
+    for m in &mismatches {
+        println!(
+            "{:>9}  {:>9}  {:>16.6}",
+            format!("{}/{}", m.layer, m.datatype),
+            m.polygons,
+            m.area
+        );
+    }
+    Ok(mismatches)
+}
+
+/// XOR two libraries flattened below `top`, layer by layer.
+///
+/// # Returns
+/// A library in the units of `lib_a` with the XOR polygons in a cell named after `top`, and
+/// the mismatch per layer/datatype present in either layout.
+pub fn xor_libraries(
+    lib_a: &GdsLibrary,
+    lib_b: &GdsLibrary,
+    top: &str,
+) -> Result<(GdsLibrary, Vec<LayerMismatch>), String> {
+    let (_, meters_per_dbu) = units_pair(lib_a);
+    if units_pair(lib_b) != units_pair(lib_a) {
+        return Err(format!(
+            "database units differ: {:?} and {:?}",
+            units_pair(lib_a),
+            units_pair(lib_b)
+        ));
+    }
+
+    let mut polygons_a = flat_polygons(lib_a, top)?;
+    let mut polygons_b = flat_polygons(lib_b, top)?;
+    let mut layers: Vec<(i16, i16)> = polygons_a.keys().chain(polygons_b.keys()).copied().collect();
+    layers.sort();
+    layers.dedup();
+
+    let um_per_dbu = meters_per_dbu * 1e6;
+    let mut xor_struct = GdsStruct {
+        name: top.to_string(),
+        dates: Default::default(),
+        elems: vec![],
+    };
+    let mut mismatches = vec![];
+    for (layer, datatype) in layers {
+        let merged = |polygons: Option<Vec<Vec<GdsPoint>>>| {
+            let polygons = polygons.unwrap_or_default();
+            merge_polygons(polygons.iter().map(|p| to_polygon(p)).collect())
+        };
+        let merged_a = merged(polygons_a.remove(&(layer, datatype)));
+        let merged_b = merged(polygons_b.remove(&(layer, datatype)));
+        let xor = merged_a.xor(&merged_b);
+
+        let area: f64 = xor.polygons.iter().map(polygon_area).sum();
+        for polygon in &xor.polygons {
+            let (outlines, warnings) = polygon_to_outlines(polygon);
+            for warning in warnings {
+                println!("{}", warning);
+            }
+            for outline in outlines {
+                xor_struct.elems.push(GdsElement::GdsBoundary(GdsBoundary {
+                    layer,
+                    datatype,
+                    xy: closed_points(&outline),
+                    ..Default::default()
+                }));
+            }
+        }
+        mismatches.push(LayerMismatch {
+            layer,
+            datatype,
+            area: area * um_per_dbu * um_per_dbu,
+            polygons: xor.polygons.len(),
+        });
+    }
+
+    let xor_lib = GdsLibrary {
+        name: "XOR".to_string(),
+        version: lib_a.version,
+        units: lib_a.units.clone(),
+        structs: vec![xor_struct],
+        ..Default::default()
+    };
+    Ok((xor_lib, mismatches))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::test_support::{cell, library_in_units, rect, sref};
+
+    fn layout(db_unit: f64, elems: Vec<GdsElement>) -> GdsLibrary {
+        library_in_units("lib", db_unit, vec![cell("top", vec![sref("leaf")]), cell("leaf", elems)])
+    }
+
+    #[test]
+    fn xor_keeps_the_area_covered_by_one_layout_only() {
+        let a = layout(1e-9, vec![rect(0, 0, 2000, 1000)]);
+        let b = layout(1e-9, vec![rect(1000, 0, 3000, 1000)]);
+        let (xor_lib, mismatches) = xor_libraries(&a, &b, "top").unwrap();
+
+        assert_eq!(mismatches.len(), 1);
+        assert_eq!((mismatches[0].layer, mismatches[0].datatype, mismatches[0].polygons), (1, 0, 2));
+        assert!((mismatches[0].area - 2.0).abs() < 1e-9);
+
+        assert_eq!(xor_lib.structs.len(), 1);
+        assert_eq!(xor_lib.structs[0].name, "top");
+        let mut x_ranges: Vec<(i32, i32)> = xor_lib.structs[0]
+            .elems
+            .iter()
+            .map(|e| match e {
+                GdsElement::GdsBoundary(b) => {
+                    let xs = b.xy.iter().map(|p| p.x);
+                    (xs.clone().min().unwrap(), xs.max().unwrap())
+                }
+                _ => panic!("unexpected element {:?}", e),
+            })
+            .collect();
+        x_ranges.sort();
+        assert_eq!(x_ranges, [(0, 1000), (2000, 3000)]);
+    }
+
+    #[test]
+    fn identical_layouts_have_no_mismatch_area() {
+        let a = layout(1e-9, vec![rect(0, 0, 2000, 1000)]);
+        let (xor_lib, mismatches) = xor_libraries(&a, &a, "top").unwrap();
+        assert_eq!((mismatches[0].polygons, mismatches[0].area), (0, 0.0));
+        assert!(xor_lib.structs[0].elems.is_empty());
+    }
+
+    #[test]
+    fn different_database_units_are_an_error() {
+        let a = layout(1e-9, vec![rect(0, 0, 2000, 1000)]);
+        let b = layout(1e-8, vec![rect(0, 0, 200, 100)]);
+        let err = xor_libraries(&a, &b, "top").err().unwrap();
+        assert!(err.starts_with("database units differ"), "{}", err);
+    }
+}
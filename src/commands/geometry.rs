@@ -0,0 +1,789 @@
+use gds21::{
+    GdsBoundary, GdsBox, GdsElement, GdsLibrary, GdsNode, GdsPath, GdsPoint, GdsProperty, GdsStrans, GdsTextElem,
+};
+use iron_shapes::prelude::*;
+use iron_shapes_booleanop::BooleanOp;
+use serde::Serialize;
+
+/// The `(user units per DBU, meters per DBU)` values of the UNITS record of a library.
+pub fn units_pair(lib: &GdsLibrary) -> (f64, f64) {
+    (lib.units.user_unit(), lib.units.db_unit())
+}
+
+/// The placement transformation of a reference, built from its `GdsStrans` and origin.
+///
+/// Points are reflected about the x-axis first, then magnified, rotated counter-clockwise and
+/// finally translated, as the GDSII specification demands.
+///
+/// An absolute magnification or angle, flagged in the STRANS, is not affected by the
+/// placements of the parent cells.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GdsTransform {
+    /// Row-major 2x2 matrix holding reflection, magnification and rotation.
+    pub matrix: [f64; 4],
+    /// Translation applied after the matrix.
+    pub offset: (f64, f64),
+    /// The magnification is absolute.
+    pub abs_mag: bool,
+    /// The angle is absolute.
+    pub abs_angle: bool,
+}
+
+impl Default for GdsTransform {
+    fn default() -> Self {
+        Self::identity()
+    }
+}
+
+impl GdsTransform {
+    /// The transformation leaving every point in place.
+    pub fn identity() -> Self {
+        Self {
+            matrix: [1.0, 0.0, 0.0, 1.0],
+            offset: (0.0, 0.0),
+            abs_mag: false,
+            abs_angle: false,
+        }
+    }
+
+    /// Create the transformation of a reference placed at `origin`.
+    pub fn new(strans: Option<&GdsStrans>, origin: &GdsPoint) -> Self {
+        let (reflected, mag, angle) = strans
+            .map(|s| (s.reflected, s.mag.unwrap_or(1.0), s.angle.unwrap_or(0.0)))
+            .unwrap_or((false, 1.0, 0.0));
+        Self {
+            matrix: linear_matrix(reflected, mag, angle),
+            offset: (origin.x as f64, origin.y as f64),
+            abs_mag: strans.map(|s| s.abs_mag).unwrap_or(false),
+            abs_angle: strans.map(|s| s.abs_angle).unwrap_or(false),
+        }
+    }
+
+    /// A pure translation.
+    pub fn translation(dx: f64, dy: f64) -> Self {
+        Self {
+            offset: (dx, dy),
+            ..Self::identity()
+        }
+    }
+
+    /// Apply the transformation to a point given in floating point coordinates.
+    pub fn apply(&self, x: f64, y: f64) -> (f64, f64) {
+        let [a, b, c, d] = self.matrix;
+        (a * x + b * y + self.offset.0, c * x + d * y + self.offset.1)
+    }
+
+    /// Apply the transformation to a `GdsPoint`, rounding to the database grid.
+    pub fn apply_point(&self, p: &GdsPoint) -> GdsPoint {
+        let (x, y) = self.apply(p.x as f64, p.y as f64);
+        GdsPoint {
+            x: x.round() as i32,
+            y: y.round() as i32,
+        }
+    }
+
+    /// The transformation applying `self` first and `outer` afterwards.
+    ///
+    /// An absolute magnification or angle of `self` replaces the combined one, reflections
+    /// still combine. The result is absolute where either transformation is.
+    pub fn then(&self, outer: &GdsTransform) -> GdsTransform {
+        let [a, b, c, d] = outer.matrix;
+        let [e, f, g, h] = self.matrix;
+        let (x, y) = outer.apply(self.offset.0, self.offset.1);
+        let mut combined = GdsTransform {
+            matrix: [a * e + b * g, a * f + b * h, c * e + d * g, c * f + d * h],
+            offset: (x, y),
+            abs_mag: self.abs_mag || outer.abs_mag,
+            abs_angle: self.abs_angle || outer.abs_angle,
+        };
+        if self.abs_mag || self.abs_angle {
+            let mag = if self.abs_mag { self.magnification() } else { combined.magnification() };
+            let angle = if self.abs_angle { self.rotation() } else { combined.rotation() };
+            combined.matrix = linear_matrix(combined.is_reflected(), mag, angle);
+        }
+        combined
+    }
+
+    /// Whether the transformation mirrors the geometry.
+    pub fn is_reflected(&self) -> bool {
+        let [a, b, c, d] = self.matrix;
+        a * d - b * c < 0.0
+    }
+
+    /// The magnification factor.
+    pub fn magnification(&self) -> f64 {
+        let [a, b, c, d] = self.matrix;
+        (a * d - b * c).abs().sqrt()
+    }
+
+    /// The counter-clockwise rotation in degrees, within `[0, 360)`.
+    pub fn rotation(&self) -> f64 {
+        let angle = self.matrix[2].atan2(self.matrix[0]).to_degrees();
+        let angle = (angle * 1e9).round() / 1e9;
+        if angle < 0.0 {
+            angle + 360.0
+        } else {
+            angle
+        }
+    }
+
+    /// Whether the rotation is a multiple of 90 degrees.
+    pub fn is_orthogonal(&self) -> bool {
+        (self.rotation() % 90.0).abs() < 1e-9
+    }
+
+    /// The translation, rounded to the database grid.
+    pub fn origin(&self) -> GdsPoint {
+        GdsPoint {
+            x: self.offset.0.round() as i32,
+            y: self.offset.1.round() as i32,
+        }
+    }
+
+    /// Express the linear part of the transformation as a `GdsStrans`.
+    ///
+    /// Returns `None` when the transformation is a pure translation.
+    pub fn to_strans(&self) -> Option<GdsStrans> {
+        let reflected = self.is_reflected();
+        let mag = self.magnification();
+        let angle = self.rotation();
+        let has_mag = (mag - 1.0).abs() > 1e-12;
+        let has_angle = angle.abs() > 1e-9;
+        if !reflected && !has_mag && !has_angle && !self.abs_mag && !self.abs_angle {
+            return None;
+        }
+        Some(GdsStrans {
+            reflected,
+            abs_mag: self.abs_mag,
+            abs_angle: self.abs_angle,
+            mag: has_mag.then_some(mag),
+            angle: has_angle.then_some(angle),
+        })
+    }
+}
+
+/// The matrix reflecting about the x-axis, magnifying and then rotating by `angle` degrees.
+fn linear_matrix(reflected: bool, mag: f64, angle: f64) -> [f64; 4] {
+    let (sin, cos) = sin_cos_degrees(angle);
+    let r = if reflected { -1.0 } else { 1.0 };
+    [mag * cos, -mag * sin * r, mag * sin, mag * cos * r]
+}
+
+/// Sine and cosine of an angle in degrees, exact for multiples of 90 degrees.
+fn sin_cos_degrees(angle: f64) -> (f64, f64) {
+    let quarter = angle / 90.0;
+    if quarter.fract() == 0.0 {
+        match (quarter as i64).rem_euclid(4) {
+            0 => (0.0, 1.0),
+            1 => (1.0, 0.0),
+            2 => (0.0, -1.0),
+            _ => (-1.0, 0.0),
+        }
+    } else {
+        angle.to_radians().sin_cos()
+    }
+}
+
+/// An axis-aligned bounding box in database units.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct BoundingBox {
+    pub min_x: i32,
+    pub min_y: i32,
+    pub max_x: i32,
+    pub max_y: i32,
+}
+
+impl BoundingBox {
+    /// The bounding box of a set of points, `None` if there are none.
+    pub fn from_points<'a>(points: impl IntoIterator<Item = &'a GdsPoint>) -> Option<Self> {
+        points.into_iter().fold(None, |bbox: Option<Self>, p| {
+            Some(match bbox {
+                None => Self {
+                    min_x: p.x,
+                    min_y: p.y,
+                    max_x: p.x,
+                    max_y: p.y,
+                },
+                Some(b) => Self {
+                    min_x: b.min_x.min(p.x),
+                    min_y: b.min_y.min(p.y),
+                    max_x: b.max_x.max(p.x),
+                    max_y: b.max_y.max(p.y),
+                },
+            })
+        })
+    }
+
+    /// The smallest box containing both boxes.
+    pub fn union(&self, other: &BoundingBox) -> BoundingBox {
+        BoundingBox {
+            min_x: self.min_x.min(other.min_x),
+            min_y: self.min_y.min(other.min_y),
+            max_x: self.max_x.max(other.max_x),
+            max_y: self.max_y.max(other.max_y),
+        }
+    }
+
+    pub fn width(&self) -> i64 {
+        self.max_x as i64 - self.min_x as i64
+    }
+
+    pub fn height(&self) -> i64 {
+        self.max_y as i64 - self.min_y as i64
+    }
+
+    /// The four corners, counter-clockwise from the lower left.
+    pub fn corners(&self) -> [GdsPoint; 4] {
+        [
+            GdsPoint { x: self.min_x, y: self.min_y },
+            GdsPoint { x: self.max_x, y: self.min_y },
+            GdsPoint { x: self.max_x, y: self.max_y },
+            GdsPoint { x: self.min_x, y: self.max_y },
+        ]
+    }
+
+    /// The bounding box of this box after applying a transformation.
+    pub fn transformed(&self, tf: &GdsTransform) -> BoundingBox {
+        let corners = self.corners().map(|p| tf.apply_point(&p));
+        BoundingBox::from_points(corners.iter()).unwrap()
+    }
+}
+
+/// The bounding box of a geometric element, references are not resolved.
+pub fn element_bbox(elem: &GdsElement) -> Option<BoundingBox> {
+    match elem {
+        GdsElement::GdsBoundary(GdsBoundary { xy, .. }) => BoundingBox::from_points(xy),
+        GdsElement::GdsPath(path) => BoundingBox::from_points(&path_outline(path)),
+        GdsElement::GdsBox(GdsBox { xy, .. }) => BoundingBox::from_points(xy),
+        GdsElement::GdsNode(GdsNode { xy, .. }) => BoundingBox::from_points(xy),
+        GdsElement::GdsTextElem(GdsTextElem { xy, .. }) => BoundingBox::from_points([xy]),
+        GdsElement::GdsStructRef(_) | GdsElement::GdsArrayRef(_) => None,
+    }
+}
+
+/// The polygons an element covers, as open point lists (the first point is not repeated).
+///
+/// Boundaries and boxes yield their outline, paths are expanded into their outline.
+/// Texts, nodes and references cover no area.
+pub fn element_polygons(elem: &GdsElement) -> Vec<Vec<GdsPoint>> {
+    match elem {
+        GdsElement::GdsBoundary(GdsBoundary { xy, .. }) => vec![open_points(xy)],
+        GdsElement::GdsBox(GdsBox { xy, .. }) => vec![open_points(xy)],
+        GdsElement::GdsPath(path) => {
+            let outline = path_outline(path);
+            if outline.is_empty() {
+                vec![]
+            } else {
+                vec![outline]
+            }
+        }
+        _ => vec![],
+    }
+}
+
+/// The layer/datatype of elements covering an area, boxes reporting their boxtype.
+pub fn polygon_layer(element: &GdsElement) -> Option<(i16, i16)> {
+    match element {
+        GdsElement::GdsBoundary(GdsBoundary { layer, datatype, .. }) => Some((*layer, *datatype)),
+        GdsElement::GdsPath(GdsPath { layer, datatype, .. }) => Some((*layer, *datatype)),
+        GdsElement::GdsBox(GdsBox { layer, boxtype, .. }) => Some((*layer, *boxtype)),
+        _ => None,
+    }
+}
+
+/// Drop the closing point of a GDS point list, if it repeats the first one.
+pub fn open_points(xy: &[GdsPoint]) -> Vec<GdsPoint> {
+    let mut points = xy.to_vec();
+    if points.len() > 1 && points.first() == points.last() {
+        points.pop();
+    }
+    points
+}
+
+/// Close a point list by repeating its first point, as GDS boundaries require.
+pub fn closed_points(points: &[GdsPoint]) -> Vec<GdsPoint> {
+    let mut xy = open_points(points);
+    if let Some(first) = xy.first().cloned() {
+        xy.push(first);
+    }
+    xy
+}
+
+/// A boundary on a layer/datatype through `points`, closed by repeating the first point.
+///
+/// Returns `None` for fewer than three points, which enclose no area.
+pub fn closed_boundary(
+    (layer, datatype): (i16, i16),
+    points: &[(i32, i32)],
+    properties: Vec<GdsProperty>,
+) -> Option<GdsElement> {
+    if points.len() < 3 {
+        return None;
+    }
+    let xy: Vec<GdsPoint> = points.iter().map(|&(x, y)| GdsPoint { x, y }).collect();
+    Some(GdsElement::GdsBoundary(GdsBoundary {
+        layer,
+        datatype,
+        xy: closed_points(&xy),
+        properties,
+        ..Default::default()
+    }))
+}
+
+/// The begin and end extensions of a path, in database units.
+///
+/// Round ends (type 1) are approximated by square ends extended by half the width.
+pub fn path_extensions(path: &GdsPath) -> (f64, f64) {
+    let half_width = path.width.unwrap_or(0).abs() as f64 / 2.0;
+    match path.path_type.unwrap_or(0) {
+        1 | 2 => (half_width, half_width),
+        4 => (
+            path.begin_extn.unwrap_or(0) as f64,
+            path.end_extn.unwrap_or(0) as f64,
+        ),
+        _ => (0.0, 0.0),
+    }
+}
+
+/// The outline of a path as a polygon, using mitred joins.
+///
+/// Returns an empty list for paths without width or with fewer than two distinct points.
+pub fn path_outline(path: &GdsPath) -> Vec<GdsPoint> {
+    let half_width = path.width.unwrap_or(0).abs() as f64 / 2.0;
+    let mut points: Vec<(f64, f64)> = vec![];
+    for p in &path.xy {
+        let p = (p.x as f64, p.y as f64);
+        if points.last() != Some(&p) {
+            points.push(p);
+        }
+    }
+    if half_width == 0.0 || points.len() < 2 {
+        return vec![];
+    }
+
+    let direction = |a: (f64, f64), b: (f64, f64)| {
+        let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+        let len = (dx * dx + dy * dy).sqrt();
+        (dx / len, dy / len)
+    };
+
+    // Apply the end extensions along the first and last segment.
+    let (begin_extn, end_extn) = path_extensions(path);
+    let n = points.len();
+    let d0 = direction(points[0], points[1]);
+    points[0] = (points[0].0 - d0.0 * begin_extn, points[0].1 - d0.1 * begin_extn);
+    let dn = direction(points[n - 2], points[n - 1]);
+    points[n - 1] = (points[n - 1].0 + dn.0 * end_extn, points[n - 1].1 + dn.1 * end_extn);
+
+    let mut left = Vec::with_capacity(n);
+    let mut right = Vec::with_capacity(n);
+    for i in 0..n {
+        let d_in = if i > 0 { direction(points[i - 1], points[i]) } else { d0 };
+        let d_out = if i + 1 < n { direction(points[i], points[i + 1]) } else { dn };
+        // Left-hand normals of the incoming and outgoing segments.
+        let n_in = (-d_in.1, d_in.0);
+        let n_out = (-d_out.1, d_out.0);
+        let denom = 1.0 + n_in.0 * n_out.0 + n_in.1 * n_out.1;
+        let offset = if denom.abs() < 1e-9 {
+            (n_in.0 * half_width, n_in.1 * half_width)
+        } else {
+            (
+                (n_in.0 + n_out.0) / denom * half_width,
+                (n_in.1 + n_out.1) / denom * half_width,
+            )
+        };
+        let (x, y) = points[i];
+        left.push(GdsPoint {
+            x: (x + offset.0).round() as i32,
+            y: (y + offset.1).round() as i32,
+        });
+        right.push(GdsPoint {
+            x: (x - offset.0).round() as i32,
+            y: (y - offset.1).round() as i32,
+        });
+    }
+    right.reverse();
+    left.extend(right);
+    left
+}
+
+/// Twice the signed area of a polygon, positive for counter-clockwise orientation.
+pub fn area_doubled(points: &[GdsPoint]) -> i64 {
+    let n = points.len();
+    (0..n)
+        .map(|i| {
+            let a = &points[i];
+            let b = &points[(i + 1) % n];
+            a.x as i64 * b.y as i64 - b.x as i64 * a.y as i64
+        })
+        .sum()
+}
+
+/// Convert an open GDS point list into an iron-shapes polygon.
+pub fn to_polygon(points: &[GdsPoint]) -> Polygon<i64> {
+    let points: Vec<Point<i64>> = open_points(points)
+        .iter()
+        .map(|p| Point::new(p.x as i64, p.y as i64))
+        .collect();
+    Polygon {
+        exterior: SimplePolygon::new(points),
+        interiors: vec![],
+    }
+}
+
+/// Convert an iron-shapes simple polygon back into an open GDS point list.
+pub fn from_simple_polygon(poly: &SimplePolygon<i64>) -> Vec<GdsPoint> {
+    poly.points()
+        .iter()
+        .map(|p| GdsPoint {
+            x: p.x as i32,
+            y: p.y as i32,
+        })
+        .collect()
+}
+
+/// Merge overlapping and touching polygons into their union.
+///
+/// The boolean operations fill each operand by the even-odd rule, so polygons overlapping
+/// within one operand would cancel out instead of merging. The polygons are therefore united
+/// in pairs, every operand already being a clean union, halving their number each round.
+pub fn merge_polygons(polygons: Vec<Polygon<i64>>) -> MultiPolygon<i64> {
+    let mut unions: Vec<MultiPolygon<i64>> = polygons
+        .into_iter()
+        .map(|p| MultiPolygon::from_polygons(vec![p]))
+        .collect();
+    if unions.len() == 1 {
+        // A lone polygon is normalised by a union with nothing.
+        return unions[0].union(&MultiPolygon::from_polygons(vec![]));
+    }
+    while unions.len() > 1 {
+        let mut pairs = unions.into_iter();
+        let mut next = vec![];
+        while let Some(a) = pairs.next() {
+            next.push(match pairs.next() {
+                Some(b) => a.union(&b),
+                None => a,
+            });
+        }
+        unions = next;
+    }
+    unions.pop().unwrap_or_else(|| MultiPolygon::from_polygons(vec![]))
+}
+
+/// The area of a polygon with holes, in square database units.
+pub fn polygon_area(polygon: &Polygon<i64>) -> f64 {
+    let area = |poly: &SimplePolygon<i64>| area_doubled(&from_simple_polygon(poly)).abs() as f64 / 2.0;
+    area(&polygon.exterior) - polygon.interiors.iter().map(area).sum::<f64>()
+}
+
+/// An axis-aligned rectangle as an iron-shapes polygon.
+pub fn rect_polygon(min_x: i64, min_y: i64, max_x: i64, max_y: i64) -> Polygon<i64> {
+    Polygon {
+        exterior: SimplePolygon::new(vec![
+            Point::new(min_x, min_y),
+            Point::new(max_x, min_y),
+            Point::new(max_x, max_y),
+            Point::new(min_x, max_y),
+        ]),
+        interiors: vec![],
+    }
+}
+
+/// The counter-clockwise convex hull of a set of points, by Andrew's monotone chain.
+pub fn convex_hull(mut points: Vec<Point<i64>>) -> Vec<Point<i64>> {
+    points.sort_by_key(|p| (p.x, p.y));
+    points.dedup();
+    if points.len() < 3 {
+        return points;
+    }
+    let cross = |o: &Point<i64>, a: &Point<i64>, b: &Point<i64>| {
+        (a.x - o.x) as i128 * (b.y - o.y) as i128 - (a.y - o.y) as i128 * (b.x - o.x) as i128
+    };
+    let mut hull: Vec<Point<i64>> = vec![];
+    for pass in 0..2 {
+        let start = hull.len();
+        let iter: Box<dyn Iterator<Item = &Point<i64>>> = if pass == 0 {
+            Box::new(points.iter())
+        } else {
+            Box::new(points.iter().rev())
+        };
+        for p in iter {
+            while hull.len() >= start + 2 && cross(&hull[hull.len() - 2], &hull[hull.len() - 1], p) <= 0 {
+                hull.pop();
+            }
+            hull.push(*p);
+        }
+        hull.pop();
+    }
+    hull
+}
+
+/// The bounding box of an iron-shapes simple polygon as `(min_x, min_y, max_x, max_y)`.
+fn simple_polygon_extent(poly: &SimplePolygon<i64>) -> (i64, i64, i64, i64) {
+    poly.points().iter().fold(
+        (i64::MAX, i64::MAX, i64::MIN, i64::MIN),
+        |(x0, y0, x1, y1), p| (x0.min(p.x), y0.min(p.y), x1.max(p.x), y1.max(p.y)),
+    )
+}
+
+/// Split a polygon along a vertical (`vertical == true`) or horizontal line at `at`.
+fn bisect(polygon: &Polygon<i64>, vertical: bool, at: i64) -> Vec<Polygon<i64>> {
+    let (x0, y0, x1, y1) = simple_polygon_extent(&polygon.exterior);
+    let halves = if vertical {
+        [rect_polygon(x0, y0, at, y1), rect_polygon(at, y0, x1, y1)]
+    } else {
+        [rect_polygon(x0, y0, x1, at), rect_polygon(x0, at, x1, y1)]
+    };
+    halves
+        .iter()
+        .flat_map(|half| polygon.intersection(half).polygons)
+        .collect()
+}
+
+/// Convert a polygon into GDS boundary outlines, which cannot have holes.
+///
+/// Polygons with holes are cut along a line through the middle of a hole until no hole is
+/// left, so the pieces cover the polygon without overlaps or gaps.
+///
+/// # Returns
+/// The outlines and a warning for every hole too small to be cut open, which is dropped.
+pub fn polygon_to_outlines(polygon: &Polygon<i64>) -> (Vec<Vec<GdsPoint>>, Vec<String>) {
+    let mut warnings = vec![];
+    let outlines = cut_holes(polygon, &mut warnings);
+    (outlines, warnings)
+}
+
+fn cut_holes(polygon: &Polygon<i64>, warnings: &mut Vec<String>) -> Vec<Vec<GdsPoint>> {
+    let Some(hole) = polygon.interiors.first() else {
+        return vec![from_simple_polygon(&polygon.exterior)];
+    };
+    let (x0, y0, x1, y1) = simple_polygon_extent(hole);
+    // A line strictly inside the extent of the hole crosses its interior and opens it up.
+    let pieces = if x1 - x0 >= 2 {
+        bisect(polygon, true, (x0 + x1) / 2)
+    } else if y1 - y0 >= 2 {
+        bisect(polygon, false, (y0 + y1) / 2)
+    } else {
+        warnings.push(format!("Dropping a hole of less than two database units at ({}, {})", x0, y0));
+        let mut rest = polygon.clone();
+        rest.interiors.remove(0);
+        vec![rest]
+    };
+    pieces.iter().flat_map(|piece| cut_holes(piece, warnings)).collect()
+}
+
+/// GDS boundaries covering a set of polygons, holes cut open as by [`polygon_to_outlines`].
+///
+/// # Returns
+/// The boundaries and the warnings about dropped holes.
+pub fn polygons_to_boundaries(
+    polygons: &MultiPolygon<i64>,
+    layer: i16,
+    datatype: i16,
+) -> (Vec<GdsElement>, Vec<String>) {
+    let mut warnings = vec![];
+    let boundaries = polygons
+        .polygons
+        .iter()
+        .flat_map(|polygon| cut_holes(polygon, &mut warnings))
+        .map(|outline| {
+            GdsElement::GdsBoundary(GdsBoundary {
+                layer,
+                datatype,
+                xy: closed_points(&outline),
+                ..Default::default()
+            })
+        })
+        .collect();
+    (boundaries, warnings)
+}
+
+/// Cut a polygon into hole-free outlines of at most `max_vertices` vertices each.
+///
+/// Outlines with too many vertices are bisected through the middle of their longer extent,
+/// so the pieces still cover the polygon without overlaps or gaps.
+///
+/// # Returns
+/// The outlines and warnings about dropped holes and outlines too small to be split, which
+/// are kept as they are.
+pub fn split_polygon(polygon: &Polygon<i64>, max_vertices: usize) -> (Vec<Vec<GdsPoint>>, Vec<String>) {
+    let mut warnings = vec![];
+    let outlines = split_outlines(polygon, max_vertices, &mut warnings);
+    (outlines, warnings)
+}
+
+fn split_outlines(polygon: &Polygon<i64>, max_vertices: usize, warnings: &mut Vec<String>) -> Vec<Vec<GdsPoint>> {
+    let mut result = vec![];
+    for outline in cut_holes(polygon, warnings) {
+        if outline.len() <= max_vertices {
+            result.push(outline);
+            continue;
+        }
+        let piece = to_polygon(&outline);
+        let (x0, y0, x1, y1) = simple_polygon_extent(&piece.exterior);
+        let halves = if x1 - x0 >= y1 - y0 && x1 - x0 >= 2 {
+            bisect(&piece, true, (x0 + x1) / 2)
+        } else if y1 - y0 >= 2 {
+            bisect(&piece, false, (y0 + y1) / 2)
+        } else {
+            warnings.push(format!("Cannot split a polygon of {} vertices at ({}, {})", outline.len(), x0, y0));
+            result.push(outline);
+            continue;
+        };
+        result.extend(halves.iter().flat_map(|half| split_outlines(half, max_vertices, warnings)));
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn xy(points: &[GdsPoint]) -> Vec<(i32, i32)> {
+        points.iter().map(|p| (p.x, p.y)).collect()
+    }
+
+    fn path(points: &[(i32, i32)], width: i32, path_type: i16) -> GdsPath {
+        GdsPath {
+            xy: points.iter().map(|&(x, y)| GdsPoint { x, y }).collect(),
+            width: Some(width),
+            path_type: Some(path_type),
+            ..Default::default()
+        }
+    }
+
+    fn total_area(outlines: &[Vec<GdsPoint>]) -> i64 {
+        outlines.iter().map(|o| area_doubled(o).abs()).sum::<i64>() / 2
+    }
+
+    fn strans(reflected: bool, mag: f64, angle: f64, abs_mag: bool, abs_angle: bool) -> GdsStrans {
+        GdsStrans {
+            reflected,
+            abs_mag,
+            abs_angle,
+            mag: Some(mag),
+            angle: Some(angle),
+        }
+    }
+
+    #[test]
+    fn transform_reflects_magnifies_rotates_and_translates() {
+        let tf = GdsTransform::new(Some(&strans(true, 2.0, 90.0, false, false)), &GdsPoint { x: 100, y: 0 });
+        // (1, 2) reflects to (1, -2), magnifies to (2, -4) and rotates to (4, 2).
+        assert_eq!(tf.apply(1.0, 2.0), (104.0, 2.0));
+        assert!(tf.is_reflected());
+        assert_eq!((tf.magnification(), tf.rotation()), (2.0, 90.0));
+    }
+
+    #[test]
+    fn transforms_compose() {
+        let parent = GdsTransform::new(Some(&strans(false, 3.0, 90.0, false, false)), &GdsPoint { x: 100, y: 0 });
+        let child = GdsTransform::new(Some(&strans(false, 2.0, 90.0, false, false)), &GdsPoint { x: 10, y: 0 });
+        let combined = child.then(&parent);
+        assert_eq!(combined.origin(), GdsPoint { x: 100, y: 30 });
+        assert_eq!((combined.magnification(), combined.rotation()), (6.0, 180.0));
+        assert_eq!(combined.to_strans().map(|s| (s.abs_mag, s.abs_angle)), Some((false, false)));
+    }
+
+    #[test]
+    fn absolute_magnification_and_angle_ignore_the_parent() {
+        let parent = GdsTransform::new(Some(&strans(true, 3.0, 90.0, false, false)), &GdsPoint { x: 100, y: 0 });
+        let child = GdsTransform::new(Some(&strans(false, 2.0, 0.0, true, true)), &GdsPoint { x: 10, y: 0 });
+        let combined = child.then(&parent);
+        // The placement point still follows the parent, the linear part only its reflection.
+        assert_eq!(combined.origin(), GdsPoint { x: 100, y: 30 });
+        assert!(combined.is_reflected());
+        assert_eq!((combined.magnification(), combined.rotation()), (2.0, 0.0));
+        let flags = combined.to_strans().unwrap();
+        assert!(flags.abs_mag && flags.abs_angle);
+
+        // A relative grandchild inherits the absolute magnification of its parent.
+        let grandchild = GdsTransform::new(Some(&strans(false, 5.0, 0.0, false, false)), &GdsPoint { x: 0, y: 0 });
+        let outer = GdsTransform::new(Some(&strans(false, 7.0, 0.0, false, false)), &GdsPoint { x: 0, y: 0 });
+        assert_eq!(grandchild.then(&combined).then(&outer).magnification(), 10.0);
+    }
+
+    #[test]
+    fn path_outline_of_flush_and_extended_ends() {
+        let flush = path(&[(0, 0), (100, 0)], 20, 0);
+        assert_eq!(xy(&path_outline(&flush)), vec![(0, 10), (100, 10), (100, -10), (0, -10)]);
+        let extended = path(&[(0, 0), (100, 0)], 20, 2);
+        assert_eq!(xy(&path_outline(&extended)), vec![(-10, 10), (110, 10), (110, -10), (-10, -10)]);
+        let custom = GdsPath {
+            begin_extn: Some(5),
+            end_extn: Some(0),
+            ..path(&[(0, 0), (100, 0)], 20, 4)
+        };
+        assert_eq!(xy(&path_outline(&custom)), vec![(-5, 10), (100, 10), (100, -10), (-5, -10)]);
+    }
+
+    #[test]
+    fn path_outline_mitres_corners() {
+        let bent = path(&[(0, 0), (100, 0), (100, 100)], 20, 0);
+        assert_eq!(
+            xy(&path_outline(&bent)),
+            vec![(0, 10), (90, 10), (90, 100), (110, 100), (110, -10), (0, -10)]
+        );
+    }
+
+    #[test]
+    fn path_outline_of_degenerate_paths_is_empty() {
+        assert!(path_outline(&path(&[(0, 0), (100, 0)], 0, 0)).is_empty());
+        assert!(path_outline(&path(&[(5, 5), (5, 5)], 20, 0)).is_empty());
+    }
+
+    #[test]
+    fn merge_polygons_unites_overlaps() {
+        let merged = merge_polygons(vec![
+            rect_polygon(0, 0, 10, 10),
+            rect_polygon(5, 0, 15, 10),
+            rect_polygon(0, 0, 10, 10),
+        ]);
+        assert_eq!(merged.polygons.len(), 1);
+        assert_eq!(merged.polygons.iter().map(polygon_area).sum::<f64>(), 150.0);
+        assert!(merge_polygons(vec![]).polygons.is_empty());
+    }
+
+    #[test]
+    fn polygon_to_outlines_cuts_holes_open() {
+        let ring = rect_polygon(0, 0, 30, 30).difference(&rect_polygon(10, 10, 20, 20));
+        assert_eq!(ring.polygons.len(), 1);
+        let (outlines, warnings) = polygon_to_outlines(&ring.polygons[0]);
+        assert!(outlines.len() >= 2);
+        assert_eq!(total_area(&outlines), 800);
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn polygon_to_outlines_reports_dropped_holes() {
+        let ring = rect_polygon(0, 0, 30, 30).difference(&rect_polygon(10, 10, 11, 11));
+        let (outlines, warnings) = polygon_to_outlines(&ring.polygons[0]);
+        assert_eq!(total_area(&outlines), 900);
+        assert_eq!(warnings.len(), 1);
+    }
+
+    #[test]
+    fn split_polygon_respects_the_vertex_limit() {
+        // A comb with 10 teeth has 40 vertices.
+        let mut points = vec![(0, 0)];
+        for i in 0..10 {
+            points.extend([(i * 20, 10), (i * 20 + 10, 10), (i * 20 + 10, 0), (i * 20 + 20, 0)]);
+        }
+        points.extend([(200, -10), (0, -10)]);
+        let comb: Vec<GdsPoint> = points.iter().map(|&(x, y)| GdsPoint { x, y }).collect();
+        let (outlines, warnings) = split_polygon(&to_polygon(&comb), 8);
+        assert!(outlines.iter().all(|o| o.len() <= 8));
+        assert_eq!(total_area(&outlines), area_doubled(&comb).abs() / 2);
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn convex_hull_drops_interior_and_collinear_points() {
+        let points = [(0, 0), (10, 0), (5, 0), (10, 10), (0, 10), (5, 5), (0, 0)]
+            .iter()
+            .map(|&(x, y)| Point::new(x, y))
+            .collect();
+        let hull: Vec<(i64, i64)> = convex_hull(points).iter().map(|p| (p.x, p.y)).collect();
+        assert_eq!(hull, [(0, 0), (10, 0), (10, 10), (0, 10)]);
+    }
+}
@@ -0,0 +1,192 @@
+use std::collections::{HashMap, HashSet};
+
+use gds21::{GdsArrayRef, GdsElement, GdsLibrary, GdsStruct, GdsStructRef};
+
+use super::geometry::{element_bbox, BoundingBox, GdsTransform};
+
+/// Names of the cells placed by the SREFs and AREFs of a struct, in element order.
+pub fn referenced_cells(gds_struct: &GdsStruct) -> impl Iterator<Item = &str> {
+    gds_struct.elems.iter().filter_map(|element| match element {
+        GdsElement::GdsStructRef(GdsStructRef { name, .. }) => Some(name.as_str()),
+        GdsElement::GdsArrayRef(GdsArrayRef { name, .. }) => Some(name.as_str()),
+        _ => None,
+    })
+}
+
+/// Index the structs of a library by name.
+pub fn structs_by_name(lib: &GdsLibrary) -> HashMap<&str, &GdsStruct> {
+    lib.structs.iter().map(|s| (s.name.as_str(), s)).collect()
+}
+
+/// The cells that are not placed by any other cell, in library order.
+pub fn top_cells(lib: &GdsLibrary) -> Vec<String> {
+    let referenced: HashSet<&str> = lib.structs.iter().flat_map(referenced_cells).collect();
+    lib.structs
+        .iter()
+        .filter(|s| !referenced.contains(s.name.as_str()))
+        .map(|s| s.name.clone())
+        .collect()
+}
+
+/// The cells reachable from the given top cells, tops included.
+///
+/// Cells are returned in depth-first pre-order, each one once. References to cells missing
+/// from the library are reported as an error.
+pub fn reachable_cells(lib: &GdsLibrary, tops: &[&str]) -> Result<Vec<String>, String> {
+    let by_name = structs_by_name(lib);
+    let mut visited: HashSet<&str> = HashSet::new();
+    let mut order: Vec<String> = vec![];
+    let mut stack: Vec<&str> = tops.iter().rev().copied().collect();
+
+    while let Some(name) = stack.pop() {
+        if !visited.insert(name) {
+            continue;
+        }
+        let gds_struct: &GdsStruct = *by_name
+            .get(name)
+            .ok_or_else(|| format!("cell {} is not defined in the library", name))?;
+        order.push(name.to_string());
+        let children: Vec<&str> = referenced_cells(gds_struct).collect();
+        stack.extend(children.into_iter().rev().filter(|c| !visited.contains(c)));
+    }
+    Ok(order)
+}
+
+/// The number of hierarchy levels below each cell, leaf cells having depth 0.
+///
+/// References to missing cells do not add a level; reference cycles are cut where detected.
+pub fn hierarchy_depths(lib: &GdsLibrary) -> HashMap<String, usize> {
+    fn depth<'a>(
+        name: &'a str,
+        by_name: &HashMap<&'a str, &'a GdsStruct>,
+        depths: &mut HashMap<String, usize>,
+        visiting: &mut HashSet<&'a str>,
+    ) -> usize {
+        if let Some(d) = depths.get(name) {
+            return *d;
+        }
+        let Some(&gds_struct) = by_name.get(name) else {
+            return 0;
+        };
+        if !visiting.insert(name) {
+            return 0;
+        }
+        let d = referenced_cells(gds_struct)
+            .filter(|child| by_name.contains_key(child))
+            .map(|child| 1 + depth(child, by_name, depths, visiting))
+            .max()
+            .unwrap_or(0);
+        visiting.remove(name);
+        depths.insert(name.to_string(), d);
+        d
+    }
+
+    let by_name = structs_by_name(lib);
+    let mut depths = HashMap::new();
+    let mut visiting = HashSet::new();
+    for s in &lib.structs {
+        depth(&s.name, &by_name, &mut depths, &mut visiting);
+    }
+    depths
+}
+
+/// The placement transformations of a reference, one per array member for AREFs.
+pub fn reference_transforms(element: &GdsElement) -> Vec<GdsTransform> {
+    match element {
+        GdsElement::GdsStructRef(GdsStructRef { xy, strans, .. }) => {
+            vec![GdsTransform::new(strans.as_ref(), xy)]
+        }
+        GdsElement::GdsArrayRef(GdsArrayRef { xy, cols, rows, strans, .. }) => {
+            let (cols, rows) = ((*cols).max(1) as i64, (*rows).max(1) as i64);
+            // xy holds the origin, the displacement after `cols` columns and after `rows` rows.
+            let col_step = (
+                (xy[1].x as i64 - xy[0].x as i64) as f64 / cols as f64,
+                (xy[1].y as i64 - xy[0].y as i64) as f64 / cols as f64,
+            );
+            let row_step = (
+                (xy[2].x as i64 - xy[0].x as i64) as f64 / rows as f64,
+                (xy[2].y as i64 - xy[0].y as i64) as f64 / rows as f64,
+            );
+            let base = GdsTransform::new(strans.as_ref(), &xy[0]);
+            let mut transforms = Vec::with_capacity((cols * rows) as usize);
+            for row in 0..rows {
+                for col in 0..cols {
+                    let dx = col_step.0 * col as f64 + row_step.0 * row as f64;
+                    let dy = col_step.1 * col as f64 + row_step.1 * row as f64;
+                    transforms.push(base.then(&GdsTransform::translation(dx.round(), dy.round())));
+                }
+            }
+            transforms
+        }
+        _ => vec![],
+    }
+}
+
+/// The bounding box of every cell, including the geometry of the cells it places.
+///
+/// Cells without any geometry map to `None`.
+pub fn cell_bounding_boxes(lib: &GdsLibrary) -> HashMap<String, Option<BoundingBox>> {
+    fn bbox<'a>(
+        name: &'a str,
+        by_name: &HashMap<&'a str, &'a GdsStruct>,
+        bboxes: &mut HashMap<String, Option<BoundingBox>>,
+        visiting: &mut HashSet<&'a str>,
+    ) -> Option<BoundingBox> {
+        if let Some(b) = bboxes.get(name) {
+            return *b;
+        }
+        let gds_struct: &'a GdsStruct = *by_name.get(name)?;
+        if !visiting.insert(name) {
+            return None;
+        }
+        let mut result: Option<BoundingBox> = None;
+        let mut extend = |b: BoundingBox| {
+            result = Some(result.map(|r| r.union(&b)).unwrap_or(b));
+        };
+        for element in &gds_struct.elems {
+            match element {
+                GdsElement::GdsStructRef(GdsStructRef { name: child, .. })
+                | GdsElement::GdsArrayRef(GdsArrayRef { name: child, .. }) => {
+                    if let Some(child_bbox) = bbox(child, by_name, bboxes, visiting) {
+                        for tf in reference_transforms(element) {
+                            extend(child_bbox.transformed(&tf));
+                        }
+                    }
+                }
+                _ => {
+                    if let Some(b) = element_bbox(element) {
+                        extend(b);
+                    }
+                }
+            }
+        }
+        visiting.remove(name);
+        bboxes.insert(name.to_string(), result);
+        result
+    }
+
+    let by_name = structs_by_name(lib);
+    let mut bboxes = HashMap::new();
+    let mut visiting = HashSet::new();
+    for s in &lib.structs {
+        bbox(&s.name, &by_name, &mut bboxes, &mut visiting);
+    }
+    bboxes
+}
+
+/// Point the SREFs and AREFs of a struct to new cell names.
+///
+/// References to cells missing from `renames` are left untouched.
+pub fn rename_references(gds_struct: &mut GdsStruct, renames: &HashMap<String, String>) {
+    for element in &mut gds_struct.elems {
+        match element {
+            GdsElement::GdsStructRef(GdsStructRef { name, .. })
+            | GdsElement::GdsArrayRef(GdsArrayRef { name, .. }) => {
+                if let Some(new_name) = renames.get(name.as_str()) {
+                    *name = new_name.clone();
+                }
+            }
+            _ => {}
+        }
+    }
+}
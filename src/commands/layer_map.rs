@@ -0,0 +1,219 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::path::Path;
+
+use regex::Regex;
+
+/// A GDS layer/datatype pair.
+pub type GdsLayer = (i16, i16);
+
+/// Parse a layer written as `layer/datatype`, or just `layer` for datatype 0.
+pub fn parse_gds_layer(s: &str) -> Result<GdsLayer, String> {
+    let s = s.trim();
+    let (layer, datatype) = s.split_once('/').unwrap_or((s, "0"));
+    let parse = |v: &str| {
+        v.trim()
+            .parse::<i16>()
+            .map_err(|_| format!("invalid GDS layer {}, expected layer/datatype", s))
+    };
+    Ok((parse(layer)?, parse(datatype)?))
+}
+
+/// Mapping from LEF/DEF layer names to GDS layers.
+///
+/// Read from a KLayout `lefdef-to-gds` mapping such as `resources/layer_map.xml`, or from
+/// a text file with one `NAME layer/datatype` pair per line.
+///
+/// Besides LEF layers, `def2gds` looks up names such as `OUTLINE` or `M1.PIN` for DEF
+/// objects, see [`super::def_objects`].
+#[derive(Clone, Debug, Default)]
+pub struct LayerMap {
+    pub layers: BTreeMap<String, GdsLayer>,
+}
+
+impl LayerMap {
+    /// Read a layer map, choosing the format by the `.xml` extension.
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let text = std::fs::read_to_string(&path)?;
+        let is_xml = path
+            .as_ref()
+            .extension()
+            .map(|e| e.eq_ignore_ascii_case("xml"))
+            .unwrap_or(false);
+        if is_xml {
+            Ok(Self::from_xml(&text)?)
+        } else {
+            Ok(Self::from_text(&text)?)
+        }
+    }
+
+    /// Parse the `<map lef-layer=".." gds-layer=".." gds-datatype=".."/>` entries of a
+    /// KLayout mapping, ignoring everything else.
+    pub fn from_xml(text: &str) -> Result<Self, String> {
+        let map_re = Regex::new(r"<map\s([^>]*?)/?>").unwrap();
+        let attr_re = Regex::new(r#"([\w-]+)\s*=\s*"([^"]*)""#).unwrap();
+        let mut layers = BTreeMap::new();
+        for map in map_re.captures_iter(text) {
+            let attrs: BTreeMap<&str, &str> = attr_re
+                .captures_iter(map.get(1).unwrap().as_str())
+                .map(|c| (c.get(1).unwrap().as_str(), c.get(2).unwrap().as_str()))
+                .collect();
+            let (Some(name), Some(layer)) = (attrs.get("lef-layer"), attrs.get("gds-layer")) else {
+                return Err(format!("incomplete layer map entry {}", &map[0]));
+            };
+            let datatype = attrs.get("gds-datatype").unwrap_or(&"0");
+            layers.insert(name.to_string(), parse_gds_layer(&format!("{}/{}", layer, datatype))?);
+        }
+        Ok(Self { layers })
+    }
+
+    /// Parse a text layer map with `NAME layer/datatype` per line and `#` comments.
+    pub fn from_text(text: &str) -> Result<Self, String> {
+        let mut layers = BTreeMap::new();
+        for (i, line) in table_lines(text) {
+            let mut fields = line.split_whitespace();
+            let (Some(name), Some(layer), None) = (fields.next(), fields.next(), fields.next())
+            else {
+                return Err(format!("line {}: expected NAME layer/datatype", i));
+            };
+            layers.insert(name.to_string(), parse_gds_layer(layer)?);
+        }
+        Ok(Self { layers })
+    }
+
+    /// The GDS layer of a LEF/DEF layer name.
+    pub fn get(&self, name: &str) -> Option<GdsLayer> {
+        self.layers.get(name).copied()
+    }
+}
+
+/// A table translating GDS layers to other GDS layers.
+pub type LayerTable = BTreeMap<GdsLayer, GdsLayer>;
+
+/// Read a layer translation table, see [`parse_layer_table`].
+pub fn read_layer_table<P: AsRef<Path>>(path: P) -> Result<LayerTable, Box<dyn Error>> {
+    let text = std::fs::read_to_string(path)?;
+    Ok(parse_layer_table(&text)?)
+}
+
+/// Parse a layer translation table.
+///
+/// Text tables hold one `from to` pair per line, for example `10/0 : 20/0` or `10/0 20/0`,
+/// with `#` comments.
+pub fn parse_layer_table(text: &str) -> Result<LayerTable, String> {
+    let mut table = LayerTable::new();
+    for (i, line) in table_lines(text) {
+        let fields: Vec<&str> = line
+            .split(|c: char| c.is_whitespace() || c == ':' || c == ',')
+            .filter(|f| !f.is_empty())
+            .collect();
+        let [from, to] = fields.as_slice() else {
+            return Err(format!("line {}: expected a pair of layer/datatype", i));
+        };
+        let from = parse_gds_layer(from)?;
+        if table.insert(from, parse_gds_layer(to)?).is_some() {
+            return Err(format!("line {}: layer {}/{} is mapped twice", i, from.0, from.1));
+        }
+    }
+    Ok(table)
+}
+
+/// Translate between two layer maps, matching their layer names.
+///
+/// Names only present in `from` are reported as an error, so no layer is silently kept.
+pub fn layer_table_between(from: &LayerMap, to: &LayerMap) -> Result<LayerTable, String> {
+    let mut table = LayerTable::new();
+    for (name, old) in &from.layers {
+        let new = to
+            .get(name)
+            .ok_or_else(|| format!("layer {} is missing from the target layer map", name))?;
+        if let Some(other) = table.insert(*old, new) {
+            if other != new {
+                return Err(format!(
+                    "layer {}/{} maps to both {}/{} and {}/{}",
+                    old.0, old.1, other.0, other.1, new.0, new.1
+                ));
+            }
+        }
+    }
+    Ok(table)
+}
+
+/// The non-empty lines of a table file with comments stripped, numbered from 1.
+fn table_lines(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    text.lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.split('#').next().unwrap().trim()))
+        .filter(|(_, line)| !line.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gds_layers_default_to_datatype_zero() {
+        assert_eq!(parse_gds_layer("31/5"), Ok((31, 5)));
+        assert_eq!(parse_gds_layer(" 31 "), Ok((31, 0)));
+        assert!(parse_gds_layer("M1").is_err());
+        assert!(parse_gds_layer("31/x").is_err());
+        assert!(parse_gds_layer("40000/0").is_err());
+    }
+
+    #[test]
+    fn xml_maps_read_the_map_entries() {
+        let map = LayerMap::from_xml(
+            r#"<layer-map>
+                <map lef-layer="M1" gds-layer="31" gds-datatype="0"/>
+                <map gds-datatype="2" lef-layer="M1.PIN" gds-layer="31">
+                <map lef-layer="OUTLINE" gds-layer="235"/>
+                <other lef-layer="IGNORED" gds-layer="1"/>
+            </layer-map>"#,
+        )
+        .unwrap();
+        assert_eq!(map.get("M1"), Some((31, 0)));
+        assert_eq!(map.get("M1.PIN"), Some((31, 2)));
+        assert_eq!(map.get("OUTLINE"), Some((235, 0)));
+        assert_eq!(map.layers.len(), 3);
+
+        assert!(LayerMap::from_xml(r#"<map lef-layer="M1"/>"#).is_err());
+    }
+
+    #[test]
+    fn text_maps_skip_comments_and_blank_lines() {
+        let map = LayerMap::from_text("# name layer\nM1 31/0\n\nVIA1 51 # cut\n").unwrap();
+        assert_eq!(map.get("M1"), Some((31, 0)));
+        assert_eq!(map.get("VIA1"), Some((51, 0)));
+        assert_eq!(map.get("M2"), None);
+
+        let err = LayerMap::from_text("M1 31/0\nM2 32/0 extra\n").unwrap_err();
+        assert!(err.starts_with("line 2"), "{}", err);
+    }
+
+    #[test]
+    fn layer_tables_accept_several_separators() {
+        let table = parse_layer_table("10/0 : 20/0\n11/0 21/1 # comment\n12,22\n").unwrap();
+        assert_eq!(
+            table.into_iter().collect::<Vec<_>>(),
+            [((10, 0), (20, 0)), ((11, 0), (21, 1)), ((12, 0), (22, 0))]
+        );
+        assert!(parse_layer_table("10/0 20/0\n10/0 30/0\n").unwrap_err().contains("mapped twice"));
+        assert!(parse_layer_table("10/0\n").is_err());
+    }
+
+    #[test]
+    fn tables_between_maps_match_layer_names() {
+        let from = LayerMap::from_text("M1 31/0\nM2 32/0\n").unwrap();
+        let to = LayerMap::from_text("M1 11/0\nM2 12/0\nM3 13/0\n").unwrap();
+        let table = layer_table_between(&from, &to).unwrap();
+        assert_eq!(table.get(&(31, 0)), Some(&(11, 0)));
+        assert_eq!(table.get(&(32, 0)), Some(&(12, 0)));
+
+        let err = layer_table_between(&to, &from).unwrap_err();
+        assert!(err.contains("M3"), "{}", err);
+
+        let from = LayerMap::from_text("M1 31/0\nM1.PIN 31/0\n").unwrap();
+        let to = LayerMap::from_text("M1 11/0\nM1.PIN 11/2\n").unwrap();
+        assert!(layer_table_between(&from, &to).is_err());
+    }
+}
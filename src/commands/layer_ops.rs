@@ -0,0 +1,255 @@
+use std::collections::{BTreeMap, HashSet};
+use std::path::PathBuf;
+
+use gds21::{GdsBoundary, GdsBox, GdsElement, GdsLibrary, GdsNode, GdsPath, GdsTextElem};
+use regex::RegexSet;
+
+use super::hierarchy::reachable_cells;
+use super::layer_map::{GdsLayer, LayerTable};
+use super::vertex_limit::save_library;
+
+/// An edit applied to the layers of every selected element.
+#[derive(Clone, Debug)]
+pub enum LayerOperation {
+    /// Move elements to the layer given by the table, elements on other layers stay put.
+    Remap(LayerTable),
+    /// Add a copy of every element on the first layer to the second.
+    Copy(GdsLayer, GdsLayer),
+    /// Remove every element on the given layers.
+    Delete(Vec<GdsLayer>),
+}
+
+/// Element counts on one layer/datatype, over the whole library.
+#[derive(Debug, Default)]
+pub struct LayerUsage {
+    pub boundaries: usize,
+    pub paths: usize,
+    pub texts: usize,
+    pub nodes: usize,
+    pub boxes: usize,
+    /// Number of cells with at least one element on the layer.
+    pub cells: usize,
+}
+
+/// Apply a layer operation to a GDS file.
+///
+/// # Arguments
+/// * `input` - Path to the GDS file
+/// * `output` - Output GDS file path
+/// * `operation` - The remap, copy or delete to apply
+/// * `top` - Only edit the cells reachable from this cell, all cells if `None`
+/// * `patterns` - Regexes a cell name must match to be edited
+/// * `max_points` - Most XY points written per boundary or path
+pub fn edit_gds_layers(
+    input: &PathBuf,
+    output: &PathBuf,
+    operation: &LayerOperation,
+    top: Option<&str>,
+    patterns: Option<Vec<&str>>,
+    max_points: usize,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut lib = GdsLibrary::load(input)?;
+    let re = RegexSet::new(patterns.unwrap_or(vec![".*"]).into_iter())?;
+    let cells: Option<HashSet<String>> = match top {
+        Some(top) => Some(reachable_cells(&lib, &[top])?.into_iter().collect()),
+        None => None,
+    };
+    let mut edited = 0;
+    for s in &mut lib.structs {
+        let selected = cells.as_ref().map(|c| c.contains(&s.name)).unwrap_or(true);
+        if selected && re.matches(&s.name).matched_any() {
+            edited += edit_layers(&mut s.elems, operation);
+        }
+    }
+    println!("Edited {} elements", edited);
+    save_library(&mut lib, output, max_points)?;
+    Ok(())
+}
+
+/// Apply a layer operation to a list of elements.
+///
+/// # Returns
+/// The number of elements moved, added or removed.
+pub fn edit_layers(elems: &mut Vec<GdsElement>, operation: &LayerOperation) -> usize {
+    match operation {
+        LayerOperation::Remap(table) => {
+            let mut count = 0;
+            for element in elems.iter_mut() {
+                if let Some(layer) = element_layer_mut(element) {
+                    if let Some(new_layer) = table.get(&(*layer.0, *layer.1)) {
+                        (*layer.0, *layer.1) = *new_layer;
+                        count += 1;
+                    }
+                }
+            }
+            count
+        }
+        LayerOperation::Copy(from, to) => {
+            let copies: Vec<GdsElement> = elems
+                .iter()
+                .filter(|element| element_layer(element) == Some(*from))
+                .map(|element| {
+                    let mut copy = element.clone();
+                    if let Some(layer) = element_layer_mut(&mut copy) {
+                        (*layer.0, *layer.1) = *to;
+                    }
+                    copy
+                })
+                .collect();
+            let count = copies.len();
+            elems.extend(copies);
+            count
+        }
+        LayerOperation::Delete(layers) => {
+            let before = elems.len();
+            elems.retain(|element| {
+                element_layer(element)
+                    .map(|layer| !layers.contains(&layer))
+                    .unwrap_or(true)
+            });
+            before - elems.len()
+        }
+    }
+}
+
+/// The layer of an element together with its datatype, texttype, nodetype or boxtype.
+///
+/// References have no layer.
+pub fn element_layer(element: &GdsElement) -> Option<GdsLayer> {
+    match element {
+        GdsElement::GdsBoundary(GdsBoundary { layer, datatype, .. })
+        | GdsElement::GdsPath(GdsPath { layer, datatype, .. }) => Some((*layer, *datatype)),
+        GdsElement::GdsTextElem(GdsTextElem { layer, texttype, .. }) => Some((*layer, *texttype)),
+        GdsElement::GdsNode(GdsNode { layer, nodetype, .. }) => Some((*layer, *nodetype)),
+        GdsElement::GdsBox(GdsBox { layer, boxtype, .. }) => Some((*layer, *boxtype)),
+        GdsElement::GdsStructRef(_) | GdsElement::GdsArrayRef(_) => None,
+    }
+}
+
+/// Mutable access to the layer fields reported by [`element_layer`].
+pub fn element_layer_mut(element: &mut GdsElement) -> Option<(&mut i16, &mut i16)> {
+    match element {
+        GdsElement::GdsBoundary(GdsBoundary { layer, datatype, .. })
+        | GdsElement::GdsPath(GdsPath { layer, datatype, .. }) => Some((layer, datatype)),
+        GdsElement::GdsTextElem(GdsTextElem { layer, texttype, .. }) => Some((layer, texttype)),
+        GdsElement::GdsNode(GdsNode { layer, nodetype, .. }) => Some((layer, nodetype)),
+        GdsElement::GdsBox(GdsBox { layer, boxtype, .. }) => Some((layer, boxtype)),
+        GdsElement::GdsStructRef(_) | GdsElement::GdsArrayRef(_) => None,
+    }
+}
+
+/// Print the layers used in a GDS file with their element counts.
+pub fn print_layers(input: &PathBuf) -> Result<BTreeMap<GdsLayer, LayerUsage>, Box<dyn std::error::Error>> {
+    let lib = GdsLibrary::load(input)?;
+    let usage = layer_usage(&lib);
+    println!(
+        "{:>9}  {:>9}  {:>9}  {:>9}  {:>9}  {:>9}  {:>9}",
+        "Layer", "Boundary", "Path", "Text", "Node", "Box", "Cells"
+    );
+    for ((layer, datatype), u) in &usage {
+        println!(
+            "{:>9}  {:>9}  {:>9}  {:>9}  {:>9}  {:>9}  {:>9}",
+            format!("{}/{}", layer, datatype),
+            u.boundaries,
+            u.paths,
+            u.texts,
+            u.nodes,
+            u.boxes,
+            u.cells
+        );
+    }
+    Ok(usage)
+}
+
+/// Count the elements on every layer of a library, cell definitions are not expanded.
+pub fn layer_usage(lib: &GdsLibrary) -> BTreeMap<GdsLayer, LayerUsage> {
+    let mut usage: BTreeMap<GdsLayer, LayerUsage> = BTreeMap::new();
+    for s in &lib.structs {
+        let mut seen: HashSet<GdsLayer> = HashSet::new();
+        for element in &s.elems {
+            let Some(key) = element_layer(element) else {
+                continue;
+            };
+            let u = usage.entry(key).or_default();
+            match element {
+                GdsElement::GdsBoundary(_) => u.boundaries += 1,
+                GdsElement::GdsPath(_) => u.paths += 1,
+                GdsElement::GdsTextElem(_) => u.texts += 1,
+                GdsElement::GdsNode(_) => u.nodes += 1,
+                GdsElement::GdsBox(_) => u.boxes += 1,
+                _ => {}
+            }
+            if seen.insert(key) {
+                u.cells += 1;
+            }
+        }
+    }
+    usage
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gds21::GdsPoint;
+
+    use crate::commands::test_support::{cell, library, rect, sref};
+
+    fn on_layer(layer: i16, datatype: i16) -> GdsElement {
+        let mut element = rect(0, 0, 10, 10);
+        if let Some(l) = element_layer_mut(&mut element) {
+            (*l.0, *l.1) = (layer, datatype);
+        }
+        element
+    }
+
+    fn text(layer: i16, texttype: i16) -> GdsElement {
+        GdsElement::GdsTextElem(GdsTextElem {
+            string: "A".to_string(),
+            layer,
+            texttype,
+            xy: GdsPoint { x: 0, y: 0 },
+            ..Default::default()
+        })
+    }
+
+    fn layers(elems: &[GdsElement]) -> Vec<Option<GdsLayer>> {
+        elems.iter().map(element_layer).collect()
+    }
+
+    #[test]
+    fn remap_moves_listed_layers_only() {
+        let mut elems = vec![on_layer(1, 0), on_layer(2, 0), text(1, 0), sref("leaf")];
+        let table = LayerTable::from([((1, 0), (10, 5))]);
+        assert_eq!(edit_layers(&mut elems, &LayerOperation::Remap(table)), 2);
+        assert_eq!(layers(&elems), [Some((10, 5)), Some((2, 0)), Some((10, 5)), None]);
+    }
+
+    #[test]
+    fn copy_appends_the_copies_on_the_target_layer() {
+        let mut elems = vec![on_layer(1, 0), on_layer(2, 0), on_layer(1, 0)];
+        assert_eq!(edit_layers(&mut elems, &LayerOperation::Copy((1, 0), (3, 1))), 2);
+        assert_eq!(
+            layers(&elems),
+            [Some((1, 0)), Some((2, 0)), Some((1, 0)), Some((3, 1)), Some((3, 1))]
+        );
+    }
+
+    #[test]
+    fn delete_removes_the_given_layers_and_keeps_references() {
+        let mut elems = vec![on_layer(1, 0), on_layer(1, 1), text(2, 0), sref("leaf")];
+        assert_eq!(edit_layers(&mut elems, &LayerOperation::Delete(vec![(1, 0), (2, 0)])), 2);
+        assert_eq!(layers(&elems), [Some((1, 1)), None]);
+    }
+
+    #[test]
+    fn usage_counts_elements_and_cells_per_layer() {
+        let lib = library(vec![
+            cell("a", vec![on_layer(1, 0), on_layer(1, 0), text(1, 0)]),
+            cell("b", vec![on_layer(1, 0), sref("a")]),
+        ]);
+        let usage = layer_usage(&lib);
+        assert_eq!(usage.len(), 1);
+        let u = &usage[&(1, 0)];
+        assert_eq!((u.boundaries, u.texts, u.cells), (3, 1, 2));
+    }
+}
@@ -0,0 +1,285 @@
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::sync::OnceLock;
+
+use libreda_lefdef::LEF;
+use regex::{Captures, Regex};
+
+/// Decimals kept of the numbers compared by [`same_definition`], well below any LEF grid.
+const COMPARED_DECIMALS: usize = 6;
+
+/// Merge the technology and macro sections of several LEF files into the first.
+///
+/// Layers, vias, via rules, sites, non-default rules and macros of later files are added when
+/// their name is new. A name defined again with a different definition is a conflict: the
+/// first definition is kept and the conflict reported. Redefinitions that are identical, as
+/// when a cell LEF repeats the sites of the technology LEF, are silently accepted.
+///
+/// LEF values are in microns whatever the `DATABASE MICRONS`, so files with different database
+/// units can be merged as long as the finest of them is a multiple of the others: the merged
+/// LEF takes the finest, on which every value of the coarser files lies. Other combinations
+/// are an error.
+///
+/// # Returns
+/// The merged LEF and one message per conflict.
+pub fn merge_lefs(lefs: Vec<(String, LEF)>) -> Result<(LEF, Vec<String>), String> {
+    let database_microns = merged_database_microns(&lefs)?;
+    let mut lefs = lefs.into_iter();
+    let Some((_, mut merged)) = lefs.next() else {
+        return Ok((LEF::default(), vec![]));
+    };
+    merged.technology.units.database_microns = database_microns;
+    let mut conflicts = vec![];
+    for (source, lef) in lefs {
+        let mut report = |kind: &str, name: &str| {
+            conflicts.push(format!(
+                "{} {} of {} conflicts with an earlier definition, keeping the first",
+                kind, name, source
+            ));
+        };
+
+        for layer in lef.technology.layers {
+            match merged.technology.layers.iter().find(|l| l.name() == layer.name()) {
+                Some(existing) if !same_definition(existing, &layer) => report("layer", layer.name()),
+                Some(_) => {}
+                None => merged.technology.layers.push(layer),
+            }
+        }
+        merge_named("via", &mut merged.technology.vias, lef.technology.vias, &mut report);
+        merge_named("via rule", &mut merged.technology.via_rules_generate, lef.technology.via_rules_generate, &mut report);
+        merge_named("site", &mut merged.technology.sites, lef.technology.sites, &mut report);
+        merge_named(
+            "non-default rule",
+            &mut merged.technology.non_default_rules,
+            lef.technology.non_default_rules,
+            &mut report,
+        );
+        merge_named("macro", &mut merged.library.macros, lef.library.macros, &mut report);
+    }
+    Ok((merged, conflicts))
+}
+
+/// The `DATABASE MICRONS` of the merged LEF, the finest of the files.
+///
+/// Fails unless the finest value is a multiple of every other one.
+fn merged_database_microns(lefs: &[(String, LEF)]) -> Result<Option<u32>, String> {
+    let units: Vec<(&str, u32)> = lefs
+        .iter()
+        .filter_map(|(source, lef)| lef.technology.units.database_microns.map(|u| (source.as_str(), u)))
+        .collect();
+    let Some(&(finest_source, finest)) = units.iter().max_by_key(|(_, u)| *u) else {
+        return Ok(None);
+    };
+    for &(source, u) in &units {
+        if u == 0 || finest % u != 0 {
+            return Err(format!(
+                "DATABASE MICRONS {} of {} is not a divisor of DATABASE MICRONS {} of {}",
+                u, source, finest, finest_source
+            ));
+        }
+    }
+    Ok(Some(finest))
+}
+
+/// Add the definitions of `from` whose names are not in `into`, reporting differing ones.
+fn merge_named<T: Debug>(
+    kind: &str,
+    into: &mut BTreeMap<String, T>,
+    from: BTreeMap<String, T>,
+    report: &mut impl FnMut(&str, &str),
+) {
+    for (name, definition) in from {
+        match into.get(&name) {
+            Some(existing) if !same_definition(existing, &definition) => report(kind, &name),
+            Some(_) => {}
+            None => {
+                into.insert(name, definition);
+            }
+        }
+    }
+}
+
+/// Compare two parsed definitions, the LEF types have no `PartialEq`.
+///
+/// The definitions are compared by their debug form with every number rounded to
+/// [`COMPARED_DECIMALS`] decimals, so values parsed from different spellings, such as `0.1`
+/// and `.100`, or picking up rounding noise on the way, still compare equal.
+fn same_definition<T: Debug>(a: &T, b: &T) -> bool {
+    normalised_debug(a) == normalised_debug(b)
+}
+
+/// The debug form of a value with its numbers rounded, see [`same_definition`].
+fn normalised_debug<T: Debug>(value: &T) -> String {
+    static NUMBER: OnceLock<Regex> = OnceLock::new();
+    // A number not glued to a preceding name character, so names such as `via1.2` stay as they are.
+    let number = NUMBER
+        .get_or_init(|| Regex::new(r"(^|[^\w.])(-?\d+(?:\.\d+)?(?:e-?\d+)?)").expect("valid number pattern"));
+    number
+        .replace_all(&format!("{:?}", value), |caps: &Captures| {
+            let rounded = match caps[2].parse::<f64>() {
+                Ok(v) => format!("{:.*}", COMPARED_DECIMALS, v),
+                Err(_) => caps[2].to_string(),
+            };
+            // Rounding can leave a negative zero behind.
+            let rounded = match rounded.strip_prefix('-') {
+                Some(magnitude) if magnitude.bytes().all(|b| b == b'0' || b == b'.') => magnitude.to_string(),
+                _ => rounded,
+            };
+            format!("{}{}", &caps[1], rounded)
+        })
+        .into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libreda_lefdef::lef_parser::read_lef_bytes;
+
+    #[derive(Debug)]
+    struct Site {
+        name: String,
+        size: (f64, f64),
+    }
+
+    fn lef(database_microns: Option<u32>) -> LEF {
+        let mut lef = LEF::default();
+        lef.technology.units.database_microns = database_microns;
+        lef
+    }
+
+    #[test]
+    fn float_noise_is_not_a_conflict() {
+        let a = Site { name: "core".to_string(), size: (0.1, 1.4) };
+        let b = Site { name: "core".to_string(), size: (0.1 + 1e-12, 0.7 * 2.0) };
+        assert!(same_definition(&a, &b));
+
+        let c = Site { name: "core".to_string(), size: (0.2, 1.4) };
+        assert!(!same_definition(&a, &c));
+    }
+
+    #[test]
+    fn numbers_in_names_are_kept() {
+        assert_ne!(normalised_debug(&"via1.2"), normalised_debug(&"via1.20"));
+        assert_eq!(normalised_debug(&(-0.0000001, 2)), "(0.000000, 2.000000)");
+    }
+
+    #[test]
+    fn finest_database_units_are_taken_when_compatible() {
+        let lefs = vec![
+            ("tech.lef".to_string(), lef(Some(1000))),
+            ("cells.lef".to_string(), lef(Some(2000))),
+            ("other.lef".to_string(), lef(None)),
+        ];
+        let (merged, conflicts) = merge_lefs(lefs).unwrap();
+        assert_eq!(merged.technology.units.database_microns, Some(2000));
+        assert!(conflicts.is_empty());
+    }
+
+    #[test]
+    fn incompatible_database_units_are_rejected() {
+        let lefs = vec![
+            ("tech.lef".to_string(), lef(Some(1000))),
+            ("cells.lef".to_string(), lef(Some(300))),
+            ("more.lef".to_string(), lef(Some(2000))),
+        ];
+        let err = merge_lefs(lefs).unwrap_err();
+        assert!(err.contains("cells.lef"), "{}", err);
+    }
+
+    /// A technology and cell LEF with the given M1 width, via cut half size, site height and
+    /// inverter width, in microns, and the LEF text of further macros.
+    fn cell_lef(m1_width: &str, cut: &str, site_height: &str, inv_width: &str, extra_macro: &str) -> LEF {
+        let text = format!(
+            "VERSION 5.8 ;
+UNITS
+  DATABASE MICRONS 1000 ;
+END UNITS
+LAYER M1
+  TYPE ROUTING ;
+  DIRECTION HORIZONTAL ;
+  PITCH 0.2 ;
+  WIDTH {m1_width} ;
+END M1
+LAYER V1
+  TYPE CUT ;
+END V1
+LAYER M2
+  TYPE ROUTING ;
+  DIRECTION VERTICAL ;
+  PITCH 0.2 ;
+  WIDTH 0.1 ;
+END M2
+VIA via1 DEFAULT
+  LAYER M1 ;
+    RECT -0.1 -0.1 0.1 0.1 ;
+  LAYER V1 ;
+    RECT -{cut} -{cut} {cut} {cut} ;
+  LAYER M2 ;
+    RECT -0.1 -0.1 0.1 0.1 ;
+END via1
+SITE core
+  CLASS CORE ;
+  SIZE 0.2 BY {site_height} ;
+END core
+MACRO inv
+  CLASS CORE ;
+  ORIGIN 0 0 ;
+  SIZE {inv_width} BY 1.4 ;
+  SITE core ;
+END inv
+{extra_macro}END LIBRARY
+"
+        );
+        read_lef_bytes(&mut text.as_bytes()).unwrap()
+    }
+
+    fn base() -> LEF {
+        cell_lef("0.1", "0.05", "1.4", "0.4", "")
+    }
+
+    fn merge_with(second: LEF) -> (LEF, Vec<String>) {
+        merge_lefs(vec![("tech.lef".to_string(), base()), ("cells.lef".to_string(), second)]).unwrap()
+    }
+
+    fn layer_definition(lef: &LEF, name: &str) -> String {
+        normalised_debug(lef.technology.layers.iter().find(|l| l.name() == name).unwrap())
+    }
+
+    #[test]
+    fn repeated_definitions_are_merged_silently() {
+        let buf = "MACRO buf\n  CLASS CORE ;\n  SIZE 0.6 BY 1.4 ;\nEND buf\n";
+        let (merged, conflicts) = merge_with(cell_lef(".10", "0.050", "1.40", "0.4", buf));
+        assert!(conflicts.is_empty(), "{:?}", conflicts);
+        assert_eq!(merged.technology.layers.len(), 3);
+        assert_eq!(merged.technology.vias.len(), 1);
+        assert_eq!(merged.library.macros.keys().collect::<Vec<_>>(), ["buf", "inv"]);
+    }
+
+    #[test]
+    fn conflicting_layer_is_reported_and_the_first_kept() {
+        let (merged, conflicts) = merge_with(cell_lef("0.12", "0.05", "1.4", "0.4", ""));
+        assert_eq!(conflicts, ["layer M1 of cells.lef conflicts with an earlier definition, keeping the first"]);
+        assert_eq!(layer_definition(&merged, "M1"), layer_definition(&base(), "M1"));
+    }
+
+    #[test]
+    fn conflicting_via_is_reported_and_the_first_kept() {
+        let (merged, conflicts) = merge_with(cell_lef("0.1", "0.06", "1.4", "0.4", ""));
+        assert_eq!(conflicts, ["via via1 of cells.lef conflicts with an earlier definition, keeping the first"]);
+        assert!(same_definition(&merged.technology.vias["via1"], &base().technology.vias["via1"]));
+    }
+
+    #[test]
+    fn conflicting_site_is_reported_and_the_first_kept() {
+        let (merged, conflicts) = merge_with(cell_lef("0.1", "0.05", "2.8", "0.4", ""));
+        assert_eq!(conflicts, ["site core of cells.lef conflicts with an earlier definition, keeping the first"]);
+        assert_eq!(merged.technology.sites["core"].size, (0.2, 1.4));
+    }
+
+    #[test]
+    fn conflicting_macro_is_reported_and_the_first_kept() {
+        let (merged, conflicts) = merge_with(cell_lef("0.1", "0.05", "1.4", "0.6", ""));
+        assert_eq!(conflicts, ["macro inv of cells.lef conflicts with an earlier definition, keeping the first"]);
+        assert_eq!(merged.library.macros["inv"].size, Some((0.4, 1.4)));
+    }
+}
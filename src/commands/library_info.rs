@@ -0,0 +1,296 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+
+use gds21::{GdsArrayRef, GdsElement, GdsLibrary, GdsStruct};
+use serde::Serialize;
+
+use super::geometry::{
+    element_polygons, merge_polygons, polygon_area, polygon_layer, to_polygon, units_pair, BoundingBox,
+};
+use super::hierarchy::{cell_bounding_boxes, hierarchy_depths, top_cells};
+
+#[derive(Serialize)]
+pub struct LibraryInfo {
+    name: String,
+    version: i16,
+    user_units_per_dbu: f64,
+    meters_per_dbu: f64,
+    top_cells: Vec<String>,
+    cells: Vec<CellInfo>,
+    layers: Vec<LayerInfo>,
+}
+
+#[derive(Serialize, Default)]
+pub struct CellInfo {
+    name: String,
+    bbox: Option<BoundingBox>,
+    boundaries: usize,
+    paths: usize,
+    srefs: usize,
+    arefs: usize,
+    texts: usize,
+    nodes: usize,
+    boxes: usize,
+    /// Cells placed by this cell, AREFs counting once per array member.
+    instances: usize,
+    /// How often this cell is placed throughout the library.
+    references: usize,
+    /// Hierarchy levels below this cell.
+    depth: usize,
+}
+
+#[derive(Serialize, Default)]
+pub struct LayerInfo {
+    layer: i16,
+    datatype: i16,
+    polygons: usize,
+    vertices: usize,
+    /// Area in square microns, summed over the cell definitions.
+    area: f64,
+}
+
+/// Print statistics about a GDS library.
+///
+/// # Arguments
+/// * `input` - Path to the GDS file
+/// * `merged` - Merge overlapping polygons per cell and layer before summing areas
+/// * `json` - Print a JSON report instead of tables
+pub fn print_library_info(
+    input: &PathBuf,
+    merged: bool,
+    json: bool,
+) -> Result<LibraryInfo, Box<dyn std::error::Error>> {
+    let lib = GdsLibrary::load(input)?;
+    let info = library_info(&lib, merged);
+    if json {
+        println!("{}", serde_json::to_string_pretty(&info)?);
+    } else {
+        print_tables(&info);
+    }
+    Ok(info)
+}
+
+/// Collect statistics per cell and per layer/datatype of a library.
+///
+/// Layer statistics are taken over the cell definitions, instances are not expanded.
+pub fn library_info(lib: &GdsLibrary, merged: bool) -> LibraryInfo {
+    let (user_units_per_dbu, meters_per_dbu) = units_pair(lib);
+    let bboxes = cell_bounding_boxes(lib);
+    let depths = hierarchy_depths(lib);
+
+    let mut references: HashMap<&str, usize> = HashMap::new();
+    for s in &lib.structs {
+        for element in &s.elems {
+            match element {
+                GdsElement::GdsStructRef(r) => *references.entry(r.name.as_str()).or_default() += 1,
+                GdsElement::GdsArrayRef(GdsArrayRef { name, cols, rows, .. }) => {
+                    *references.entry(name.as_str()).or_default() += array_size(*cols, *rows)
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let cells = lib
+        .structs
+        .iter()
+        .map(|s| {
+            let mut cell = cell_info(s);
+            cell.bbox = bboxes.get(&s.name).copied().flatten();
+            cell.depth = depths.get(&s.name).copied().unwrap_or_default();
+            cell.references = references.get(s.name.as_str()).copied().unwrap_or_default();
+            cell
+        })
+        .collect();
+
+    // Square microns per square database unit.
+    let um_per_dbu = meters_per_dbu * 1e6;
+    let area_scale = um_per_dbu * um_per_dbu;
+    let mut layers: BTreeMap<(i16, i16), LayerInfo> = BTreeMap::new();
+    for s in &lib.structs {
+        let mut polygons_per_layer: BTreeMap<(i16, i16), Vec<_>> = BTreeMap::new();
+        for element in &s.elems {
+            let Some(key) = polygon_layer(element) else {
+                continue;
+            };
+            let entry = layers.entry(key).or_insert_with(|| LayerInfo {
+                layer: key.0,
+                datatype: key.1,
+                ..Default::default()
+            });
+            for polygon in element_polygons(element) {
+                entry.polygons += 1;
+                entry.vertices += polygon.len();
+                polygons_per_layer.entry(key).or_default().push(polygon);
+            }
+        }
+        for (key, polygons) in polygons_per_layer {
+            let area: f64 = if merged {
+                let polygons = polygons.iter().map(|p| to_polygon(p)).collect();
+                merge_polygons(polygons).polygons.iter().map(polygon_area).sum()
+            } else {
+                polygons.iter().map(|p| polygon_area(&to_polygon(p))).sum()
+            };
+            layers.get_mut(&key).unwrap().area += area * area_scale;
+        }
+    }
+
+    LibraryInfo {
+        name: lib.name.clone(),
+        version: lib.version,
+        user_units_per_dbu,
+        meters_per_dbu,
+        top_cells: top_cells(lib),
+        cells,
+        layers: layers.into_values().collect(),
+    }
+}
+
+fn cell_info(gds_struct: &GdsStruct) -> CellInfo {
+    let mut cell = CellInfo {
+        name: gds_struct.name.clone(),
+        ..Default::default()
+    };
+    for element in &gds_struct.elems {
+        match element {
+            GdsElement::GdsBoundary(_) => cell.boundaries += 1,
+            GdsElement::GdsPath(_) => cell.paths += 1,
+            GdsElement::GdsStructRef(_) => {
+                cell.srefs += 1;
+                cell.instances += 1;
+            }
+            GdsElement::GdsArrayRef(GdsArrayRef { cols, rows, .. }) => {
+                cell.arefs += 1;
+                cell.instances += array_size(*cols, *rows);
+            }
+            GdsElement::GdsTextElem(_) => cell.texts += 1,
+            GdsElement::GdsNode(_) => cell.nodes += 1,
+            GdsElement::GdsBox(_) => cell.boxes += 1,
+        }
+    }
+    cell
+}
+
+fn array_size(cols: i16, rows: i16) -> usize {
+    cols.max(0) as usize * rows.max(0) as usize
+}
+
+fn print_tables(info: &LibraryInfo) {
+    println!("Library:     {}", info.name);
+    println!("GDS version: {}", info.version);
+    println!(
+        "Units:       {} user units per DBU, {} m per DBU",
+        info.user_units_per_dbu, info.meters_per_dbu
+    );
+    println!("Top cells:   {}", info.top_cells.join(", "));
+    println!();
+
+    let name_width = info.cells.iter().map(|c| c.name.len()).max().unwrap_or(4).max(4);
+    println!(
+        "{:<name_width$}  {:>40}  {:>6} {:>6} {:>6} {:>6} {:>6} {:>6} {:>6} {:>6} {:>6} {:>5}",
+        "Cell", "BBox", "Bound", "Path", "SRef", "ARef", "Text", "Node", "Box", "Inst", "Refs", "Depth",
+    );
+    for c in &info.cells {
+        let bbox = c
+            .bbox
+            .map(|b| format!("({}, {}) ({}, {})", b.min_x, b.min_y, b.max_x, b.max_y))
+            .unwrap_or_else(|| "-".to_string());
+        println!(
+            "{:<name_width$}  {:>40}  {:>6} {:>6} {:>6} {:>6} {:>6} {:>6} {:>6} {:>6} {:>6} {:>5}",
+            c.name,
+            bbox,
+            c.boundaries,
+            c.paths,
+            c.srefs,
+            c.arefs,
+            c.texts,
+            c.nodes,
+            c.boxes,
+            c.instances,
+            c.references,
+            c.depth,
+        );
+    }
+    println!();
+
+    println!("{:>9}  {:>9}  {:>9}  {:>16}", "Layer", "Polygons", "Vertices", "Area [um^2]");
+    for l in &info.layers {
+        println!(
+            "{:>9}  {:>9}  {:>9}  {:>16.6}",
+            format!("{}/{}", l.layer, l.datatype),
+            l.polygons,
+            l.vertices,
+            l.area
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gds21::GdsPath;
+
+    use crate::commands::test_support::{aref, cell, library_in_units, points, rect, sref};
+
+    /// `leaf` has two overlapping 1 um squares on 1/0 and a wire on 2/0, `top` places it seven
+    /// times, in nanometre database units.
+    fn layout() -> GdsLibrary {
+        let wire = GdsElement::GdsPath(GdsPath {
+            layer: 2,
+            datatype: 0,
+            xy: points(&[(0, 0), (1000, 0)]),
+            width: Some(100),
+            path_type: Some(0),
+            ..Default::default()
+        });
+        library_in_units(
+            "lib",
+            1e-9,
+            vec![
+                cell("leaf", vec![rect(0, 0, 1000, 1000), rect(500, 0, 1500, 1000), wire]),
+                cell("top", vec![sref("leaf"), aref("leaf", 2, 3)]),
+            ],
+        )
+    }
+
+    fn layer(info: &LibraryInfo, key: (i16, i16)) -> &LayerInfo {
+        info.layers.iter().find(|l| (l.layer, l.datatype) == key).unwrap()
+    }
+
+    #[test]
+    fn cells_count_their_elements_and_references() {
+        let info = library_info(&layout(), false);
+        assert!((info.user_units_per_dbu - 1e-3).abs() < 1e-12);
+        assert!((info.meters_per_dbu - 1e-9).abs() < 1e-18);
+        assert_eq!(info.top_cells, ["top"]);
+
+        let leaf = &info.cells[0];
+        assert_eq!((leaf.boundaries, leaf.paths, leaf.references, leaf.depth), (2, 1, 7, 0));
+        let bbox = leaf.bbox.unwrap();
+        assert_eq!((bbox.min_x, bbox.min_y, bbox.max_x, bbox.max_y), (0, -50, 1500, 1000));
+
+        let top = &info.cells[1];
+        assert_eq!((top.srefs, top.arefs, top.instances, top.references, top.depth), (1, 1, 7, 0, 1));
+    }
+
+    #[test]
+    fn layers_sum_polygons_vertices_and_area_per_definition() {
+        let info = library_info(&layout(), false);
+        assert_eq!(info.layers.len(), 2);
+        let metal = layer(&info, (1, 0));
+        assert_eq!((metal.polygons, metal.vertices), (2, 8));
+        assert!((metal.area - 2.0).abs() < 1e-9);
+        let wire = layer(&info, (2, 0));
+        assert_eq!((wire.polygons, wire.vertices), (1, 4));
+        assert!((wire.area - 0.1).abs() < 1e-9);
+    }
+
+    #[test]
+    fn merged_area_counts_overlaps_once() {
+        let info = library_info(&layout(), true);
+        let metal = layer(&info, (1, 0));
+        assert_eq!(metal.polygons, 2);
+        assert!((metal.area - 1.5).abs() < 1e-9);
+        assert!((layer(&info, (2, 0)).area - 0.1).abs() < 1e-9);
+    }
+}
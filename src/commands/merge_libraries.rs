@@ -0,0 +1,306 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use gds21::{GdsElement, GdsLibrary, GdsPoint, GdsStruct};
+
+use super::gds_diff::diff_cells;
+use super::geometry::units_pair;
+use super::hierarchy::rename_references;
+use super::rename_cells::GDS_MAX_NAME_LENGTH;
+use super::vertex_limit::save_library;
+
+/// Joins the affix of the prefix and suffix policies to the cell name.
+const AFFIX_SEPARATOR: &str = "_";
+
+/// What to do when several libraries define a cell of the same name.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConflictPolicy {
+    /// Keep the cell of the first library defining it.
+    KeepFirst,
+    /// Keep the cell of the last library defining it.
+    KeepLast,
+    /// Rename later cells to `<affix>_<name>`.
+    Prefix,
+    /// Rename later cells to `<name>_<affix>`.
+    Suffix,
+    /// Keep the first cell if all definitions have identical geometry, fail otherwise.
+    Identical,
+}
+
+impl std::str::FromStr for ConflictPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "keep-first" => Ok(ConflictPolicy::KeepFirst),
+            "keep-last" => Ok(ConflictPolicy::KeepLast),
+            "prefix" => Ok(ConflictPolicy::Prefix),
+            "suffix" => Ok(ConflictPolicy::Suffix),
+            "identical" => Ok(ConflictPolicy::Identical),
+            _ => Err(format!(
+                "unknown conflict policy {}, expected keep-first, keep-last, prefix, suffix or identical",
+                s
+            )),
+        }
+    }
+}
+
+/// Merge several GDS files into one.
+///
+/// # Arguments
+/// * `inputs` - Paths to the GDS files, in order of precedence
+/// * `output` - Output GDS file path
+/// * `policy` - How to resolve cells defined by more than one library
+/// * `affix` - Affix used by the prefix and suffix policies, the library name if `None`
+/// * `max_points` - Most XY points written per boundary or path
+pub fn merge_gds_files(
+    inputs: &[&PathBuf],
+    output: &PathBuf,
+    policy: ConflictPolicy,
+    affix: Option<&str>,
+    max_points: usize,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let libs = inputs
+        .iter()
+        .map(|input| GdsLibrary::load(input))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut merged = merge_libraries(libs, policy, affix)?;
+    save_library(&mut merged, output, max_points)?;
+    Ok(())
+}
+
+/// Merge the structs of several libraries.
+///
+/// The result uses the name and units of the first library. Libraries with other database
+/// units are rescaled first. Renamed cells have their references rewritten throughout the
+/// library they came from. A rename fails if the new name is taken or longer than GDSII allows.
+pub fn merge_libraries(
+    libs: Vec<GdsLibrary>,
+    policy: ConflictPolicy,
+    affix: Option<&str>,
+) -> Result<GdsLibrary, String> {
+    let mut libs = libs.into_iter();
+    let Some(mut merged) = libs.next() else {
+        return Err("no libraries to merge".to_string());
+    };
+    let (_, target_dbu) = units_pair(&merged);
+    let mut index: HashMap<String, usize> = merged
+        .structs
+        .iter()
+        .enumerate()
+        .map(|(i, s)| (s.name.clone(), i))
+        .collect();
+
+    for mut lib in libs {
+        let (_, dbu) = units_pair(&lib);
+        let factor = dbu / target_dbu;
+        if (factor - 1.0).abs() > 1e-12 {
+            println!("Rescaling library {} by {}", lib.name, factor);
+            for s in &mut lib.structs {
+                for element in &mut s.elems {
+                    rescale_element(element, factor)?;
+                }
+            }
+        }
+
+        // Decide the fate of every conflicting cell before touching any reference.
+        let mut renames: HashMap<String, String> = HashMap::new();
+        let mut dropped: Vec<String> = vec![];
+        for s in &lib.structs {
+            let Some(&existing) = index.get(&s.name) else {
+                continue;
+            };
+            match policy {
+                ConflictPolicy::KeepFirst => dropped.push(s.name.clone()),
+                ConflictPolicy::KeepLast => {}
+                ConflictPolicy::Prefix | ConflictPolicy::Suffix => {
+                    let affix = affix.unwrap_or(&lib.name);
+                    let new_name = match policy {
+                        ConflictPolicy::Prefix => format!("{}{}{}", affix, AFFIX_SEPARATOR, s.name),
+                        _ => format!("{}{}{}", s.name, AFFIX_SEPARATOR, affix),
+                    };
+                    if new_name.len() > GDS_MAX_NAME_LENGTH {
+                        return Err(format!(
+                            "renaming cell {} of library {} to {} exceeds {} characters",
+                            s.name, lib.name, new_name, GDS_MAX_NAME_LENGTH
+                        ));
+                    }
+                    if index.contains_key(&new_name)
+                        || renames.values().any(|v| *v == new_name)
+                        || lib.structs.iter().any(|o| o.name == new_name)
+                    {
+                        return Err(format!(
+                            "cannot rename cell {} of library {} to {}, the name is already taken",
+                            s.name, lib.name, new_name
+                        ));
+                    }
+                    println!("Renaming {} of library {} to {}", s.name, lib.name, new_name);
+                    renames.insert(s.name.clone(), new_name);
+                }
+                ConflictPolicy::Identical => {
+                    if !diff_cells(&merged.structs[existing], s, 0).is_empty() {
+                        return Err(format!(
+                            "cell {} of library {} differs from an earlier definition",
+                            s.name, lib.name
+                        ));
+                    }
+                    dropped.push(s.name.clone());
+                }
+            }
+        }
+
+        for mut s in lib.structs {
+            if dropped.contains(&s.name) {
+                continue;
+            }
+            rename_references(&mut s, &renames);
+            if let Some(new_name) = renames.get(&s.name) {
+                s.name = new_name.clone();
+            }
+            match index.get(&s.name) {
+                Some(&existing) => merged.structs[existing] = s,
+                None => {
+                    index.insert(s.name.clone(), merged.structs.len());
+                    merged.structs.push(s);
+                }
+            }
+        }
+    }
+    Ok(merged)
+}
+
+/// Scale every coordinate and width of an element by `factor`, leaving placements unmagnified.
+///
+/// Fails on the first value that no longer fits into a GDS coordinate, the element is then
+/// partly scaled.
+pub fn rescale_element(element: &mut GdsElement, factor: f64) -> Result<(), String> {
+    fn points(xy: &mut [GdsPoint]) -> impl Iterator<Item = &mut i32> {
+        xy.iter_mut().flat_map(|p| [&mut p.x, &mut p.y])
+    }
+    let values: Vec<&mut i32> = match element {
+        GdsElement::GdsBoundary(b) => points(&mut b.xy).collect(),
+        GdsElement::GdsPath(p) => points(&mut p.xy)
+            .chain([p.width.as_mut(), p.begin_extn.as_mut(), p.end_extn.as_mut()].into_iter().flatten())
+            .collect(),
+        GdsElement::GdsStructRef(r) => points(std::slice::from_mut(&mut r.xy)).collect(),
+        GdsElement::GdsArrayRef(r) => points(&mut r.xy).collect(),
+        GdsElement::GdsTextElem(t) => points(std::slice::from_mut(&mut t.xy))
+            .chain(t.width.as_mut())
+            .collect(),
+        GdsElement::GdsNode(n) => points(&mut n.xy).collect(),
+        GdsElement::GdsBox(b) => points(&mut b.xy).collect(),
+    };
+    for v in values {
+        let scaled = (*v as f64 * factor).round();
+        if scaled < i32::MIN as f64 || scaled > i32::MAX as f64 {
+            return Err(format!("coordinate {} overflows when scaled by {}", v, factor));
+        }
+        *v = scaled as i32;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::test_support::{cell, library_in_units, square, sref};
+
+    /// Two libraries both defining `leaf`, the second also placing it from `top`.
+    fn conflicting(second_leaf: i32) -> Vec<GdsLibrary> {
+        vec![
+            library_in_units("a", 1e-9, vec![cell("leaf", vec![square(10)])]),
+            library_in_units(
+                "b",
+                1e-9,
+                vec![cell("leaf", vec![square(second_leaf)]), cell("top", vec![sref("leaf")])],
+            ),
+        ]
+    }
+
+    fn leaf_size(lib: &GdsLibrary, name: &str) -> i32 {
+        let s = lib.structs.iter().find(|s| s.name == name).unwrap();
+        match &s.elems[0] {
+            GdsElement::GdsBoundary(b) => b.xy[2].x,
+            _ => panic!("expected a boundary"),
+        }
+    }
+
+    fn placed(lib: &GdsLibrary, name: &str) -> String {
+        let s = lib.structs.iter().find(|s| s.name == name).unwrap();
+        match &s.elems[0] {
+            GdsElement::GdsStructRef(r) => r.name.clone(),
+            _ => panic!("expected a reference"),
+        }
+    }
+
+    #[test]
+    fn keep_first_and_keep_last() {
+        let merged = merge_libraries(conflicting(20), ConflictPolicy::KeepFirst, None).unwrap();
+        assert_eq!(merged.structs.len(), 2);
+        assert_eq!(leaf_size(&merged, "leaf"), 10);
+
+        let merged = merge_libraries(conflicting(20), ConflictPolicy::KeepLast, None).unwrap();
+        assert_eq!(merged.structs.len(), 2);
+        assert_eq!(leaf_size(&merged, "leaf"), 20);
+    }
+
+    #[test]
+    fn prefix_and_suffix_rename_the_later_cell_and_its_references() {
+        let merged = merge_libraries(conflicting(20), ConflictPolicy::Prefix, None).unwrap();
+        assert_eq!(leaf_size(&merged, "leaf"), 10);
+        assert_eq!(leaf_size(&merged, "b_leaf"), 20);
+        assert_eq!(placed(&merged, "top"), "b_leaf");
+
+        let merged = merge_libraries(conflicting(20), ConflictPolicy::Suffix, Some("v2")).unwrap();
+        assert_eq!(leaf_size(&merged, "leaf_v2"), 20);
+        assert_eq!(placed(&merged, "top"), "leaf_v2");
+    }
+
+    #[test]
+    fn renames_onto_existing_names_are_errors() {
+        let mut libs = conflicting(20);
+        libs[0].structs.push(cell("b_leaf", vec![]));
+        let err = merge_libraries(libs, ConflictPolicy::Prefix, None).unwrap_err();
+        assert!(err.contains("b_leaf"), "{}", err);
+
+        let mut libs = conflicting(20);
+        libs[1].structs.push(cell("leaf_b", vec![]));
+        assert!(merge_libraries(libs, ConflictPolicy::Suffix, None).is_err());
+    }
+
+    #[test]
+    fn renames_longer_than_gds_allows_are_errors() {
+        let affix = "x".repeat(GDS_MAX_NAME_LENGTH - "leaf".len());
+        let err = merge_libraries(conflicting(20), ConflictPolicy::Suffix, Some(&affix)).unwrap_err();
+        assert!(err.contains("exceeds"), "{}", err);
+
+        let affix = "x".repeat(GDS_MAX_NAME_LENGTH - "leaf_".len());
+        assert!(merge_libraries(conflicting(20), ConflictPolicy::Suffix, Some(&affix)).is_ok());
+    }
+
+    #[test]
+    fn identical_accepts_equal_cells_and_rejects_different_ones() {
+        let merged = merge_libraries(conflicting(10), ConflictPolicy::Identical, None).unwrap();
+        assert_eq!(merged.structs.len(), 2);
+
+        let err = merge_libraries(conflicting(20), ConflictPolicy::Identical, None).unwrap_err();
+        assert!(err.contains("leaf"));
+    }
+
+    #[test]
+    fn libraries_with_other_units_are_rescaled() {
+        let libs = vec![
+            library_in_units("a", 1e-9, vec![cell("fine", vec![square(10)])]),
+            library_in_units("b", 1e-8, vec![cell("coarse", vec![square(10)])]),
+        ];
+        let merged = merge_libraries(libs, ConflictPolicy::KeepFirst, None).unwrap();
+        assert_eq!(leaf_size(&merged, "fine"), 10);
+        assert_eq!(leaf_size(&merged, "coarse"), 100);
+    }
+
+    #[test]
+    fn rescale_reports_overflow() {
+        let mut element = square(i32::MAX / 2);
+        assert!(rescale_element(&mut element, 4.0).is_err());
+    }
+}
@@ -0,0 +1,227 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use gds21::{GdsElement, GdsLibrary, GdsProperty, GdsStruct};
+use regex::RegexSet;
+
+use super::geometry::{area_doubled, element_polygons, merge_polygons, polygons_to_boundaries, to_polygon};
+use super::layer_map::GdsLayer;
+use super::vertex_limit::save_library;
+
+/// Property attribute holding the net name of a shape, as written by `def2gds`.
+pub const NET_NAME_PROPERTY: i16 = 2;
+
+/// Which shapes may be merged with each other.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MergeMode {
+    /// All shapes on the same layer/datatype.
+    Layer,
+    /// Shapes on the same layer/datatype that also belong to the same net.
+    Net,
+}
+
+/// Merge overlapping and touching shapes per layer in the selected cells of a GDS file.
+///
+/// # Arguments
+/// * `input` - Path to the GDS file
+/// * `output` - Output GDS file path
+/// * `layers` - Only merge these layers, all layers if `None`
+/// * `mode` - Merge per layer or per net within each layer
+/// * `patterns` - Regexes a cell name must match to be merged
+/// * `max_points` - Most XY points written per boundary or path
+pub fn merge_gds_shapes(
+    input: &PathBuf,
+    output: &PathBuf,
+    layers: Option<&[GdsLayer]>,
+    mode: MergeMode,
+    patterns: Option<Vec<&str>>,
+    max_points: usize,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut lib = GdsLibrary::load(input)?;
+    let re = RegexSet::new(patterns.unwrap_or(vec![".*"]).into_iter())?;
+    for s in lib.structs.iter_mut().filter(|s| re.matches(&s.name).matched_any()) {
+        let before = s.elems.len();
+        for warning in merge_struct_shapes(s, layers, mode) {
+            println!("{}: {}", s.name, warning);
+        }
+        println!("Merged {}: {} -> {} elements", s.name, before, s.elems.len());
+    }
+    save_library(&mut lib, output, max_points)?;
+    Ok(())
+}
+
+/// Replace the boundaries and paths of a struct by their union per layer.
+///
+/// The merged polygons are written as boundaries, holes cut open so they stay valid GDS.
+/// In [`MergeMode::Net`] shapes are grouped by their [`NET_NAME_PROPERTY`] as well, and the
+/// merged boundaries carry that property again. Other elements are kept as they are, and so
+/// are shapes enclosing no area, such as paths without width.
+///
+/// # Returns
+/// Warnings about holes too small to be cut open, which are dropped.
+pub fn merge_struct_shapes(gds_struct: &mut GdsStruct, layers: Option<&[GdsLayer]>, mode: MergeMode) -> Vec<String> {
+    let mut warnings = vec![];
+    let mut groups: BTreeMap<(GdsLayer, Option<String>), Vec<Vec<_>>> = BTreeMap::new();
+    let mut kept = vec![];
+    for element in gds_struct.elems.drain(..) {
+        let (layer, properties) = match &element {
+            GdsElement::GdsBoundary(b) => ((b.layer, b.datatype), &b.properties),
+            GdsElement::GdsPath(p) => ((p.layer, p.datatype), &p.properties),
+            _ => {
+                kept.push(element);
+                continue;
+            }
+        };
+        if layers.map(|l| !l.contains(&layer)).unwrap_or(false) {
+            kept.push(element);
+            continue;
+        }
+        let polygons = element_polygons(&element);
+        if polygons.iter().all(|p| area_doubled(p) == 0) {
+            kept.push(element);
+            continue;
+        }
+        let net = match mode {
+            MergeMode::Layer => None,
+            MergeMode::Net => net_name(properties),
+        };
+        groups.entry((layer, net)).or_default().extend(polygons);
+    }
+
+    for (((layer, datatype), net), polygons) in groups {
+        let merged = merge_polygons(polygons.iter().map(|p| to_polygon(p)).collect());
+        let (boundaries, merge_warnings) = polygons_to_boundaries(&merged, layer, datatype);
+        warnings.extend(merge_warnings);
+        for mut boundary in boundaries {
+            if let (Some(net), GdsElement::GdsBoundary(b)) = (&net, &mut boundary) {
+                b.properties = vec![GdsProperty {
+                    attr: NET_NAME_PROPERTY,
+                    value: net.clone(),
+                }];
+            }
+            kept.push(boundary);
+        }
+    }
+    gds_struct.elems = kept;
+    warnings
+}
+
+/// The net name stored in the properties of a shape.
+pub fn net_name(properties: &[GdsProperty]) -> Option<String> {
+    properties
+        .iter()
+        .find(|p| p.attr == NET_NAME_PROPERTY)
+        .map(|p| p.value.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gds21::{GdsBoundary, GdsPath};
+
+    use crate::commands::geometry::BoundingBox;
+    use crate::commands::test_support::{cell, points, rect, sref};
+
+    fn shape(layer: i16, x0: i32, x1: i32, net: Option<&str>) -> GdsElement {
+        GdsElement::GdsBoundary(GdsBoundary {
+            layer,
+            datatype: 0,
+            xy: points(&[(x0, 0), (x1, 0), (x1, 10), (x0, 10), (x0, 0)]),
+            properties: net_properties(net),
+            ..Default::default()
+        })
+    }
+
+    fn wire(x0: i32, x1: i32, width: i32, net: Option<&str>) -> GdsElement {
+        GdsElement::GdsPath(GdsPath {
+            layer: 1,
+            datatype: 0,
+            xy: points(&[(x0, 5), (x1, 5)]),
+            width: Some(width),
+            path_type: Some(0),
+            properties: net_properties(net),
+            ..Default::default()
+        })
+    }
+
+    fn net_properties(net: Option<&str>) -> Vec<GdsProperty> {
+        net.map(|net| GdsProperty {
+            attr: NET_NAME_PROPERTY,
+            value: net.to_string(),
+        })
+        .into_iter()
+        .collect()
+    }
+
+    /// Layer, x extent and net of every boundary, and the kinds of the other elements.
+    fn summary(gds_struct: &GdsStruct) -> Vec<String> {
+        let mut out: Vec<String> = gds_struct
+            .elems
+            .iter()
+            .map(|e| match e {
+                GdsElement::GdsBoundary(b) => {
+                    let bbox = BoundingBox::from_points(&b.xy).unwrap();
+                    format!("{} {}..{} {:?}", b.layer, bbox.min_x, bbox.max_x, net_name(&b.properties))
+                }
+                GdsElement::GdsPath(p) => format!("path {:?}", p.width),
+                GdsElement::GdsStructRef(r) => format!("sref {}", r.name),
+                _ => panic!("unexpected element {:?}", e),
+            })
+            .collect();
+        out.sort();
+        out
+    }
+
+    #[test]
+    fn layer_mode_merges_touching_shapes_of_one_layer() {
+        let mut s = cell(
+            "top",
+            vec![
+                shape(1, 0, 10, Some("a")),
+                wire(10, 20, 10, Some("b")),
+                shape(2, 0, 10, None),
+                sref("leaf"),
+            ],
+        );
+        assert!(merge_struct_shapes(&mut s, None, MergeMode::Layer).is_empty());
+        assert_eq!(summary(&s), ["1 0..20 None", "2 0..10 None", "sref leaf"]);
+    }
+
+    #[test]
+    fn net_mode_merges_per_net_and_keeps_the_net_name() {
+        let mut s = cell(
+            "top",
+            vec![
+                shape(1, 0, 10, Some("a")),
+                wire(10, 20, 10, Some("a")),
+                shape(1, 20, 30, Some("b")),
+                shape(1, 30, 40, None),
+            ],
+        );
+        merge_struct_shapes(&mut s, None, MergeMode::Net);
+        assert_eq!(summary(&s), [r#"1 0..20 Some("a")"#, r#"1 20..30 Some("b")"#, "1 30..40 None"]);
+    }
+
+    #[test]
+    fn layers_outside_the_filter_are_left_alone() {
+        let mut s = cell(
+            "top",
+            vec![rect(0, 0, 10, 10), rect(10, 0, 20, 10), shape(2, 0, 10, None), shape(2, 10, 20, None)],
+        );
+        merge_struct_shapes(&mut s, Some(&[(2, 0)]), MergeMode::Layer);
+        assert_eq!(summary(&s), ["1 0..10 None", "1 10..20 None", "2 0..20 None"]);
+    }
+
+    #[test]
+    fn shapes_without_area_are_kept() {
+        let degenerate = GdsElement::GdsBoundary(GdsBoundary {
+            layer: 1,
+            datatype: 0,
+            xy: points(&[(0, 0), (10, 0), (20, 0), (0, 0)]),
+            ..Default::default()
+        });
+        let mut s = cell("top", vec![shape(1, 0, 10, None), wire(0, 20, 0, None), degenerate]);
+        merge_struct_shapes(&mut s, None, MergeMode::Layer);
+        assert_eq!(summary(&s), ["1 0..10 None", "1 0..20 None", "path Some(0)"]);
+    }
+}
@@ -1,8 +1,36 @@
+pub mod arrays;
+pub mod cell_layouts;
+pub mod cell_tree;
+pub mod dedup_cells;
+pub mod def_grids;
+pub mod def_objects;
+pub mod def_routes;
 pub mod def_to_gds;
+pub mod def_vias;
+pub mod flatten;
+pub mod gds_bool;
+pub mod gds_diff;
 pub mod gds_to_def;
+pub mod gds_to_txt;
+pub mod gds_xor;
+pub mod geometry;
+pub mod hierarchy;
+pub mod layer_map;
+pub mod layer_ops;
+pub mod lef_merge;
+pub mod library_info;
+pub mod merge_libraries;
+pub mod merge_shapes;
 pub mod positions_to_file;
+pub mod prune_cells;
+pub mod rename_cells;
 pub mod replace_all;
+pub mod sizing;
 pub mod snap_to_grid;
+#[cfg(test)]
+mod test_support;
+pub mod txt_to_gds;
+pub mod vertex_limit;
 pub mod def_to_oasis;
 
 
@@ -0,0 +1,140 @@
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+use gds21::GdsLibrary;
+
+use super::hierarchy::reachable_cells;
+use super::vertex_limit::save_library;
+
+/// Remove every struct of a GDS file that is not reachable from the given top cells.
+///
+/// # Arguments
+/// * `input` - Path to the GDS file
+/// * `output` - Output GDS file path
+/// * `tops` - Cells to keep together with everything they place
+/// * `max_points` - Most XY points written per boundary or path
+pub fn prune_gds_file(
+    input: &PathBuf,
+    output: &PathBuf,
+    tops: &[&str],
+    max_points: usize,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut lib = GdsLibrary::load(input)?;
+    let removed = prune_library(&mut lib, tops)?;
+    for name in &removed {
+        println!("Removing unreachable cell {}", name);
+    }
+    println!("Removed {} cells, kept {}", removed.len(), lib.structs.len());
+    save_library(&mut lib, output, max_points)?;
+    Ok(())
+}
+
+/// Remove the structs not reachable from `tops`, keeping the library order of the others.
+///
+/// # Returns
+/// The names of the removed structs.
+pub fn prune_library(lib: &mut GdsLibrary, tops: &[&str]) -> Result<Vec<String>, String> {
+    let keep: HashSet<String> = reachable_cells(lib, tops)?.into_iter().collect();
+    let mut removed = vec![];
+    lib.structs.retain(|s| {
+        let kept = keep.contains(&s.name);
+        if !kept {
+            removed.push(s.name.clone());
+        }
+        kept
+    });
+    Ok(removed)
+}
+
+/// Write a cell and all cells it depends on to a new GDS file.
+///
+/// # Arguments
+/// * `cell` - Name of the cell to extract
+/// * `input` - Path to the GDS file
+/// * `output` - Output GDS file path
+/// * `max_points` - Most XY points written per boundary or path
+pub fn extract_cell(
+    cell: &str,
+    input: &PathBuf,
+    output: &PathBuf,
+    max_points: usize,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let lib = GdsLibrary::load(input)?;
+    let mut extracted = extract_cell_library(&lib, cell)?;
+    println!("Extracted {} cells below {}", extracted.structs.len(), cell);
+    save_library(&mut extracted, output, max_points)?;
+    Ok(())
+}
+
+/// A new library holding `cell` and its dependency closure, with the units of `lib`.
+///
+/// Structs are in library order and the library is named after `cell`.
+pub fn extract_cell_library(lib: &GdsLibrary, cell: &str) -> Result<GdsLibrary, String> {
+    let keep: HashSet<String> = reachable_cells(lib, &[cell])?.into_iter().collect();
+    Ok(GdsLibrary {
+        name: cell.to_string(),
+        version: lib.version,
+        units: lib.units.clone(),
+        structs: lib
+            .structs
+            .iter()
+            .filter(|s| keep.contains(&s.name))
+            .cloned()
+            .collect(),
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::test_support::{cell_names, library, parent};
+
+    /// `top` places `mid` twice, `mid` places `leaf`; `orphan` and `orphan_leaf` are unused.
+    fn tree() -> GdsLibrary {
+        library(vec![
+            parent("orphan", &["orphan_leaf"]),
+            parent("leaf", &[]),
+            parent("mid", &["leaf"]),
+            parent("orphan_leaf", &[]),
+            parent("top", &["mid", "mid"]),
+        ])
+    }
+
+    #[test]
+    fn unreachable_cells_are_removed_in_library_order() {
+        let mut lib = tree();
+        let removed = prune_library(&mut lib, &["top"]).unwrap();
+        assert_eq!(removed, ["orphan", "orphan_leaf"]);
+        assert_eq!(cell_names(&lib), ["leaf", "mid", "top"]);
+    }
+
+    #[test]
+    fn several_tops_keep_all_their_cells() {
+        let mut lib = tree();
+        let removed = prune_library(&mut lib, &["mid", "orphan"]).unwrap();
+        assert_eq!(removed, ["top"]);
+    }
+
+    #[test]
+    fn missing_cells_are_an_error_and_leave_the_library_untouched() {
+        let mut lib = tree();
+        assert!(prune_library(&mut lib, &["nope"]).is_err());
+        lib.structs.push(parent("broken", &["missing"]));
+        assert!(prune_library(&mut lib, &["broken"]).is_err());
+        assert_eq!(lib.structs.len(), 6);
+    }
+
+    #[test]
+    fn reachable_cells_visit_each_cell_once_depth_first() {
+        let lib = tree();
+        assert_eq!(reachable_cells(&lib, &["top"]).unwrap(), ["top", "mid", "leaf"]);
+    }
+
+    #[test]
+    fn extracted_library_is_named_after_the_cell() {
+        let extracted = extract_cell_library(&tree(), "mid").unwrap();
+        assert_eq!(extracted.name, "mid");
+        assert_eq!(cell_names(&extracted), ["leaf", "mid"]);
+    }
+}
@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use gds21::GdsLibrary;
+use regex::Regex;
+
+use super::hierarchy::rename_references;
+use super::replace_all::read_replacements_csv;
+use super::vertex_limit::save_library;
+
+/// Maximum struct name length allowed by the GDSII stream format.
+pub const GDS_MAX_NAME_LENGTH: usize = 32;
+
+/// Rename the structs of a GDS file and write the result.
+///
+/// # Arguments
+/// * `input` - Path to the GDS file
+/// * `output` - Output GDS file path
+/// * `rules` - Pairs of regular expression and replacement, applied in order
+/// * `mapping` - CSV file of `old,new` name pairs, taking precedence over `rules`
+/// * `max_length` - Longest struct name accepted
+/// * `dry_run` - Only print the renames, do not write `output`
+/// * `max_points` - Most XY points written per boundary or path
+pub fn rename_gds_cells(
+    input: &PathBuf,
+    output: Option<&PathBuf>,
+    rules: &[(&str, &str)],
+    mapping: Option<&PathBuf>,
+    max_length: usize,
+    dry_run: bool,
+    max_points: usize,
+) -> Result<HashMap<String, String>, Box<dyn std::error::Error>> {
+    let mut lib = GdsLibrary::load(input)?;
+    let rules = rules
+        .iter()
+        .map(|(pattern, replacement)| Ok((Regex::new(pattern)?, replacement.to_string())))
+        .collect::<Result<Vec<_>, regex::Error>>()?;
+    let mapping = match mapping {
+        Some(path) => read_replacements_csv(path)?,
+        None => HashMap::new(),
+    };
+
+    let renames = cell_renames(&lib, &rules, &mapping, max_length)?;
+    let mut sorted: Vec<_> = renames.iter().collect();
+    sorted.sort();
+    for (old, new) in sorted {
+        println!("Renaming {} to {}", old, new);
+    }
+    if !dry_run {
+        apply_renames(&mut lib, &renames);
+        let output = output.ok_or("an output path is required unless --dry-run is given")?;
+        save_library(&mut lib, output, max_points)?;
+    }
+    Ok(renames)
+}
+
+/// Work out the new name of every struct of a library.
+///
+/// A struct listed in `mapping` takes its name from there, every other struct has all `rules`
+/// applied to its name in turn. Only structs whose name changes are returned. Renames that
+/// make two structs share a name, or produce a name longer than `max_length`, are an error.
+pub fn cell_renames(
+    lib: &GdsLibrary,
+    rules: &[(Regex, String)],
+    mapping: &HashMap<String, String>,
+    max_length: usize,
+) -> Result<HashMap<String, String>, String> {
+    for old in mapping.keys() {
+        if !lib.structs.iter().any(|s| &s.name == old) {
+            println!("Warning: cell {} of the mapping is not defined in the library", old);
+        }
+    }
+
+    let mut renames = HashMap::new();
+    let mut owners: HashMap<String, &str> = HashMap::new();
+    for s in &lib.structs {
+        let new_name = match mapping.get(&s.name) {
+            Some(new_name) => new_name.clone(),
+            None => rules.iter().fold(s.name.clone(), |name, (re, replacement)| {
+                re.replace_all(&name, replacement.as_str()).into_owned()
+            }),
+        };
+        if new_name.is_empty() {
+            return Err(format!("cell {} would be renamed to an empty name", s.name));
+        }
+        if new_name != s.name && new_name.len() > max_length {
+            return Err(format!(
+                "new name {} of cell {} exceeds {} characters",
+                new_name, s.name, max_length
+            ));
+        }
+        if let Some(other) = owners.insert(new_name.clone(), &s.name) {
+            return Err(format!(
+                "cells {} and {} would both be named {}",
+                other, s.name, new_name
+            ));
+        }
+        if new_name != s.name {
+            renames.insert(s.name.clone(), new_name);
+        }
+    }
+    Ok(renames)
+}
+
+/// Rename structs and every reference to them throughout the library.
+pub fn apply_renames(lib: &mut GdsLibrary, renames: &HashMap<String, String>) {
+    for s in &mut lib.structs {
+        rename_references(s, renames);
+        if let Some(new_name) = renames.get(&s.name) {
+            s.name = new_name.clone();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::test_support::{cell, library, sref};
+
+    fn rules(rules: &[(&str, &str)]) -> Vec<(Regex, String)> {
+        rules
+            .iter()
+            .map(|(pattern, replacement)| (Regex::new(pattern).unwrap(), replacement.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn rules_apply_in_order_and_mapping_takes_precedence() {
+        let lib = library(vec![cell("old_a", vec![]), cell("old_b", vec![]), cell("keep", vec![])]);
+        let mapping = HashMap::from([("old_b".to_string(), "mapped".to_string())]);
+        let renames = cell_renames(&lib, &rules(&[("old", "new"), ("new_", "n_")]), &mapping, 32).unwrap();
+        assert_eq!(renames.len(), 2);
+        assert_eq!(renames["old_a"], "n_a");
+        assert_eq!(renames["old_b"], "mapped");
+    }
+
+    #[test]
+    fn collisions_are_rejected() {
+        let lib = library(vec![cell("a_1", vec![]), cell("a_2", vec![])]);
+        let err = cell_renames(&lib, &rules(&[("_[0-9]", "")]), &HashMap::new(), 32).unwrap_err();
+        assert!(err.contains("a_1") && err.contains("a_2"), "{}", err);
+
+        // Renaming onto a name that stays in use is a collision too.
+        let lib = library(vec![cell("a", vec![]), cell("b", vec![])]);
+        let mapping = HashMap::from([("a".to_string(), "b".to_string())]);
+        assert!(cell_renames(&lib, &[], &mapping, 32).is_err());
+    }
+
+    #[test]
+    fn swapping_names_is_allowed() {
+        let lib = library(vec![cell("a", vec![]), cell("b", vec![])]);
+        let mapping = HashMap::from([
+            ("a".to_string(), "b".to_string()),
+            ("b".to_string(), "a".to_string()),
+        ]);
+        let renames = cell_renames(&lib, &[], &mapping, 32).unwrap();
+        assert_eq!(renames.len(), 2);
+    }
+
+    #[test]
+    fn empty_and_long_names_are_rejected() {
+        let lib = library(vec![cell("abc", vec![])]);
+        assert!(cell_renames(&lib, &rules(&[(".*", "")]), &HashMap::new(), 32).is_err());
+        assert!(cell_renames(&lib, &rules(&[("abc", "abcdef")]), &HashMap::new(), 4).is_err());
+    }
+
+    #[test]
+    fn apply_renames_rewrites_references() {
+        let mut lib = library(vec![cell("leaf", vec![]), cell("top", vec![sref("leaf"), sref("other")])]);
+        let renames = HashMap::from([("leaf".to_string(), "LEAF".to_string())]);
+        apply_renames(&mut lib, &renames);
+        assert_eq!(lib.structs[0].name, "LEAF");
+        let names: Vec<&str> = lib.structs[1]
+            .elems
+            .iter()
+            .filter_map(|e| match e {
+                GdsElement::GdsStructRef(r) => Some(r.name.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(names, ["LEAF", "other"]);
+    }
+}
@@ -11,7 +11,7 @@ use std::path::{Path, PathBuf};
 use csv::{Reader, ReaderBuilder};
 use toml;
 
-fn read_replacements_csv<P: AsRef<Path>>(path: P) -> Result<HashMap<String, String>, Box<dyn Error>> {
+pub fn read_replacements_csv<P: AsRef<Path>>(path: P) -> Result<HashMap<String, String>, Box<dyn Error>> {
     let mut reader =  ReaderBuilder::new()
         .delimiter(b',')
         .has_headers(false)
@@ -0,0 +1,265 @@
+use std::path::PathBuf;
+
+use gds21::GdsLibrary;
+use iron_shapes::prelude::*;
+use iron_shapes_booleanop::BooleanOp;
+
+use super::flatten::flat_polygons;
+use super::geometry::{convex_hull, merge_polygons, polygon_area, polygons_to_boundaries, to_polygon};
+use super::layer_map::GdsLayer;
+use super::vertex_limit::save_library;
+
+/// Number of segments approximating a full circle for round corners.
+const ROUND_SEGMENTS: usize = 32;
+
+/// Shape of the corners produced when growing convex corners or shrinking concave ones.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CornerMode {
+    Square,
+    Octagon,
+    Round,
+}
+
+impl std::str::FromStr for CornerMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "square" => Ok(CornerMode::Square),
+            "octagon" => Ok(CornerMode::Octagon),
+            "round" => Ok(CornerMode::Round),
+            _ => Err(format!("unknown corner mode {}, expected square, octagon or round", s)),
+        }
+    }
+}
+
+/// How to size the polygons of a layer.
+#[derive(Clone, Copy, Debug)]
+pub struct SizingOptions {
+    /// Distance in database units added in x direction, negative to shrink.
+    pub dx: i64,
+    /// Distance in database units added in y direction, with the same sign as `dx`.
+    pub dy: i64,
+    pub corners: CornerMode,
+    /// After shrinking, pieces narrower than this many database units are removed.
+    pub min_width: Option<i64>,
+}
+
+/// Grow or shrink the flattened polygons of a layer and write them to a target layer.
+///
+/// # Arguments
+/// * `top` - Cell whose flattened geometry is sized, receiving the result
+/// * `input` - Path to the GDS file
+/// * `output` - Output GDS file path
+/// * `layer` - Layer/datatype to size
+/// * `target` - Layer/datatype receiving the result, different from `layer`
+/// * `options` - Sizing distances and corner mode
+/// * `max_points` - Most XY points written per boundary or path
+pub fn size_gds_layer(
+    top: &str,
+    input: &PathBuf,
+    output: &PathBuf,
+    layer: GdsLayer,
+    target: GdsLayer,
+    options: &SizingOptions,
+    max_points: usize,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if layer == target {
+        return Err("the sized polygons need a target layer different from the source".into());
+    }
+    let mut lib = GdsLibrary::load(input)?;
+    let polygons = flat_polygons(&lib, top)?.remove(&layer).unwrap_or_default();
+    let merged = merge_polygons(polygons.iter().map(|p| to_polygon(p)).collect());
+    let sized = size_polygons(&merged, options)?;
+
+    let (boundaries, warnings) = polygons_to_boundaries(&sized, target.0, target.1);
+    for warning in warnings {
+        println!("{}", warning);
+    }
+    let top_struct = lib.structs.iter_mut().find(|s| s.name == top).unwrap();
+    top_struct.elems.extend(boundaries);
+    save_library(&mut lib, output, max_points)?;
+    Ok(())
+}
+
+/// Grow or shrink merged polygons.
+///
+/// The result is the Minkowski sum (growing) or difference (shrinking) of the polygons with
+/// a rectangle, octagon or ellipse of half-extent `dx` by `dy`. Grown polygons come back
+/// merged; shrinking drops pieces without area and, if requested, narrow slivers.
+pub fn size_polygons(polygons: &MultiPolygon<i64>, options: &SizingOptions) -> Result<MultiPolygon<i64>, String> {
+    let SizingOptions { dx, dy, corners, min_width } = *options;
+    if dx.signum() * dy.signum() < 0 {
+        return Err(format!("cannot grow and shrink at once, got dx {} and dy {}", dx, dy));
+    }
+    if dx == 0 && dy == 0 {
+        return Ok(polygons.clone());
+    }
+    let kernel = sizing_kernel(dx.abs(), dy.abs(), corners);
+    let band = merge_polygons(edge_sweeps(polygons, &kernel));
+    if dx > 0 || dy > 0 {
+        return Ok(polygons.union(&band));
+    }
+
+    let shrunk = polygons.difference(&band);
+    let pieces = shrunk
+        .polygons
+        .into_iter()
+        .filter(|p| polygon_area(p) > 0.0)
+        .filter(|p| match min_width {
+            Some(w) if w > 1 => {
+                let half = (w - 1) / 2;
+                let probe = SizingOptions {
+                    dx: -half,
+                    dy: -half,
+                    corners: CornerMode::Square,
+                    min_width: None,
+                };
+                let piece = MultiPolygon::from_polygons(vec![p.clone()]);
+                size_polygons(&piece, &probe)
+                    .map(|eroded| !eroded.polygons.is_empty())
+                    .unwrap_or(true)
+            }
+            _ => true,
+        })
+        .collect();
+    Ok(MultiPolygon::from_polygons(pieces))
+}
+
+/// The convex shape swept along the polygon edges, centred on the origin.
+fn sizing_kernel(dx: i64, dy: i64, corners: CornerMode) -> Vec<Point<i64>> {
+    let (fx, fy) = (dx as f64, dy as f64);
+    let points: Vec<(f64, f64)> = match corners {
+        CornerMode::Square => vec![(fx, fy), (-fx, fy), (-fx, -fy), (fx, -fy)],
+        CornerMode::Octagon => {
+            let t = std::f64::consts::SQRT_2 - 1.0;
+            vec![
+                (fx, t * fy),
+                (t * fx, fy),
+                (-t * fx, fy),
+                (-fx, t * fy),
+                (-fx, -t * fy),
+                (-t * fx, -fy),
+                (t * fx, -fy),
+                (fx, -t * fy),
+            ]
+        }
+        CornerMode::Round => (0..ROUND_SEGMENTS)
+            .map(|i| {
+                let a = 2.0 * std::f64::consts::PI * i as f64 / ROUND_SEGMENTS as f64;
+                (fx * a.cos(), fy * a.sin())
+            })
+            .collect(),
+    };
+    let points = points
+        .into_iter()
+        .map(|(x, y)| Point::new(x.round() as i64, y.round() as i64))
+        .collect();
+    convex_hull(points)
+}
+
+/// The area swept by the kernel moving along every edge, holes included.
+fn edge_sweeps(polygons: &MultiPolygon<i64>, kernel: &[Point<i64>]) -> Vec<Polygon<i64>> {
+    let mut sweeps = vec![];
+    for polygon in &polygons.polygons {
+        for ring in std::iter::once(&polygon.exterior).chain(polygon.interiors.iter()) {
+            let points = ring.points();
+            for i in 0..points.len() {
+                let (a, b) = (points[i], points[(i + 1) % points.len()]);
+                let hull = convex_hull(
+                    kernel
+                        .iter()
+                        .flat_map(|k| [Point::new(a.x + k.x, a.y + k.y), Point::new(b.x + k.x, b.y + k.y)])
+                        .collect(),
+                );
+                if hull.len() >= 3 {
+                    sweeps.push(Polygon {
+                        exterior: SimplePolygon::new(hull),
+                        interiors: vec![],
+                    });
+                }
+            }
+        }
+    }
+    sweeps
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::geometry::rect_polygon;
+
+    fn options(d: i64, corners: CornerMode) -> SizingOptions {
+        SizingOptions {
+            dx: d,
+            dy: d,
+            corners,
+            min_width: None,
+        }
+    }
+
+    fn area(polygons: &MultiPolygon<i64>) -> f64 {
+        polygons.polygons.iter().map(polygon_area).sum()
+    }
+
+    fn square() -> MultiPolygon<i64> {
+        MultiPolygon::from_polygons(vec![rect_polygon(0, 0, 100, 100)])
+    }
+
+    #[test]
+    fn square_corners_grow_and_shrink_by_the_distance() {
+        let grown = size_polygons(&square(), &options(10, CornerMode::Square)).unwrap();
+        assert_eq!(grown.polygons.len(), 1);
+        assert_eq!(area(&grown), 120.0 * 120.0);
+
+        let shrunk = size_polygons(&square(), &options(-10, CornerMode::Square)).unwrap();
+        assert_eq!(area(&shrunk), 80.0 * 80.0);
+    }
+
+    #[test]
+    fn octagon_and_round_corners_cut_the_grown_corners() {
+        let square_area = 120.0 * 120.0;
+        let octagon = area(&size_polygons(&square(), &options(10, CornerMode::Octagon)).unwrap());
+        let round = area(&size_polygons(&square(), &options(10, CornerMode::Round)).unwrap());
+        let circle_area = 100.0 * 100.0 + 4.0 * 100.0 * 10.0 + std::f64::consts::PI * 100.0;
+        assert!(octagon < square_area && octagon > circle_area);
+        assert!((round - circle_area).abs() < 20.0);
+    }
+
+    #[test]
+    fn shrinking_removes_narrow_pieces() {
+        // A square and a separate 30 wide bar, shrunk to 80 and 10 wide pieces.
+        let shape = merge_polygons(vec![rect_polygon(0, 0, 100, 100), rect_polygon(200, 0, 400, 30)]);
+        let shrunk = size_polygons(&shape, &options(-10, CornerMode::Square)).unwrap();
+        assert_eq!(shrunk.polygons.len(), 2);
+
+        let sliver_free = SizingOptions {
+            min_width: Some(20),
+            ..options(-10, CornerMode::Square)
+        };
+        let shrunk = size_polygons(&shape, &sliver_free).unwrap();
+        assert_eq!(shrunk.polygons.len(), 1);
+        assert_eq!(area(&shrunk), 80.0 * 80.0);
+
+        let gone = size_polygons(&shape, &options(-60, CornerMode::Square)).unwrap();
+        assert!(gone.polygons.is_empty());
+    }
+
+    #[test]
+    fn growing_and_shrinking_at_once_is_an_error() {
+        let mixed = SizingOptions {
+            dx: 10,
+            dy: -10,
+            corners: CornerMode::Square,
+            min_width: None,
+        };
+        assert!(size_polygons(&square(), &mixed).is_err());
+        assert_eq!(area(&size_polygons(&square(), &options(0, CornerMode::Square)).unwrap()), 100.0 * 100.0);
+    }
+
+    #[test]
+    fn corner_modes_parse_from_their_names() {
+        assert_eq!("octagon".parse::<CornerMode>(), Ok(CornerMode::Octagon));
+        assert!("diamond".parse::<CornerMode>().is_err());
+    }
+}
@@ -0,0 +1,100 @@
+//! Fixtures shared by the unit tests of the commands.
+
+use gds21::{GdsArrayRef, GdsBoundary, GdsElement, GdsLibrary, GdsPoint, GdsStrans, GdsStruct, GdsStructRef, GdsUnits};
+
+/// GDS points from coordinate pairs.
+pub fn points(xy: &[(i32, i32)]) -> Vec<GdsPoint> {
+    xy.iter().map(|&(x, y)| GdsPoint { x, y }).collect()
+}
+
+/// A boundary on layer 1/0 through the points as given, closed or not.
+pub fn boundary(xy: &[(i32, i32)]) -> GdsElement {
+    GdsElement::GdsBoundary(GdsBoundary {
+        layer: 1,
+        datatype: 0,
+        xy: points(xy),
+        ..Default::default()
+    })
+}
+
+/// A closed rectangular boundary on layer 1/0.
+pub fn rect(x0: i32, y0: i32, x1: i32, y1: i32) -> GdsElement {
+    boundary(&[(x0, y0), (x1, y0), (x1, y1), (x0, y1), (x0, y0)])
+}
+
+/// A `size` by `size` square with its lower left corner at the origin.
+pub fn square(size: i32) -> GdsElement {
+    rect(0, 0, size, size)
+}
+
+/// A reference to `name` placed at `(x, y)`.
+pub fn sref_at(name: &str, x: i32, y: i32, strans: Option<GdsStrans>) -> GdsElement {
+    GdsElement::GdsStructRef(GdsStructRef {
+        name: name.to_string(),
+        xy: GdsPoint { x, y },
+        strans,
+        elflags: None,
+        plex: None,
+        properties: vec![],
+    })
+}
+
+/// An untransformed reference to `name` at the origin.
+pub fn sref(name: &str) -> GdsElement {
+    sref_at(name, 0, 0, None)
+}
+
+/// A `cols` by `rows` array of `name` at the origin, on a 2000 unit pitch.
+pub fn aref(name: &str, cols: i16, rows: i16) -> GdsElement {
+    GdsElement::GdsArrayRef(GdsArrayRef {
+        name: name.to_string(),
+        xy: [
+            GdsPoint { x: 0, y: 0 },
+            GdsPoint { x: 2000 * cols as i32, y: 0 },
+            GdsPoint { x: 0, y: 2000 * rows as i32 },
+        ],
+        cols,
+        rows,
+        strans: None,
+        elflags: None,
+        plex: None,
+        properties: vec![],
+    })
+}
+
+pub fn cell(name: &str, elems: Vec<GdsElement>) -> GdsStruct {
+    GdsStruct {
+        name: name.to_string(),
+        dates: Default::default(),
+        elems,
+    }
+}
+
+/// A struct placing each of `children` once, at the origin.
+pub fn parent(name: &str, children: &[&str]) -> GdsStruct {
+    cell(name, children.iter().map(|child| sref(child)).collect())
+}
+
+/// A library named `lib` in the gds21 default units.
+pub fn library(structs: Vec<GdsStruct>) -> GdsLibrary {
+    GdsLibrary {
+        name: "lib".to_string(),
+        structs,
+        ..Default::default()
+    }
+}
+
+/// A library with database units of `db_unit` meters and a user unit of one micron.
+pub fn library_in_units(name: &str, db_unit: f64, structs: Vec<GdsStruct>) -> GdsLibrary {
+    GdsLibrary {
+        name: name.to_string(),
+        units: GdsUnits::new(db_unit / 1e-6, db_unit),
+        structs,
+        ..Default::default()
+    }
+}
+
+/// The struct names of a library, in library order.
+pub fn cell_names(lib: &GdsLibrary) -> Vec<&str> {
+    lib.structs.iter().map(|s| s.name.as_str()).collect()
+}
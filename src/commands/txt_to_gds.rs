@@ -0,0 +1,447 @@
+use std::error::Error;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use chrono::NaiveDateTime;
+use gds21::{
+    GdsArrayRef, GdsBoundary, GdsBox, GdsDateTimes, GdsElemFlags, GdsElement, GdsLibrary,
+    GdsNode, GdsPath, GdsPlex, GdsPoint, GdsPresentation, GdsProperty, GdsStrans, GdsStruct,
+    GdsStructRef, GdsTextElem, GdsUnits,
+};
+use serde::de::DeserializeOwned;
+
+use super::gds_to_txt::{STRANS_ABS_ANGLE, STRANS_ABS_MAG, STRANS_REFLECTED};
+use super::vertex_limit::save_library;
+
+/// Date format accepted in the BGNLIB and BGNSTR records, month and day may be unpadded.
+const DATE_PARSE_FORMAT: &str = "%m/%d/%Y %H:%M:%S";
+
+/// Convert a textual record dump, as written by `gds2txt`, back into a GDS file.
+///
+/// # Arguments
+/// * `input` - Path to the record dump
+/// * `output` - Output GDS file path
+/// * `max_points` - Most XY points written per boundary or path
+pub fn convert_txt_to_gds(input: &PathBuf, output: &PathBuf, max_points: usize) -> Result<(), Box<dyn Error>> {
+    let text = std::fs::read_to_string(input)?;
+    let mut lib = txt_to_gds_library(&text)?;
+    save_library(&mut lib, output, max_points)?;
+    Ok(())
+}
+
+/// Records collected between an element keyword and its `ENDEL`.
+#[derive(Default)]
+struct ElementRecords {
+    kind: String,
+    layer: i16,
+    /// DATATYPE, TEXTTYPE, NODETYPE or BOXTYPE, depending on the element kind.
+    kind_type: i16,
+    name: String,
+    string: String,
+    xy: Vec<GdsPoint>,
+    width: Option<i32>,
+    path_type: Option<i16>,
+    begin_extn: Option<i32>,
+    end_extn: Option<i32>,
+    strans: Option<GdsStrans>,
+    cols: i16,
+    rows: i16,
+    presentation: Option<GdsPresentation>,
+    elflags: Option<GdsElemFlags>,
+    plex: Option<GdsPlex>,
+    properties: Vec<GdsProperty>,
+    pending_attr: Option<i16>,
+}
+
+impl ElementRecords {
+    fn strans_mut(&mut self) -> &mut GdsStrans {
+        self.strans.get_or_insert_with(|| GdsStrans {
+            reflected: false,
+            abs_mag: false,
+            abs_angle: false,
+            mag: None,
+            angle: None,
+        })
+    }
+
+    fn into_element(self, line_no: usize) -> Result<GdsElement, Box<dyn Error>> {
+        let element = match self.kind.as_str() {
+            "BOUNDARY" => GdsElement::GdsBoundary(GdsBoundary {
+                layer: self.layer,
+                datatype: self.kind_type,
+                xy: self.xy,
+                elflags: self.elflags,
+                plex: self.plex,
+                properties: self.properties,
+            }),
+            "PATH" => GdsElement::GdsPath(GdsPath {
+                layer: self.layer,
+                datatype: self.kind_type,
+                xy: self.xy,
+                width: self.width,
+                path_type: self.path_type,
+                begin_extn: self.begin_extn,
+                end_extn: self.end_extn,
+                elflags: self.elflags,
+                plex: self.plex,
+                properties: self.properties,
+            }),
+            "SREF" => GdsElement::GdsStructRef(GdsStructRef {
+                name: self.name,
+                xy: single_point(self.xy, line_no)?,
+                strans: self.strans,
+                elflags: self.elflags,
+                plex: self.plex,
+                properties: self.properties,
+            }),
+            "AREF" => GdsElement::GdsArrayRef(GdsArrayRef {
+                name: self.name,
+                xy: fixed_points::<3>(self.xy, line_no)?,
+                cols: self.cols,
+                rows: self.rows,
+                strans: self.strans,
+                elflags: self.elflags,
+                plex: self.plex,
+                properties: self.properties,
+            }),
+            "TEXT" => GdsElement::GdsTextElem(GdsTextElem {
+                string: self.string,
+                layer: self.layer,
+                texttype: self.kind_type,
+                xy: single_point(self.xy, line_no)?,
+                strans: self.strans,
+                width: self.width,
+                presentation: self.presentation,
+                path_type: self.path_type,
+                elflags: self.elflags,
+                plex: self.plex,
+                properties: self.properties,
+            }),
+            "NODE" => GdsElement::GdsNode(GdsNode {
+                layer: self.layer,
+                nodetype: self.kind_type,
+                xy: self.xy,
+                elflags: self.elflags,
+                plex: self.plex,
+                properties: self.properties,
+            }),
+            "BOX" => GdsElement::GdsBox(GdsBox {
+                layer: self.layer,
+                boxtype: self.kind_type,
+                xy: fixed_points::<5>(self.xy, line_no)?,
+                elflags: self.elflags,
+                plex: self.plex,
+                properties: self.properties,
+            }),
+            kind => return Err(format!("line {}: unknown element {}", line_no, kind).into()),
+        };
+        Ok(element)
+    }
+}
+
+/// Parse a textual record dump into a `GdsLibrary`.
+///
+/// # Arguments
+/// * `text` - The dump, in the format written by `gds_library_to_txt`.
+///
+/// # Returns
+/// The parsed library, or an error naming the offending line.
+pub fn txt_to_gds_library(text: &str) -> Result<GdsLibrary, Box<dyn Error>> {
+    let mut lib = GdsLibrary {
+        ..Default::default()
+    };
+    let mut current_struct: Option<GdsStruct> = None;
+    let mut current_elem: Option<ElementRecords> = None;
+    let mut ended = false;
+
+    for (i, line) in text.lines().enumerate() {
+        let line_no = i + 1;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if ended {
+            return Err(format!("line {}: records after ENDLIB", line_no).into());
+        }
+        let (keyword, value) = line.split_once(' ').unwrap_or((line, ""));
+        let value = value.trim();
+
+        // Continuation lines of an XY record only hold a point.
+        if keyword.ends_with(':') {
+            let elem = current_elem
+                .as_mut()
+                .ok_or_else(|| format!("line {}: coordinates outside of an element", line_no))?;
+            elem.xy.push(parse_point(line, line_no)?);
+            continue;
+        }
+
+        if let Some(elem) = current_elem.as_mut() {
+            match keyword {
+                "ENDEL" => {
+                    let elem = current_elem.take().unwrap().into_element(line_no)?;
+                    current_struct
+                        .as_mut()
+                        .ok_or_else(|| format!("line {}: element outside of a struct", line_no))?
+                        .elems
+                        .push(elem);
+                }
+                "LAYER" => elem.layer = parse_value(value, line_no)?,
+                "DATATYPE" | "TEXTTYPE" | "NODETYPE" | "BOXTYPE" => {
+                    elem.kind_type = parse_value(value, line_no)?
+                }
+                "SNAME" => elem.name = value.to_string(),
+                "STRING" => elem.string = value.to_string(),
+                "XY" => elem.xy.push(parse_point(value, line_no)?),
+                "WIDTH" => elem.width = Some(parse_value(value, line_no)?),
+                "PATHTYPE" => elem.path_type = Some(parse_value(value, line_no)?),
+                "BGNEXTN" => elem.begin_extn = Some(parse_value(value, line_no)?),
+                "ENDEXTN" => elem.end_extn = Some(parse_value(value, line_no)?),
+                "STRANS" => {
+                    let bits = parse_value::<i16>(value, line_no)? as u16;
+                    let strans = elem.strans_mut();
+                    strans.reflected = bits & STRANS_REFLECTED != 0;
+                    strans.abs_mag = bits & STRANS_ABS_MAG != 0;
+                    strans.abs_angle = bits & STRANS_ABS_ANGLE != 0;
+                }
+                "MAG" => elem.strans_mut().mag = Some(parse_value(value, line_no)?),
+                "ANGLE" => elem.strans_mut().angle = Some(parse_value(value, line_no)?),
+                "COLROW" => {
+                    let (cols, rows) = value
+                        .split_once(' ')
+                        .ok_or_else(|| format!("line {}: expected COLROW cols rows", line_no))?;
+                    elem.cols = parse_value(cols, line_no)?;
+                    elem.rows = parse_value(rows, line_no)?;
+                }
+                "PRESENTATION" => elem.presentation = Some(parse_opaque_pair(value, line_no)?),
+                "ELFLAGS" => elem.elflags = Some(parse_opaque_pair(value, line_no)?),
+                "PLEX" => {
+                    let plex: i32 = parse_value(value, line_no)?;
+                    elem.plex = Some(serde_json::from_value(serde_json::json!(plex))?);
+                }
+                "PROPATTR" => elem.pending_attr = Some(parse_value(value, line_no)?),
+                "PROPVALUE" => {
+                    let attr = elem.pending_attr.take().ok_or_else(|| {
+                        format!("line {}: PROPVALUE without PROPATTR", line_no)
+                    })?;
+                    elem.properties.push(GdsProperty {
+                        attr,
+                        value: value.to_string(),
+                    });
+                }
+                _ => {
+                    return Err(
+                        format!("line {}: unexpected record {} in element", line_no, keyword).into(),
+                    )
+                }
+            }
+            continue;
+        }
+
+        match keyword {
+            "HEADER" => lib.version = parse_value(value, line_no)?,
+            "BGNLIB" => lib.dates = parse_dates(value, line_no)?,
+            "LIBNAME" => lib.name = value.to_string(),
+            "UNITS" => {
+                let (user_units, db_units) = value
+                    .split_once(' ')
+                    .ok_or_else(|| format!("line {}: expected UNITS user db", line_no))?;
+                lib.units = GdsUnits::new(
+                    parse_value(user_units, line_no)?,
+                    parse_value(db_units, line_no)?,
+                );
+            }
+            "BGNSTR" => {
+                if current_struct.is_some() {
+                    return Err(format!("line {}: BGNSTR inside of a struct", line_no).into());
+                }
+                current_struct = Some(GdsStruct {
+                    name: String::new(),
+                    dates: parse_dates(value, line_no)?,
+                    elems: vec![],
+                });
+            }
+            "STRNAME" => {
+                current_struct
+                    .as_mut()
+                    .ok_or_else(|| format!("line {}: STRNAME outside of a struct", line_no))?
+                    .name = value.to_string();
+            }
+            "ENDSTR" => {
+                let gds_struct = current_struct
+                    .take()
+                    .ok_or_else(|| format!("line {}: ENDSTR without BGNSTR", line_no))?;
+                lib.structs.push(gds_struct);
+            }
+            "ENDLIB" => ended = true,
+            "BOUNDARY" | "PATH" | "SREF" | "AREF" | "TEXT" | "NODE" | "BOX" => {
+                if current_struct.is_none() {
+                    return Err(format!("line {}: element outside of a struct", line_no).into());
+                }
+                current_elem = Some(ElementRecords {
+                    kind: keyword.to_string(),
+                    ..Default::default()
+                });
+            }
+            _ => return Err(format!("line {}: unexpected record {}", line_no, keyword).into()),
+        }
+    }
+
+    if !ended {
+        return Err("missing ENDLIB record".into());
+    }
+    Ok(lib)
+}
+
+fn parse_value<T: FromStr>(value: &str, line_no: usize) -> Result<T, Box<dyn Error>> {
+    value
+        .trim()
+        .parse::<T>()
+        .map_err(|_| format!("line {}: cannot parse '{}'", line_no, value).into())
+}
+
+/// Parse a point written as `x: y`.
+fn parse_point(value: &str, line_no: usize) -> Result<GdsPoint, Box<dyn Error>> {
+    let (x, y) = value
+        .split_once(':')
+        .ok_or_else(|| format!("line {}: expected a point x: y", line_no))?;
+    Ok(GdsPoint {
+        x: parse_value(x, line_no)?,
+        y: parse_value(y, line_no)?,
+    })
+}
+
+fn single_point(xy: Vec<GdsPoint>, line_no: usize) -> Result<GdsPoint, Box<dyn Error>> {
+    let [point] = fixed_points::<1>(xy, line_no)?;
+    Ok(point)
+}
+
+fn fixed_points<const N: usize>(
+    xy: Vec<GdsPoint>,
+    line_no: usize,
+) -> Result<[GdsPoint; N], Box<dyn Error>> {
+    let len = xy.len();
+    xy.try_into()
+        .map_err(|_| format!("line {}: expected {} points, found {}", line_no, N, len).into())
+}
+
+fn parse_dates(value: &str, line_no: usize) -> Result<GdsDateTimes, Box<dyn Error>> {
+    let parts: Vec<&str> = value.split_whitespace().collect();
+    if parts.len() != 4 {
+        return Err(format!("line {}: expected two date-times", line_no).into());
+    }
+    let parse = |date: &str, time: &str| {
+        NaiveDateTime::parse_from_str(&format!("{} {}", date, time), DATE_PARSE_FORMAT)
+            .map_err(|e| format!("line {}: {}", line_no, e))
+    };
+    Ok(GdsDateTimes {
+        modified: parse(parts[0], parts[1])?,
+        accessed: parse(parts[2], parts[3])?,
+    })
+}
+
+/// Unpack a two-byte flag record written by `gds2txt` into the private gds21 representation.
+fn parse_opaque_pair<T: DeserializeOwned>(value: &str, line_no: usize) -> Result<T, Box<dyn Error>> {
+    let bits: i64 = parse_value(value, line_no)?;
+    let pair = serde_json::json!([(bits >> 8) & 0xff, bits & 0xff]);
+    Ok(serde_json::from_value(pair)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::gds_to_txt::gds_library_to_txt;
+    use crate::commands::geometry::units_pair;
+
+    /// A dump in the exact format `gds2txt` writes, trailing spaces included.
+    const DUMP: &[&str] = &[
+        "HEADER 5 ",
+        "BGNLIB 5/11/2024 21:58:14 5/11/2024 21:58:14 ",
+        "LIBNAME demo",
+        "UNITS 0.001 1e-09 ",
+        "",
+        "BGNSTR 5/11/2024 21:58:14 5/11/2024 21:58:14 ",
+        "STRNAME leaf",
+        "",
+        "BOUNDARY ",
+        "LAYER 1 ",
+        "DATATYPE 0 ",
+        "XY 0: 0",
+        "10: 0",
+        "10: 10",
+        "0: 0",
+        "ENDEL ",
+        "",
+        "PATH ",
+        "LAYER 2 ",
+        "DATATYPE 3 ",
+        "PATHTYPE 4 ",
+        "WIDTH 20 ",
+        "BGNEXTN 5 ",
+        "ENDEXTN 7 ",
+        "XY 0: 0",
+        "100: 0",
+        "PROPATTR 3 ",
+        "PROPVALUE net1",
+        "ENDEL ",
+        "ENDSTR ",
+        "",
+        "BGNSTR 5/11/2024 21:58:14 5/11/2024 21:58:14 ",
+        "STRNAME top",
+        "SREF ",
+        "SNAME leaf",
+        "STRANS -32768 ",
+        "MAG 2 ",
+        "ANGLE 90 ",
+        "XY 5: -5",
+        "ENDEL ",
+        "",
+        "TEXT ",
+        "LAYER 4 ",
+        "TEXTTYPE 0 ",
+        "XY 1: 2",
+        "STRING hello world",
+        "ENDEL ",
+        "ENDSTR ",
+        "ENDLIB ",
+    ];
+
+    #[test]
+    fn dump_round_trips() {
+        let text = DUMP.join("\n");
+        let lib = txt_to_gds_library(&text).unwrap();
+        assert_eq!(gds_library_to_txt(&lib), text);
+    }
+
+    #[test]
+    fn dump_records_are_parsed() {
+        let lib = txt_to_gds_library(&DUMP.join("\n")).unwrap();
+        assert_eq!(lib.name, "demo");
+        assert_eq!(units_pair(&lib), (1e-3, 1e-9));
+        assert_eq!(lib.structs.len(), 2);
+
+        let GdsElement::GdsPath(path) = &lib.structs[0].elems[1] else {
+            panic!("expected a path");
+        };
+        assert_eq!((path.layer, path.datatype), (2, 3));
+        assert_eq!((path.width, path.path_type), (Some(20), Some(4)));
+        assert_eq!((path.begin_extn, path.end_extn), (Some(5), Some(7)));
+        assert_eq!(path.properties[0].value, "net1");
+
+        let GdsElement::GdsStructRef(sref) = &lib.structs[1].elems[0] else {
+            panic!("expected an SREF");
+        };
+        let strans = sref.strans.as_ref().unwrap();
+        assert!(strans.reflected && !strans.abs_mag && !strans.abs_angle);
+        assert_eq!((strans.mag, strans.angle), (Some(2.0), Some(90.0)));
+        assert_eq!((sref.xy.x, sref.xy.y), (5, -5));
+    }
+
+    #[test]
+    fn malformed_dumps_are_rejected() {
+        let text = DUMP.join("\n");
+        let truncated = text.trim_end_matches("ENDLIB ");
+        assert!(txt_to_gds_library(truncated).is_err());
+        assert!(txt_to_gds_library(&format!("{}\nHEADER 5 ", text)).is_err());
+        assert!(txt_to_gds_library(&text.replace("STRNAME leaf", "STRNAME leaf\nFOO 1 ")).is_err());
+    }
+}
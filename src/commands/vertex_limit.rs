@@ -0,0 +1,348 @@
+use std::path::Path;
+
+use gds21::{GdsBoundary, GdsElement, GdsLibrary, GdsPath, GdsPoint};
+
+use super::geometry::{closed_points, path_outline, split_polygon, to_polygon};
+
+/// Maximum number of XY points of a boundary or path accepted by most readers.
+pub const GDS_MAX_POINTS: usize = 8191;
+
+/// Maximum number of XY points of the original GDSII specification, still enforced by
+/// some legacy tools.
+pub const GDS_LEGACY_MAX_POINTS: usize = 200;
+
+/// Parse a `--max-points` value, a number of at least 5 or `legacy` for
+/// [`GDS_LEGACY_MAX_POINTS`].
+pub fn parse_max_points(s: &str) -> Result<usize, String> {
+    if s.eq_ignore_ascii_case("legacy") {
+        return Ok(GDS_LEGACY_MAX_POINTS);
+    }
+    match s.parse::<usize>() {
+        Ok(n) if n >= 5 => Ok(n),
+        _ => Err(format!("invalid point limit {}, expected a number of at least 5 or legacy", s)),
+    }
+}
+
+/// Write a library after splitting every boundary and path over `max_points` XY points.
+pub fn save_library<P: AsRef<Path>>(
+    lib: &mut GdsLibrary,
+    path: P,
+    max_points: usize,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (split, warnings) = limit_library_points(lib, max_points);
+    for warning in warnings {
+        println!("{}", warning);
+    }
+    if split > 0 {
+        println!("Split {} elements exceeding {} points", split, max_points);
+    }
+    lib.save(path)?;
+    Ok(())
+}
+
+/// Split the boundaries and paths of a library so none has more than `max_points` XY points.
+///
+/// # Returns
+/// The number of elements that were split and warnings about pieces that could not be split.
+pub fn limit_library_points(lib: &mut GdsLibrary, max_points: usize) -> (usize, Vec<String>) {
+    let mut split = 0;
+    let mut warnings = vec![];
+    for s in &mut lib.structs {
+        if !s.elems.iter().any(|e| element_points(e) > max_points) {
+            continue;
+        }
+        let elems = std::mem::take(&mut s.elems);
+        for element in elems {
+            if element_points(&element) > max_points {
+                split += 1;
+                let (pieces, piece_warnings) = limit_element_points(&element, max_points);
+                s.elems.extend(pieces);
+                warnings.extend(piece_warnings.into_iter().map(|w| format!("{}: {}", s.name, w)));
+            } else {
+                s.elems.push(element);
+            }
+        }
+    }
+    (split, warnings)
+}
+
+/// Split an element into pieces of at most `max_points` XY points each.
+///
+/// Boundaries are cut into polygons covering the same area without overlaps or gaps. Paths
+/// are cut at straight-through vertices, so the pieces cover the original path exactly;
+/// paths without such vertices are converted into their outline first.
+///
+/// # Returns
+/// The pieces and warnings about parts that could not be split.
+pub fn limit_element_points(element: &GdsElement, max_points: usize) -> (Vec<GdsElement>, Vec<String>) {
+    match element {
+        GdsElement::GdsBoundary(b) if b.xy.len() > max_points => {
+            let (outlines, warnings) = split_polygon(&to_polygon(&b.xy), max_points - 1);
+            let pieces = outlines
+                .iter()
+                .map(|outline| {
+                    GdsElement::GdsBoundary(GdsBoundary {
+                        xy: closed_points(outline),
+                        ..b.clone()
+                    })
+                })
+                .collect();
+            (pieces, warnings)
+        }
+        GdsElement::GdsPath(p) if p.xy.len() > max_points => match split_path(p, max_points) {
+            Some(pieces) => (pieces.into_iter().map(GdsElement::GdsPath).collect(), vec![]),
+            None => {
+                let outline = GdsElement::GdsBoundary(GdsBoundary {
+                    layer: p.layer,
+                    datatype: p.datatype,
+                    xy: closed_points(&path_outline(p)),
+                    properties: p.properties.clone(),
+                    ..Default::default()
+                });
+                limit_element_points(&outline, max_points)
+            }
+        },
+        _ => (vec![element.clone()], vec![]),
+    }
+}
+
+/// Split a path at vertices where it runs straight on, `None` if that is not possible.
+///
+/// Pieces meet with flush ends. Custom extensions (type 4) stay on the outer ends of the
+/// first and last piece only. Round and half-width ends (types 1 and 2) apply to both ends
+/// of a path, so the first and last piece keep their type and are only cut where the
+/// neighbouring piece covers the half width the inner end reaches past the cut.
+fn split_path(path: &GdsPath, max_points: usize) -> Option<Vec<GdsPath>> {
+    let path_type = path.path_type.unwrap_or(0);
+    let (begin_extn, end_extn) = match path_type {
+        4 => (path.begin_extn.unwrap_or(0), path.end_extn.unwrap_or(0)),
+        _ => (0, 0),
+    };
+    let width = match path_type {
+        1 | 2 => path.width.unwrap_or(0).abs() as i128,
+        _ => 0,
+    };
+    let xy = &path.xy;
+    // Segments next to a cut must be at least half the width long.
+    let long_enough = |a: &GdsPoint, b: &GdsPoint| {
+        let (dx, dy) = (b.x as i128 - a.x as i128, b.y as i128 - a.y as i128);
+        4 * (dx * dx + dy * dy) >= width * width
+    };
+    let straight = |i: usize| {
+        let (a, b, c) = (&xy[i - 1], &xy[i], &xy[i + 1]);
+        let cross = (b.x as i64 - a.x as i64) * (c.y as i64 - b.y as i64)
+            - (b.y as i64 - a.y as i64) * (c.x as i64 - b.x as i64);
+        let dot = (b.x as i64 - a.x as i64) * (c.x as i64 - b.x as i64)
+            + (b.y as i64 - a.y as i64) * (c.y as i64 - b.y as i64);
+        cross == 0 && dot > 0 && long_enough(a, b) && long_enough(b, c)
+    };
+
+    let mut pieces = vec![];
+    let mut start = 0;
+    while start < xy.len() - 1 {
+        let end = if xy.len() - start <= max_points {
+            xy.len() - 1
+        } else {
+            (start + 1..start + max_points).rev().find(|&i| straight(i))?
+        };
+        let first = start == 0;
+        let last = end == xy.len() - 1;
+        let (piece_type, b, e) = match path_type {
+            1 | 2 if first || last => (path_type, 0, 0),
+            4 => {
+                let (b, e) = (if first { begin_extn } else { 0 }, if last { end_extn } else { 0 });
+                (if b == 0 && e == 0 { 0 } else { 4 }, b, e)
+            }
+            _ => (0, 0, 0),
+        };
+        pieces.push(GdsPath {
+            xy: xy[start..=end].to_vec(),
+            path_type: Some(piece_type),
+            begin_extn: (b != 0).then_some(b),
+            end_extn: (e != 0).then_some(e),
+            ..path.clone()
+        });
+        start = end;
+    }
+    Some(pieces)
+}
+
+fn element_points(element: &GdsElement) -> usize {
+    match element {
+        GdsElement::GdsBoundary(b) => b.xy.len(),
+        GdsElement::GdsPath(p) => p.xy.len(),
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::commands::geometry::area_doubled;
+    use crate::commands::test_support::{cell, library};
+
+    /// A closed comb outline with `teeth` teeth, 4 * teeth + 5 points in total.
+    fn comb(teeth: i32) -> GdsElement {
+        let mut xy = vec![(0, 0), (20 * teeth, 0), (20 * teeth, 10)];
+        for i in (0..teeth).rev() {
+            xy.extend([(20 * i + 10, 10), (20 * i + 10, 30), (20 * i, 30), (20 * i, 10)]);
+        }
+        xy.push((0, 0));
+        GdsElement::GdsBoundary(GdsBoundary {
+            layer: 1,
+            datatype: 0,
+            xy: xy.into_iter().map(|(x, y)| GdsPoint { x, y }).collect(),
+            ..Default::default()
+        })
+    }
+
+    fn straight_path(points: i32, path_type: i16) -> GdsPath {
+        GdsPath {
+            layer: 1,
+            datatype: 0,
+            xy: (0..points).map(|i| GdsPoint { x: 100 * i, y: 0 }).collect(),
+            width: Some(10),
+            path_type: Some(path_type),
+            ..Default::default()
+        }
+    }
+
+    fn boundary_area(elements: &[GdsElement]) -> i64 {
+        elements
+            .iter()
+            .map(|e| match e {
+                GdsElement::GdsBoundary(b) => area_doubled(&b.xy).abs(),
+                _ => panic!("expected boundaries"),
+            })
+            .sum()
+    }
+
+    #[test]
+    fn small_elements_are_kept() {
+        let element = comb(2);
+        let (pieces, warnings) = limit_element_points(&element, 200);
+        assert!(warnings.is_empty());
+        match (&pieces[..], &element) {
+            ([GdsElement::GdsBoundary(piece)], GdsElement::GdsBoundary(b)) => assert_eq!(piece.xy, b.xy),
+            _ => panic!("expected the boundary back"),
+        }
+    }
+
+    #[test]
+    fn boundaries_are_split_into_pieces_covering_the_same_area() {
+        let element = comb(10);
+        let (pieces, warnings) = limit_element_points(&element, 8);
+        assert!(warnings.is_empty());
+        assert!(pieces.len() > 1);
+        for piece in &pieces {
+            let GdsElement::GdsBoundary(b) = piece else {
+                panic!("expected a boundary");
+            };
+            assert!(b.xy.len() <= 8, "{} points", b.xy.len());
+            assert_eq!(b.xy.first(), b.xy.last());
+        }
+        assert_eq!(boundary_area(&pieces), boundary_area(&[element]));
+    }
+
+    #[test]
+    fn straight_paths_are_cut_into_abutting_pieces() {
+        let path = straight_path(20, 2);
+        let (pieces, warnings) = limit_element_points(&GdsElement::GdsPath(path.clone()), 5);
+        assert!(warnings.is_empty());
+        let paths: Vec<GdsPath> = pieces
+            .into_iter()
+            .map(|e| match e {
+                GdsElement::GdsPath(p) => p,
+                _ => panic!("expected paths"),
+            })
+            .collect();
+        assert!(paths.iter().all(|p| p.xy.len() <= 5));
+        for pair in paths.windows(2) {
+            assert_eq!(pair[0].xy.last(), pair[1].xy.first());
+        }
+        assert_eq!(paths[0].xy[0], path.xy[0]);
+        assert_eq!(paths.last().unwrap().xy.last(), path.xy.last());
+
+        // The half-width ends of the original path stay on the first and last piece.
+        let first = &paths[0];
+        let last = paths.last().unwrap();
+        assert_eq!((first.path_type, first.begin_extn, first.end_extn), (Some(2), None, None));
+        assert_eq!((last.path_type, last.begin_extn, last.end_extn), (Some(2), None, None));
+        for middle in &paths[1..paths.len() - 1] {
+            assert_eq!((middle.path_type, middle.begin_extn, middle.end_extn), (Some(0), None, None));
+        }
+    }
+
+    #[test]
+    fn custom_extensions_stay_on_the_outer_ends() {
+        let path = GdsPath {
+            begin_extn: Some(3),
+            end_extn: Some(7),
+            ..straight_path(20, 4)
+        };
+        let (pieces, _) = limit_element_points(&GdsElement::GdsPath(path), 5);
+        let paths: Vec<&GdsPath> = pieces
+            .iter()
+            .map(|e| match e {
+                GdsElement::GdsPath(p) => p,
+                _ => panic!("expected paths"),
+            })
+            .collect();
+        let (first, last) = (paths[0], paths[paths.len() - 1]);
+        assert_eq!((first.path_type, first.begin_extn, first.end_extn), (Some(4), Some(3), None));
+        assert_eq!((last.path_type, last.begin_extn, last.end_extn), (Some(4), None, Some(7)));
+        assert!(paths[1..paths.len() - 1].iter().all(|p| p.path_type == Some(0)));
+    }
+
+    #[test]
+    fn round_ended_paths_are_cut_like_the_others() {
+        let path = GdsPath {
+            width: Some(11),
+            ..straight_path(20, 1)
+        };
+        let (pieces, warnings) = limit_element_points(&GdsElement::GdsPath(path), 8);
+        assert!(warnings.is_empty());
+        let types: Vec<Option<i16>> = pieces
+            .iter()
+            .map(|e| match e {
+                GdsElement::GdsPath(p) => {
+                    assert!(p.xy.len() <= 8);
+                    p.path_type
+                }
+                _ => panic!("expected paths"),
+            })
+            .collect();
+        assert!(types.len() > 2);
+        assert_eq!((types[0], types[types.len() - 1]), (Some(1), Some(1)));
+        assert!(types[1..types.len() - 1].iter().all(|t| *t == Some(0)));
+    }
+
+    #[test]
+    fn round_ends_are_not_cut_next_to_short_segments() {
+        // Every segment is shorter than half the width, so no cut is covered.
+        let path = GdsPath {
+            xy: (0..20).map(|i| GdsPoint { x: 4 * i, y: 0 }).collect(),
+            ..straight_path(0, 1)
+        };
+        let (pieces, _) = limit_element_points(&GdsElement::GdsPath(path), 8);
+        assert!(pieces.iter().all(|e| matches!(e, GdsElement::GdsBoundary(_))));
+    }
+
+    #[test]
+    fn point_limits_accept_the_legacy_preset() {
+        assert_eq!(parse_max_points("legacy"), Ok(GDS_LEGACY_MAX_POINTS));
+        assert_eq!(parse_max_points("8191"), Ok(GDS_MAX_POINTS));
+        assert!(parse_max_points("4").is_err());
+        assert!(parse_max_points("many").is_err());
+    }
+
+    #[test]
+    fn library_limit_counts_split_elements() {
+        let mut lib = library(vec![cell("c", vec![comb(10), comb(1)])]);
+        let (split, warnings) = limit_library_points(&mut lib, 8);
+        assert_eq!(split, 1);
+        assert!(warnings.is_empty());
+        assert!(lib.structs[0].elems.len() > 2);
+    }
+}
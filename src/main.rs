@@ -2,10 +2,29 @@
 
 mod commands;
 
-use commands::def_to_gds::convert_def_to_gds;
+use commands::arrays::{compact_gds_arrays, expand_gds_arrays};
+use commands::cell_tree::{print_cell_tree, TreeFormat};
+use commands::dedup_cells::dedup_gds_file;
+use commands::def_grids::GridLayers;
+use commands::def_to_gds::{convert_def_to_gds, GdsWriteOptions};
+use commands::flatten::flatten_cell;
+use commands::gds_bool::{bool_gds_layers, BoolExpression};
+use commands::gds_diff::print_gds_diff;
+use commands::gds_to_txt::convert_gds_to_txt;
+use commands::gds_xor::xor_layouts;
+use commands::layer_map::{layer_table_between, parse_gds_layer, read_layer_table, LayerMap};
+use commands::layer_ops::{edit_gds_layers, print_layers, LayerOperation};
+use commands::library_info::print_library_info;
+use commands::merge_libraries::{merge_gds_files, ConflictPolicy};
+use commands::merge_shapes::{merge_gds_shapes, MergeMode};
 use commands::positions_to_file::extract_layout_data;
+use commands::prune_cells::{extract_cell, prune_gds_file};
+use commands::rename_cells::rename_gds_cells;
 use commands::replace_all::replace_all;
+use commands::sizing::{size_gds_layer, CornerMode, SizingOptions};
 use commands::snap_to_grid::snap_to_grid;
+use commands::txt_to_gds::convert_txt_to_gds;
+use commands::vertex_limit::{parse_max_points, save_library};
 
 use clap::ArgAction;
 use gds21::{GdsLibrary, GdsStruct};
@@ -18,6 +37,12 @@ fn main() {
     let cmd = clap::Command::new("gds")
         .bin_name("gdsu")
         .subcommand_required(true)
+        .arg(
+            clap::arg!(--"max-points" <INT> "Maximum XY points per boundary or path written, or legacy for 200")
+                .value_parser(parse_max_points)
+                .default_value("8191")
+                .global(true),
+        )
         .subcommand(
             clap::command!("print").arg(
                 clap::arg!(<VALUE>)
@@ -55,35 +80,52 @@ fn main() {
                 ),
         )
         .subcommand(
-            clap::command!("extract").subcommand(
-                clap::command!("srefs")
-                    .arg(
-                        clap::arg!(<VALUE>)
-                            .id("top")
-                            .value_parser(clap::value_parser!(String)),
-                    )
-                    .arg(
-                        clap::arg!(--input <PATH>)
-                            .value_parser(clap::value_parser!(std::path::PathBuf)),
-                    )
-                    .arg(
-                        clap::arg!(--"output" <PATH>)
-                            .value_parser(clap::value_parser!(std::path::PathBuf)),
-                    )
-                    .arg(
-                        clap::arg!(--"levels" <INT>)
-                            .value_parser(clap::value_parser!(i32))
-                            .default_value("1"),
-                    )
-                    .arg(
-                        clap::arg!(-P --"patterns" <STRING>)
-                            .action(ArgAction::Append)
-                            .num_args(0..)
-                            // .min_values(1)
-                            .value_parser(clap::value_parser!(String))
-                            .required(false),
-                    ),
-            ),
+            clap::command!("extract")
+                .subcommand(
+                    clap::command!("cell")
+                        .arg(
+                            clap::arg!(<VALUE>)
+                                .id("cell")
+                                .value_parser(clap::value_parser!(String)),
+                        )
+                        .arg(
+                            clap::arg!(--input <PATH>)
+                                .value_parser(clap::value_parser!(std::path::PathBuf)),
+                        )
+                        .arg(
+                            clap::arg!(-o --"output" <PATH>)
+                                .value_parser(clap::value_parser!(std::path::PathBuf)),
+                        ),
+                )
+                .subcommand(
+                    clap::command!("srefs")
+                        .arg(
+                            clap::arg!(<VALUE>)
+                                .id("top")
+                                .value_parser(clap::value_parser!(String)),
+                        )
+                        .arg(
+                            clap::arg!(--input <PATH>)
+                                .value_parser(clap::value_parser!(std::path::PathBuf)),
+                        )
+                        .arg(
+                            clap::arg!(--"output" <PATH>)
+                                .value_parser(clap::value_parser!(std::path::PathBuf)),
+                        )
+                        .arg(
+                            clap::arg!(--"levels" <INT>)
+                                .value_parser(clap::value_parser!(i32))
+                                .default_value("1"),
+                        )
+                        .arg(
+                            clap::arg!(-P --"patterns" <STRING>)
+                                .action(ArgAction::Append)
+                                .num_args(0..)
+                                // .min_values(1)
+                                .value_parser(clap::value_parser!(String))
+                                .required(false),
+                        ),
+                ),
         )
         .subcommand(
             clap::command!("replace").subcommand(
@@ -135,11 +177,539 @@ fn main() {
                         .value_parser(clap::value_parser!(std::path::PathBuf)),
                 )
                 .arg(
-                    clap::arg!(--"lef" <PATH>)
+                    clap::arg!(--"lef" <PATH> "LEF files, the technology LEF first")
+                        .num_args(1..)
+                        .action(ArgAction::Append)
+                        .required(true)
+                        .value_parser(clap::value_parser!(std::path::PathBuf)),
+                )
+                .arg(clap::arg!(--"merge-shapes" "Merge overlapping shapes per layer"))
+                .arg(
+                    clap::arg!(--"merge-per-net" "Merge overlapping shapes per layer and net")
+                        .conflicts_with("merge-shapes"),
+                )
+                .arg(clap::arg!(--"compact-arrays" "Write regular grids of instances as AREFs"))
+                .arg(
+                    clap::arg!(--"layer-map" <PATH> "LEF/DEF layer names to GDS layers, KLayout .xml or text")
+                        .value_parser(clap::value_parser!(std::path::PathBuf))
+                        .required(false),
+                )
+                .arg(
+                    clap::arg!(--"outline-layer" <LAYER> "Draw the DIEAREA on this layer, OUTLINE of the layer map or 235/0 by default")
+                        .value_parser(clap::value_parser!(String))
+                        .required(false),
+                )
+                .arg(
+                    clap::arg!(--"rows-layer" <LAYER> "Draw rows and sites with orientation marks on this layer")
+                        .value_parser(clap::value_parser!(String))
+                        .required(false),
+                )
+                .arg(
+                    clap::arg!(--"tracks-datatype" <INT> "Draw tracks on their routing layer with this datatype")
+                        .value_parser(clap::value_parser!(i16))
+                        .required(false),
+                )
+                .arg(
+                    clap::arg!(--"gcell-layer" <LAYER> "Draw the gcell grid on this layer")
+                        .value_parser(clap::value_parser!(String))
+                        .required(false),
+                )
+                .arg(
+                    clap::arg!(--"cells-gds" <PATH> "GDS libraries with the full layouts of the LEF macros")
+                        .num_args(1..)
+                        .action(ArgAction::Append)
+                        .value_parser(clap::value_parser!(std::path::PathBuf))
+                        .required(false),
+                )
+                .arg(
+                    clap::arg!(--"dbu" <INT> "Database units per micron of the GDS, the DEF UNITS by default")
+                        .value_parser(clap::value_parser!(u32).range(1..))
+                        .required(false),
+                )
+                .arg(
+                    clap::arg!(--"user-unit" <MICRONS> "GDS user unit in microns, 1 by default")
+                        .value_parser(clap::value_parser!(f64))
+                        .required(false),
+                )
+                .arg(clap::arg!(--"emit-unused-macros" "Also write the LEF macros no component places"))
+                .arg(
+                    clap::arg!(--"prefix-macros" <PREFIX> "Prefix the names of the LEF macro cells")
+                        .value_parser(clap::value_parser!(String))
+                        .required(false),
+                )
+                .arg(
+                    clap::arg!(--"exclude-cells" <REGEX> "Do not write the cells matching, keeping their references")
+                        .num_args(1..)
+                        .action(ArgAction::Append)
+                        .value_parser(clap::value_parser!(String))
+                        .required(false),
+                ),
+        )
+        .subcommand(
+            clap::command!("gds2txt")
+                .arg(
+                    clap::arg!(<VALUE>)
+                        .id("input")
+                        .value_parser(clap::value_parser!(std::path::PathBuf)),
+                )
+                .arg(
+                    clap::arg!(--"output" <PATH>)
+                        .value_parser(clap::value_parser!(std::path::PathBuf))
+                        .required(false),
+                ),
+        )
+        .subcommand(
+            clap::command!("diff")
+                .arg(
+                    clap::arg!(<A>)
+                        .id("a")
+                        .value_parser(clap::value_parser!(std::path::PathBuf)),
+                )
+                .arg(
+                    clap::arg!(<B>)
+                        .id("b")
+                        .value_parser(clap::value_parser!(std::path::PathBuf)),
+                )
+                .arg(
+                    clap::arg!(--"tolerance" <INT>)
+                        .value_parser(clap::value_parser!(i32))
+                        .default_value("0"),
+                )
+                .arg(clap::arg!(--"json" "Print the report as JSON")),
+        )
+        .subcommand(
+            clap::command!("bool")
+                .arg(
+                    clap::arg!(<EXPRESSIONS>)
+                        .id("expressions")
+                        .num_args(1..)
+                        .value_parser(clap::value_parser!(String)),
+                )
+                .arg(
+                    clap::arg!(--"top" <STRING>)
+                        .value_parser(clap::value_parser!(String))
+                        .required(true),
+                )
+                .arg(
+                    clap::arg!(--input <PATH>)
+                        .value_parser(clap::value_parser!(std::path::PathBuf)),
+                )
+                .arg(
+                    clap::arg!(--"output" <PATH>)
+                        .value_parser(clap::value_parser!(std::path::PathBuf)),
+                )
+                .arg(clap::arg!(--"per-cell" "Evaluate inside each cell when that gives the same result")),
+        )
+        .subcommand(
+            clap::command!("compact")
+                .arg(
+                    clap::arg!(--input <PATH>)
+                        .value_parser(clap::value_parser!(std::path::PathBuf)),
+                )
+                .arg(
+                    clap::arg!(--"output" <PATH>)
+                        .value_parser(clap::value_parser!(std::path::PathBuf)),
+                )
+                .arg(
+                    clap::arg!(--"min-count" <INT>)
+                        .value_parser(clap::value_parser!(usize))
+                        .default_value("2"),
+                )
+                .arg(clap::arg!(--"ignore-properties" "Combine instances whose properties differ"))
+                .arg(
+                    clap::arg!(-P --"patterns" <STRING>)
+                        .num_args(0..)
+                        .action(ArgAction::Append)
+                        .value_parser(clap::value_parser!(String))
+                        .required(false),
+                ),
+        )
+        .subcommand(
+            clap::command!("dedup")
+                .arg(
+                    clap::arg!(--input <PATH>)
+                        .value_parser(clap::value_parser!(std::path::PathBuf)),
+                )
+                .arg(
+                    clap::arg!(--"output" <PATH>)
+                        .value_parser(clap::value_parser!(std::path::PathBuf))
+                        .required(false),
+                )
+                .arg(clap::arg!(--"report" "Only list the groups of duplicate cells")),
+        )
+        .subcommand(
+            clap::command!("expand-arrays")
+                .arg(
+                    clap::arg!(--input <PATH>)
+                        .value_parser(clap::value_parser!(std::path::PathBuf)),
+                )
+                .arg(
+                    clap::arg!(--"output" <PATH>)
+                        .value_parser(clap::value_parser!(std::path::PathBuf)),
+                )
+                .arg(
+                    clap::arg!(-P --"patterns" <STRING>)
+                        .num_args(0..)
+                        .action(ArgAction::Append)
+                        .value_parser(clap::value_parser!(String))
+                        .required(false),
+                ),
+        )
+        .subcommand(
+            clap::command!("flatten")
+                .arg(
+                    clap::arg!(<VALUE>)
+                        .id("top")
+                        .value_parser(clap::value_parser!(String)),
+                )
+                .arg(
+                    clap::arg!(--input <PATH>)
+                        .value_parser(clap::value_parser!(std::path::PathBuf)),
+                )
+                .arg(
+                    clap::arg!(--"output" <PATH>)
+                        .value_parser(clap::value_parser!(std::path::PathBuf)),
+                )
+                .arg(
+                    clap::arg!(--"levels" <INT>)
+                        .value_parser(clap::value_parser!(i32))
+                        .required(false),
+                )
+                .arg(
+                    clap::arg!(-P --"patterns" <STRING>)
+                        .num_args(0..)
+                        .action(ArgAction::Append)
+                        .value_parser(clap::value_parser!(String))
+                        .required(false),
+                ),
+        )
+        .subcommand(
+            clap::command!("info")
+                .arg(
+                    clap::arg!(<VALUE>)
+                        .id("input")
+                        .value_parser(clap::value_parser!(std::path::PathBuf)),
+                )
+                .arg(clap::arg!(--"merged" "Merge overlapping polygons before summing areas"))
+                .arg(clap::arg!(--"json" "Print the report as JSON")),
+        )
+        .subcommand(
+            clap::command!("layers")
+                .subcommand(
+                    clap::command!("list").arg(
+                        clap::arg!(<VALUE>)
+                            .id("input")
+                            .value_parser(clap::value_parser!(std::path::PathBuf)),
+                    ),
+                )
+                .subcommand(
+                    clap::command!("remap")
+                        .arg(
+                            clap::arg!(--"table" <PATH>)
+                                .value_parser(clap::value_parser!(std::path::PathBuf))
+                                .required_unless_present_all(["from-map", "to-map"]),
+                        )
+                        .arg(
+                            clap::arg!(--"from-map" <PATH>)
+                                .value_parser(clap::value_parser!(std::path::PathBuf))
+                                .requires("to-map")
+                                .required(false),
+                        )
+                        .arg(
+                            clap::arg!(--"to-map" <PATH>)
+                                .value_parser(clap::value_parser!(std::path::PathBuf))
+                                .requires("from-map")
+                                .required(false),
+                        )
+                        .arg(
+                            clap::arg!(--input <PATH>)
+                                .value_parser(clap::value_parser!(std::path::PathBuf)),
+                        )
+                        .arg(
+                            clap::arg!(--"output" <PATH>)
+                                .value_parser(clap::value_parser!(std::path::PathBuf)),
+                        )
+                        .arg(
+                            clap::arg!(--"top" <STRING>)
+                                .value_parser(clap::value_parser!(String))
+                                .required(false),
+                        )
+                        .arg(
+                            clap::arg!(-P --"patterns" <STRING>)
+                                .num_args(0..)
+                                .action(ArgAction::Append)
+                                .value_parser(clap::value_parser!(String))
+                                .required(false),
+                        ),
+                )
+                .subcommand(
+                    clap::command!("copy")
+                        .arg(
+                            clap::arg!(--"from" <LAYER>)
+                                .value_parser(clap::value_parser!(String)),
+                        )
+                        .arg(
+                            clap::arg!(--"to" <LAYER>)
+                                .value_parser(clap::value_parser!(String)),
+                        )
+                        .arg(
+                            clap::arg!(--input <PATH>)
+                                .value_parser(clap::value_parser!(std::path::PathBuf)),
+                        )
+                        .arg(
+                            clap::arg!(--"output" <PATH>)
+                                .value_parser(clap::value_parser!(std::path::PathBuf)),
+                        )
+                        .arg(
+                            clap::arg!(--"top" <STRING>)
+                                .value_parser(clap::value_parser!(String))
+                                .required(false),
+                        )
+                        .arg(
+                            clap::arg!(-P --"patterns" <STRING>)
+                                .num_args(0..)
+                                .action(ArgAction::Append)
+                                .value_parser(clap::value_parser!(String))
+                                .required(false),
+                        ),
+                )
+                .subcommand(
+                    clap::command!("delete")
+                        .arg(
+                            clap::arg!(<LAYERS>)
+                                .id("layers")
+                                .num_args(1..)
+                                .value_parser(clap::value_parser!(String)),
+                        )
+                        .arg(
+                            clap::arg!(--input <PATH>)
+                                .value_parser(clap::value_parser!(std::path::PathBuf)),
+                        )
+                        .arg(
+                            clap::arg!(--"output" <PATH>)
+                                .value_parser(clap::value_parser!(std::path::PathBuf)),
+                        )
+                        .arg(
+                            clap::arg!(--"top" <STRING>)
+                                .value_parser(clap::value_parser!(String))
+                                .required(false),
+                        )
+                        .arg(
+                            clap::arg!(-P --"patterns" <STRING>)
+                                .num_args(0..)
+                                .action(ArgAction::Append)
+                                .value_parser(clap::value_parser!(String))
+                                .required(false),
+                        ),
+                ),
+        )
+        .subcommand(
+            clap::command!("merge")
+                .arg(
+                    clap::arg!(<INPUTS>)
+                        .id("inputs")
+                        .num_args(1..)
+                        .value_parser(clap::value_parser!(std::path::PathBuf)),
+                )
+                .arg(
+                    clap::arg!(-o --"output" <PATH>)
+                        .value_parser(clap::value_parser!(std::path::PathBuf))
+                        .required(true),
+                )
+                .arg(
+                    clap::arg!(--"policy" <POLICY>)
+                        .value_parser(["keep-first", "keep-last", "prefix", "suffix", "identical"])
+                        .default_value("identical"),
+                )
+                .arg(
+                    clap::arg!(--"affix" <STRING>)
+                        .value_parser(clap::value_parser!(String))
+                        .required(false),
+                ),
+        )
+        .subcommand(
+            clap::command!("merge-shapes")
+                .arg(
+                    clap::arg!(--input <PATH>)
+                        .value_parser(clap::value_parser!(std::path::PathBuf)),
+                )
+                .arg(
+                    clap::arg!(--"output" <PATH>)
+                        .value_parser(clap::value_parser!(std::path::PathBuf)),
+                )
+                .arg(
+                    clap::arg!(--"layers" <LAYER>)
+                        .num_args(1..)
+                        .value_parser(clap::value_parser!(String))
+                        .required(false),
+                )
+                .arg(clap::arg!(--"per-net" "Only merge shapes of the same net"))
+                .arg(
+                    clap::arg!(-P --"patterns" <STRING>)
+                        .num_args(0..)
+                        .action(ArgAction::Append)
+                        .value_parser(clap::value_parser!(String))
+                        .required(false),
+                ),
+        )
+        .subcommand(
+            clap::command!("prune")
+                .arg(
+                    clap::arg!(--"top" <STRING>)
+                        .action(ArgAction::Append)
+                        .value_parser(clap::value_parser!(String))
+                        .required(true),
+                )
+                .arg(
+                    clap::arg!(--input <PATH>)
+                        .value_parser(clap::value_parser!(std::path::PathBuf)),
+                )
+                .arg(
+                    clap::arg!(--"output" <PATH>)
+                        .value_parser(clap::value_parser!(std::path::PathBuf)),
+                ),
+        )
+        .subcommand(
+            clap::command!("rename")
+                .arg(
+                    clap::arg!(--input <PATH>)
+                        .value_parser(clap::value_parser!(std::path::PathBuf)),
+                )
+                .arg(
+                    clap::arg!(--"output" <PATH>)
+                        .value_parser(clap::value_parser!(std::path::PathBuf))
+                        .required(false),
+                )
+                .arg(
+                    clap::arg!(-r --"rule" <REGEX_AND_REPLACEMENT>)
+                        .num_args(2)
+                        .action(ArgAction::Append)
+                        .value_parser(clap::value_parser!(String))
+                        .required(false),
+                )
+                .arg(
+                    clap::arg!(--"mapping" <PATH>)
+                        .value_parser(clap::value_parser!(std::path::PathBuf))
+                        .required(false),
+                )
+                .arg(
+                    clap::arg!(--"max-length" <INT>)
+                        .value_parser(clap::value_parser!(usize))
+                        .default_value("32"),
+                )
+                .arg(clap::arg!(--"dry-run" "Only print the renames")),
+        )
+        .subcommand(
+            clap::command!("size")
+                .arg(
+                    clap::arg!(<VALUE>)
+                        .id("top")
+                        .value_parser(clap::value_parser!(String)),
+                )
+                .arg(
+                    clap::arg!(--input <PATH>)
+                        .value_parser(clap::value_parser!(std::path::PathBuf)),
+                )
+                .arg(
+                    clap::arg!(--"output" <PATH>)
+                        .value_parser(clap::value_parser!(std::path::PathBuf)),
+                )
+                .arg(
+                    clap::arg!(--"layer" <LAYER>)
+                        .value_parser(clap::value_parser!(String)),
+                )
+                .arg(
+                    clap::arg!(--"to" <LAYER>)
+                        .value_parser(clap::value_parser!(String)),
+                )
+                .arg(
+                    clap::arg!(--"dx" <INT>)
+                        .allow_negative_numbers(true)
+                        .value_parser(clap::value_parser!(i64)),
+                )
+                .arg(
+                    clap::arg!(--"dy" <INT>)
+                        .allow_negative_numbers(true)
+                        .value_parser(clap::value_parser!(i64))
+                        .required(false),
+                )
+                .arg(
+                    clap::arg!(--"corners" <MODE>)
+                        .value_parser(["square", "octagon", "round"])
+                        .default_value("square"),
+                )
+                .arg(
+                    clap::arg!(--"min-width" <INT>)
+                        .value_parser(clap::value_parser!(i64))
+                        .required(false),
+                ),
+        )
+        .subcommand(
+            clap::command!("tree")
+                .arg(
+                    clap::arg!(<VALUE>)
+                        .id("top")
+                        .value_parser(clap::value_parser!(String)),
+                )
+                .arg(
+                    clap::arg!(--input <PATH>)
+                        .value_parser(clap::value_parser!(std::path::PathBuf)),
+                )
+                .arg(
+                    clap::arg!(--"levels" <INT>)
+                        .value_parser(clap::value_parser!(i32))
+                        .required(false),
+                )
+                .arg(
+                    clap::arg!(--"format" <FORMAT>)
+                        .value_parser(["text", "dot", "json"])
+                        .default_value("text"),
+                )
+                .arg(
+                    clap::arg!(-P --"patterns" <STRING>)
+                        .num_args(0..)
+                        .action(ArgAction::Append)
+                        .value_parser(clap::value_parser!(String))
+                        .required(false),
+                ),
+        )
+        .subcommand(
+            clap::command!("xor")
+                .arg(
+                    clap::arg!(<A>)
+                        .id("a")
+                        .value_parser(clap::value_parser!(std::path::PathBuf)),
+                )
+                .arg(
+                    clap::arg!(<B>)
+                        .id("b")
+                        .value_parser(clap::value_parser!(std::path::PathBuf)),
+                )
+                .arg(
+                    clap::arg!(--"top" <STRING>)
+                        .value_parser(clap::value_parser!(String))
+                        .required(true),
+                )
+                .arg(
+                    clap::arg!(--"output" <PATH>)
+                        .value_parser(clap::value_parser!(std::path::PathBuf))
+                        .default_value("xor.gds"),
+                ),
+        )
+        .subcommand(
+            clap::command!("txt2gds")
+                .arg(
+                    clap::arg!(<VALUE>)
+                        .id("input")
+                        .value_parser(clap::value_parser!(std::path::PathBuf)),
+                )
+                .arg(
+                    clap::arg!(--"output" <PATH>)
                         .value_parser(clap::value_parser!(std::path::PathBuf)),
                 ),
         );
     let matches = cmd.get_matches();
+    let max_points = matches.get_one::<usize>("max-points").copied().unwrap();
     match matches.subcommand() {
         Some(("print", matches)) => {
             let input: &String = matches.get_one::<String>("input").unwrap();
@@ -167,9 +737,15 @@ fn main() {
                 &re,
                 1,
             );
-            let result = lib.save(output.to_owned());
+            let result = save_library(&mut lib, output, max_points);
         }
         Some(("extract", matches)) => match matches.subcommand() {
+            Some(("cell", matches)) => {
+                let cell: &String = matches.get_one::<String>("cell").unwrap();
+                let input = matches.get_one::<std::path::PathBuf>("input").unwrap();
+                let output = matches.get_one::<std::path::PathBuf>("output").unwrap();
+                extract_cell(cell, input, output, max_points).unwrap();
+            }
             Some(("srefs", matches)) => {
                 let top: &String = matches.get_one::<String>("top").unwrap();
                 let input = matches.get_one::<std::path::PathBuf>("input").unwrap();
@@ -209,7 +785,7 @@ fn main() {
                     false,
                 )
                     .unwrap();
-                let _ = lib.save(output.to_owned());
+                let _ = save_library(&mut lib, output, max_points);
             }
             _ => unreachable!("clap should ensure we don't get here"),
         },
@@ -218,9 +794,251 @@ fn main() {
             let input = matches.get_one::<std::path::PathBuf>("input").unwrap();
             // let mut lib = GdsLibrary::load(input.to_owned()).unwrap();
             let output = matches.get_one::<std::path::PathBuf>("output").unwrap();
-            let lef = matches.get_one::<std::path::PathBuf>("lef").unwrap();
-            let _ = convert_def_to_gds(&top, &input, &output, &[&lef]);
-            // let result = lib.save(output.to_owned());
+            let lefs: Vec<&std::path::PathBuf> = matches
+                .get_many::<std::path::PathBuf>("lef")
+                .unwrap()
+                .collect();
+            let merge = if matches.get_flag("merge-per-net") {
+                Some(MergeMode::Net)
+            } else if matches.get_flag("merge-shapes") {
+                Some(MergeMode::Layer)
+            } else {
+                None
+            };
+            let layer_map = matches
+                .get_one::<std::path::PathBuf>("layer-map")
+                .map(|path| LayerMap::read(path).unwrap());
+            let options = GdsWriteOptions {
+                merge,
+                compact_arrays: matches.get_flag("compact-arrays"),
+                layer_map,
+                outline_layer: matches
+                    .get_one::<String>("outline-layer")
+                    .map(|l| parse_gds_layer(l).unwrap()),
+                grids: GridLayers {
+                    rows: matches
+                        .get_one::<String>("rows-layer")
+                        .map(|l| parse_gds_layer(l).unwrap()),
+                    tracks: matches.get_one::<i16>("tracks-datatype").copied(),
+                    gcells: matches
+                        .get_one::<String>("gcell-layer")
+                        .map(|l| parse_gds_layer(l).unwrap()),
+                },
+                cell_libraries: matches
+                    .get_many::<std::path::PathBuf>("cells-gds")
+                    .map(|paths| paths.cloned().collect())
+                    .unwrap_or_default(),
+                dbu: matches.get_one::<u32>("dbu").copied(),
+                user_unit: matches.get_one::<f64>("user-unit").copied(),
+                top_name: None,
+                emit_unused_macros: matches.get_flag("emit-unused-macros"),
+                macro_prefix: matches.get_one::<String>("prefix-macros").cloned(),
+                exclude_cells: matches
+                    .get_many::<String>("exclude-cells")
+                    .map(|patterns| RegexSet::new(patterns).unwrap()),
+                max_points: Some(max_points),
+            };
+            if let Err(e) = convert_def_to_gds(&top, &input, &output, &lefs, &options) {
+                eprintln!("def2gds: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(("gds2txt", matches)) => {
+            let input = matches.get_one::<std::path::PathBuf>("input").unwrap();
+            let output = matches.get_one::<std::path::PathBuf>("output");
+            convert_gds_to_txt(input, output).unwrap();
+        }
+        Some(("diff", matches)) => {
+            let a = matches.get_one::<std::path::PathBuf>("a").unwrap();
+            let b = matches.get_one::<std::path::PathBuf>("b").unwrap();
+            let tolerance = matches.get_one::<i32>("tolerance").unwrap();
+            let json = matches.get_flag("json");
+            let report = print_gds_diff(a, b, tolerance.to_owned(), json).unwrap();
+            if !report.is_empty() {
+                std::process::exit(1);
+            }
+        }
+        Some(("bool", matches)) => {
+            let expressions: Vec<BoolExpression> = matches
+                .get_many::<String>("expressions")
+                .unwrap()
+                .map(|e| e.parse().unwrap())
+                .collect();
+            let top: &String = matches.get_one::<String>("top").unwrap();
+            let input = matches.get_one::<std::path::PathBuf>("input").unwrap();
+            let output = matches.get_one::<std::path::PathBuf>("output").unwrap();
+            let per_cell = matches.get_flag("per-cell");
+            bool_gds_layers(top, input, output, &expressions, per_cell, max_points).unwrap();
+        }
+        Some(("compact", matches)) => {
+            let input = matches.get_one::<std::path::PathBuf>("input").unwrap();
+            let output = matches.get_one::<std::path::PathBuf>("output").unwrap();
+            let min_count = matches.get_one::<usize>("min-count").unwrap();
+            let ignore_properties = matches.get_flag("ignore-properties");
+            let patterns: Option<Vec<&str>> = matches
+                .get_many::<String>("patterns")
+                .map(|values_ref| values_ref.map(|s| s.as_str()).collect());
+            compact_gds_arrays(input, output, min_count.to_owned(), ignore_properties, patterns, max_points).unwrap();
+        }
+        Some(("dedup", matches)) => {
+            let input = matches.get_one::<std::path::PathBuf>("input").unwrap();
+            let output = matches.get_one::<std::path::PathBuf>("output");
+            let report = matches.get_flag("report");
+            dedup_gds_file(input, output, report, max_points).unwrap();
+        }
+        Some(("expand-arrays", matches)) => {
+            let input = matches.get_one::<std::path::PathBuf>("input").unwrap();
+            let output = matches.get_one::<std::path::PathBuf>("output").unwrap();
+            let patterns: Option<Vec<&str>> = matches
+                .get_many::<String>("patterns")
+                .map(|values_ref| values_ref.map(|s| s.as_str()).collect());
+            expand_gds_arrays(input, output, patterns, max_points).unwrap();
+        }
+        Some(("flatten", matches)) => {
+            let top: &String = matches.get_one::<String>("top").unwrap();
+            let input = matches.get_one::<std::path::PathBuf>("input").unwrap();
+            let mut lib = GdsLibrary::load(input.to_owned()).unwrap();
+            let output = matches.get_one::<std::path::PathBuf>("output").unwrap();
+            let levels = matches.get_one::<i32>("levels").copied();
+            let patterns: Option<Vec<&str>> = matches
+                .get_many::<String>("patterns")
+                .map(|values_ref| values_ref.map(|s| s.as_str()).collect());
+            let re = RegexSet::new(patterns.unwrap_or(vec![".*"]).into_iter()).unwrap();
+            flatten_cell(&mut lib, top, levels, &re).unwrap();
+            save_library(&mut lib, output, max_points).unwrap();
+        }
+        Some(("info", matches)) => {
+            let input = matches.get_one::<std::path::PathBuf>("input").unwrap();
+            let merged = matches.get_flag("merged");
+            let json = matches.get_flag("json");
+            print_library_info(input, merged, json).unwrap();
+        }
+        Some(("layers", matches)) => match matches.subcommand() {
+            Some(("list", matches)) => {
+                let input = matches.get_one::<std::path::PathBuf>("input").unwrap();
+                print_layers(input).unwrap();
+            }
+            Some((name, matches)) => {
+                let operation = match name {
+                    "remap" => match matches.get_one::<std::path::PathBuf>("table") {
+                        Some(table) => LayerOperation::Remap(read_layer_table(table).unwrap()),
+                        None => {
+                            let from_map = matches.get_one::<std::path::PathBuf>("from-map").unwrap();
+                            let to_map = matches.get_one::<std::path::PathBuf>("to-map").unwrap();
+                            LayerOperation::Remap(
+                                layer_table_between(
+                                    &LayerMap::read(from_map).unwrap(),
+                                    &LayerMap::read(to_map).unwrap(),
+                                )
+                                .unwrap(),
+                            )
+                        }
+                    },
+                    "copy" => LayerOperation::Copy(
+                        parse_gds_layer(matches.get_one::<String>("from").unwrap()).unwrap(),
+                        parse_gds_layer(matches.get_one::<String>("to").unwrap()).unwrap(),
+                    ),
+                    "delete" => LayerOperation::Delete(
+                        matches
+                            .get_many::<String>("layers")
+                            .unwrap()
+                            .map(|l| parse_gds_layer(l).unwrap())
+                            .collect(),
+                    ),
+                    _ => unreachable!("clap should ensure we don't get here"),
+                };
+                let input = matches.get_one::<std::path::PathBuf>("input").unwrap();
+                let output = matches.get_one::<std::path::PathBuf>("output").unwrap();
+                let top = matches.get_one::<String>("top").map(|s| s.as_str());
+                let patterns: Option<Vec<&str>> = matches
+                    .get_many::<String>("patterns")
+                    .map(|values_ref| values_ref.map(|s| s.as_str()).collect());
+                edit_gds_layers(input, output, &operation, top, patterns, max_points).unwrap();
+            }
+            _ => unreachable!("clap should ensure we don't get here"),
+        },
+        Some(("merge", matches)) => {
+            let inputs: Vec<&std::path::PathBuf> = matches
+                .get_many::<std::path::PathBuf>("inputs")
+                .unwrap()
+                .collect();
+            let output = matches.get_one::<std::path::PathBuf>("output").unwrap();
+            let policy: ConflictPolicy = matches.get_one::<String>("policy").unwrap().parse().unwrap();
+            let affix = matches.get_one::<String>("affix").map(|s| s.as_str());
+            merge_gds_files(&inputs, output, policy, affix, max_points).unwrap();
+        }
+        Some(("merge-shapes", matches)) => {
+            let input = matches.get_one::<std::path::PathBuf>("input").unwrap();
+            let output = matches.get_one::<std::path::PathBuf>("output").unwrap();
+            let layers: Option<Vec<(i16, i16)>> = matches
+                .get_many::<String>("layers")
+                .map(|values_ref| values_ref.map(|l| parse_gds_layer(l).unwrap()).collect());
+            let mode = if matches.get_flag("per-net") { MergeMode::Net } else { MergeMode::Layer };
+            let patterns: Option<Vec<&str>> = matches
+                .get_many::<String>("patterns")
+                .map(|values_ref| values_ref.map(|s| s.as_str()).collect());
+            merge_gds_shapes(input, output, layers.as_deref(), mode, patterns, max_points).unwrap();
+        }
+        Some(("prune", matches)) => {
+            let tops: Vec<&str> = matches
+                .get_many::<String>("top")
+                .unwrap()
+                .map(|s| s.as_str())
+                .collect();
+            let input = matches.get_one::<std::path::PathBuf>("input").unwrap();
+            let output = matches.get_one::<std::path::PathBuf>("output").unwrap();
+            prune_gds_file(input, output, &tops, max_points).unwrap();
+        }
+        Some(("rename", matches)) => {
+            let input = matches.get_one::<std::path::PathBuf>("input").unwrap();
+            let output = matches.get_one::<std::path::PathBuf>("output");
+            let rule_values: Vec<&str> = matches
+                .get_many::<String>("rule")
+                .map(|values_ref| values_ref.map(|s| s.as_str()).collect())
+                .unwrap_or_default();
+            let rules: Vec<(&str, &str)> = rule_values.chunks(2).map(|r| (r[0], r[1])).collect();
+            let mapping = matches.get_one::<std::path::PathBuf>("mapping");
+            let max_length = matches.get_one::<usize>("max-length").unwrap();
+            let dry_run = matches.get_flag("dry-run");
+            rename_gds_cells(input, output, &rules, mapping, max_length.to_owned(), dry_run, max_points).unwrap();
+        }
+        Some(("size", matches)) => {
+            let top: &String = matches.get_one::<String>("top").unwrap();
+            let input = matches.get_one::<std::path::PathBuf>("input").unwrap();
+            let output = matches.get_one::<std::path::PathBuf>("output").unwrap();
+            let layer = parse_gds_layer(matches.get_one::<String>("layer").unwrap()).unwrap();
+            let target = parse_gds_layer(matches.get_one::<String>("to").unwrap()).unwrap();
+            let dx = matches.get_one::<i64>("dx").unwrap().to_owned();
+            let dy = matches.get_one::<i64>("dy").copied().unwrap_or(dx);
+            let corners: CornerMode = matches.get_one::<String>("corners").unwrap().parse().unwrap();
+            let min_width = matches.get_one::<i64>("min-width").copied();
+            let options = SizingOptions { dx, dy, corners, min_width };
+            size_gds_layer(top, input, output, layer, target, &options, max_points).unwrap();
+        }
+        Some(("tree", matches)) => {
+            let top: &String = matches.get_one::<String>("top").unwrap();
+            let input = matches.get_one::<std::path::PathBuf>("input").unwrap();
+            let levels = matches.get_one::<i32>("levels").copied();
+            let format: TreeFormat = matches.get_one::<String>("format").unwrap().parse().unwrap();
+            let patterns: Option<Vec<&str>> = matches
+                .get_many::<String>("patterns")
+                .map(|values_ref| values_ref.map(|s| s.as_str()).collect());
+            print_cell_tree(top, input, levels, patterns, format).unwrap();
+        }
+        Some(("xor", matches)) => {
+            let a = matches.get_one::<std::path::PathBuf>("a").unwrap();
+            let b = matches.get_one::<std::path::PathBuf>("b").unwrap();
+            let top: &String = matches.get_one::<String>("top").unwrap();
+            let output = matches.get_one::<std::path::PathBuf>("output").unwrap();
+            let mismatches = xor_layouts(a, b, top, output, max_points).unwrap();
+            if mismatches.iter().any(|m| m.polygons > 0) {
+                std::process::exit(1);
+            }
+        }
+        Some(("txt2gds", matches)) => {
+            let input = matches.get_one::<std::path::PathBuf>("input").unwrap();
+            let output = matches.get_one::<std::path::PathBuf>("output").unwrap();
+            convert_txt_to_gds(input, output, max_points).unwrap();
         }
         _ => unreachable!("clap should ensure we don't get here"),
     };